@@ -27,8 +27,8 @@ FIRST_VALUE=$(echo "$UTXOS" | jq -r '.[0].value')
 echo "   Using: ${FIRST_UTXO} (${FIRST_VALUE} sats)"
 echo ""
 
-# Get App ID
-APP_ID=$(echo -n "${FIRST_UTXO}" | sha256sum | cut -d' ' -f1)
+# Get App ID (domain-separated, see DOMAIN_TAG in src/lib.rs)
+APP_ID=$(echo -n "charmpay-v1:${FIRST_UTXO}" | sha256sum | cut -d' ' -f1)
 
 # Get App VK
 APP_VK=$(cat .app_vk 2>/dev/null || echo "")
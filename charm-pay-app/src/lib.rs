@@ -1,402 +1,12469 @@
 use charms_sdk::data::{
-    charm_values, check, sum_token_amount, App, Data, Transaction, UtxoId, B32, NFT, TOKEN,
+    charm_values, sum_token_amount, App, Charms, Data, NativeOutput, Transaction, UtxoId, B32, NFT,
+    TOKEN,
 };
+use ripemd::Ripemd160;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use arith::{add_or_reject, mul_or_reject, sub_or_reject};
+
+/// Like `check!`, but returns the given `ValidationError` instead of `false`
+macro_rules! ensure {
+    ($condition:expr, $err:expr) => {
+        if !$condition {
+            return Err($err);
+        }
+    };
+}
 
 /// Minimal subscription state for CharmPay
 /// This represents a subscription with all required fields
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+/// The core fields serialize under short keys to keep the on-chain charm small; their long
+/// names are still accepted when decoding, so states written before the renames parse unchanged
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct MinimalSubscriptionState {
     /// Public key or address of the payer (subscription owner)
     /// Immutable: Set at creation, never changes
+    #[serde(rename = "p", alias = "payer_pubkey")]
     pub payer_pubkey: String,
-    
-    /// Public key or address of the merchant (payment recipient)
+
+    /// Compressed public key or bech32 address of the merchant (payment recipient)
     /// Immutable: Set at creation, never changes
+    #[serde(rename = "m", alias = "merchant_pubkey")]
     pub merchant_pubkey: String,
-    
+
     /// Payment amount per billing cycle (in satoshis)
     /// Immutable: Set at creation, defines subscription terms
+    #[serde(rename = "a", alias = "amount_sats")]
     pub amount_sats: u64,
-    
+
     /// Number of blocks between payments
     /// Immutable: Set at creation, defines subscription terms
+    #[serde(rename = "i", alias = "billing_interval_blocks")]
     pub billing_interval_blocks: u32,
-    
+
     /// Block height when last payment occurred
     /// Mutable: Updates with each payment
+    #[serde(rename = "l", alias = "last_payment_block")]
     pub last_payment_block: u32,
-    
+
     /// Whether subscription is currently active
     /// Mutable: Can be set to false on cancellation
+    #[serde(rename = "act", alias = "is_active")]
     pub is_active: bool,
-    
+
     /// Remaining locked balance (in satoshis)
     /// Mutable: Decreases with each payment
+    #[serde(rename = "bal", alias = "remaining_balance")]
     pub remaining_balance: u64,
-}
 
-/// Subscription state stored in NFT (backward compatible)
-/// This represents a subscription with locked funds
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct SubscriptionState {
-    /// Subscription identifier (e.g., "sub_001")
+    /// Whether billing is temporarily suspended
+    /// Mutable: Toggled by pause/resume, payments are rejected while paused
+    #[serde(default)]
+    pub paused: bool,
+
+    /// Number of blocks after the due block during which a payment is still accepted
+    /// Immutable: Set at creation, a later payment means the subscription has lapsed
+    #[serde(default)]
+    pub grace_period_blocks: u32,
+
+    /// Block height at which the free trial ends and billing starts
+    /// Immutable: Set at creation, no payment is recorded before this block
+    #[serde(default)]
+    pub trial_end_block: u32,
+
+    /// Public key of the marketplace receiving the platform fee
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub platform_pubkey: String,
+
+    /// Part of each cycle's `amount_sats` that goes to the platform instead of the merchant
+    /// Immutable: Set at creation, never exceeds `amount_sats`
+    #[serde(default)]
+    pub platform_fee_sats: u64,
+
+    /// Hash of a one-time coupon code granting a discount on a single cycle
+    /// Immutable: Set at creation, `None` if no coupon was issued
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub coupon_hash: Option<B32>,
+
+    /// Whether the coupon has been redeemed
+    /// Mutable: Set once by the discounted payment, never cleared
+    #[serde(default)]
+    pub coupon_used: bool,
+
+    /// Algorithm the NFT identity is derived with from the funding UTXO id
+    /// Immutable: Set at creation, checked when the NFT is minted
+    #[serde(default)]
+    pub hash_algo: HashAlgo,
+
+    /// Number of billing cycles after which the subscription ends (0 = unlimited)
+    /// Immutable: Set at creation, the final payment deactivates the subscription
+    #[serde(default)]
+    pub max_cycles: u32,
+
+    /// Number of billing cycles paid so far
+    /// Mutable: Increases by one with each payment
+    #[serde(default)]
+    pub cycles_paid: u32,
+
+    /// Last block height at which a payment can be recorded (0 = never expires)
+    /// Immutable: Set at creation, afterwards the balance can only be released by cancellation
+    #[serde(default)]
+    pub expiry_block: u32,
+
+    /// Hash of the authorization that allows reassigning the subscription to a new payer
+    /// Immutable: Set at creation, `None` if ownership cannot be transferred
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub transfer_auth_hash: Option<B32>,
+
+    /// Smallest amount that can be charged in a cycle when billing within a band
+    /// Immutable: Set at creation, never above `max_amount_sats`
+    #[serde(default)]
+    pub min_amount_sats: u64,
+
+    /// Largest amount that can be charged in a cycle (0 = charge exactly `amount_sats`)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub max_amount_sats: u64,
+
+    /// Cumulative amount paid over the subscription's lifetime (in satoshis)
+    /// Mutable: Increases by the charged amount with each payment
+    #[serde(default)]
+    pub total_paid_sats: u64,
+
+    /// Surcharge added to a payment made after the due block, within the grace period
+    /// Immutable: Set at creation, paid to the merchant with the late cycle's amount
+    #[serde(default)]
+    pub late_fee_sats: u64,
+
+    /// Number of cycles the remaining balance is declared to prepay (0 = not declared)
+    /// Mutable: Must match the balance at creation, decreases by one with each payment
+    #[serde(default)]
+    pub prepaid_cycles: u32,
+
+    /// Identity of the token app payments are made in (None = the subscription's own token)
+    /// Immutable: Set at creation; amounts and the balance are counted in that token's units
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub denomination_token: Option<B32>,
+
+    /// Number of consecutive payment attempts recorded as failed since the last payment
+    /// Mutable: Increases by one per recorded failure, reset to zero by a successful payment
+    #[serde(default)]
+    pub failed_attempts: u8,
+
+    /// Number of failed attempts after which the subscription is deactivated (0 = no dunning)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub max_attempts: u8,
+
+    /// Which party authorizes each payment: the payer pushing it or the merchant pulling it
+    /// Immutable: Set at creation, decides whose preimage a payment witness must reveal
+    #[serde(default)]
+    pub authorization_mode: AuthorizationMode,
+
+    /// Hash of the payer's payment authorization (None = push payments are not checked)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub payer_auth_hash: Option<B32>,
+
+    /// Hash of the merchant's payment authorization (None = pull payments are not checked)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub merchant_auth_hash: Option<B32>,
+
+    /// Hash of the payer's consent to moving the payments to a new merchant
+    /// Immutable: Set at creation (None = the merchant cannot be reassigned), replaced by a fresh
+    /// hash with every reassignment, which reveals the consent
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub payer_reassign_auth_hash: Option<B32>,
+
+    /// Hash of the merchant's consent to moving the payments to a new merchant
+    /// Immutable: Set at creation (None = the merchant cannot be reassigned), replaced by a fresh
+    /// hash with every reassignment, which reveals the consent
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub merchant_reassign_auth_hash: Option<B32>,
+
+    /// Hash of the payer's authorization to change the plan
+    /// Immutable: Set at creation (None = the plan cannot change), replaced by a fresh hash with
+    /// every plan change, which reveals the authorization
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub plan_change_auth_hash: Option<B32>,
+
+    /// Ceiling on `amount_sats` and `remaining_balance` guarding against fat-finger locking
+    /// Immutable: Set at creation, `None` if amounts are not capped
+    #[serde(default)]
+    pub max_amount_cap: Option<u64>,
+
+    /// Compressed public key or bech32 address that funded the locked balance, when it is not
+    /// the payer (e.g. a gift); the payer still owns, cancels and transfers the subscription
+    /// Immutable: Set at creation, empty if the payer funded it
+    #[serde(default)]
+    pub funder_pubkey: String,
+
+    /// Number of seats billed per cycle, for per-seat pricing
+    /// Mutable: Adjusted by seat changes, `amount_sats` always equals `seats * price_per_seat_sats`
+    #[serde(default)]
+    pub seats: u32,
+
+    /// Price of one seat per billing cycle (0 = not billed per seat)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub price_per_seat_sats: u64,
+
+    /// Prorated credit from a seat or plan decrease, deducted from the next payment
+    /// Mutable: Increases on seat and plan decreases, used up by the following payments
+    #[serde(default)]
+    pub seat_credit_sats: u64,
+
+    /// Hash of the fulfillment proof a payment must reveal before the merchant is paid
+    /// Immutable: Set at creation, `None` if payments are released without a proof
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub escrow_release_hash: Option<B32>,
+
+    /// Number of blocks after a payment during which the payer can reverse it (0 = never)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub dispute_window_blocks: u32,
+
+    /// Tokens charged by the last payment, which a reversal refunds exactly (0 = none)
+    /// Mutable: Set by each payment, cleared by its reversal
+    #[serde(default)]
+    pub last_charge_sats: u64,
+
+    /// Whether a balance short of the cycle's charge can be paid out by a final partial payment,
+    /// which ends the subscription
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub allow_partial_final: bool,
+
+    /// Whether the merchant's share of each payment accrues in the subscription, to be withdrawn
+    /// later, instead of being paid out with the payment
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub accrue_payments: bool,
+
+    /// Merchant's share of the payments accrued so far, still locked with the subscription
+    /// Mutable: Grows with each payment of an accruing subscription, zeroed by a withdrawal
+    #[serde(default)]
+    pub merchant_withdrawable_sats: u64,
+
+    /// Price per cycle in US cents, charged in sats at the oracle's rate (0 = priced in sats)
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub usd_amount_cents: u64,
+
+    /// How far, in basis points, a USD-priced payment may deviate from the oracle's rate
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub slippage_bps: u16,
+
+    /// Hash of the link of the price oracle's hash chain it reveals with the next rate it attests
+    /// Immutable: Set at creation (`None` unless the subscription is priced in USD), replaced by
+    /// the revealed link with every USD-priced payment, so each link is accepted once
+    #[serde(default)]
+    #[cfg_attr(feature = "schemars", schemars(with = "Option<String>"))]
+    pub oracle_auth_hash: Option<B32>,
+
+    /// Whether the subscription was deactivated for missing the grace period of a payment
+    /// Mutable: Set by the transition recording the lapse, never cleared
+    #[serde(default)]
+    pub lapsed: bool,
+
+    /// One-time fee paid to the merchant at creation, on top of the locked balance
+    /// Immutable: Set at creation, never charged again
+    #[serde(default)]
+    pub setup_fee_sats: u64,
+
+    /// Number of cycles that must be paid before the payer can cancel without a fee
+    /// Immutable: Set at creation
+    #[serde(default)]
+    pub min_commitment_cycles: u32,
+
+    /// Fee paid to the merchant for cancelling before `min_commitment_cycles` are paid
+    /// Immutable: Set at creation, taken out of the refund
+    #[serde(default)]
+    pub early_termination_fee_sats: u64,
+
+    /// `CONTRACT_VERSION` of the contract the state was written for (0 = before versioning)
+    /// Immutable: Set at creation, states of another version are rejected
+    #[serde(default)]
+    pub contract_version: u32,
+
+    /// Merchant keys the subscription may ever pay (empty = any merchant)
+    /// Immutable: Set at creation, payments and reassignments must target a listed key
+    #[serde(default)]
+    pub allowed_merchants: Vec<String>,
+
+    /// Block of the last cancellation, pause, resume or payment reversal
+    /// Mutable: Set by pauses, resumes and reversals; all of them and cancellations must come
+    /// at least `COOLDOWN_BLOCKS` after it
+    #[serde(default)]
+    pub last_state_change_block: u32,
+
+    /// Unit `amount_sats` and its bounds are expressed in; balances and fees are in tokens
+    /// Immutable: Set at creation, decides how many tokens a cycle charges
+    #[serde(default)]
+    pub currency_unit: CurrencyUnit,
+
+    /// Human-facing id indexers correlate the subscription by, like `SubscriptionState`'s
+    /// Immutable: Set at creation, must not be empty
+    #[serde(default)]
     pub subscription_id: String,
-    /// Recipient address (who receives payments)
-    pub recipient: String,
-    /// Amount per billing cycle (in satoshis)
-    pub amount_per_cycle: u64,
-    /// Remaining locked balance (in satoshis)
-    pub remaining_balance: u64,
-    /// Total amount originally locked (in satoshis)
-    pub total_locked: u64,
+
+    /// Yearly yield earned by the remaining balance, in basis points (0 = none)
+    /// Immutable: Set at creation, each payment first credits the yield of the interval since
+    /// the last one, minted against native value the payment transaction locks for it
+    #[serde(default)]
+    pub annual_yield_bps: u16,
+
+    /// Number of transitions the subscription has gone through
+    /// Mutable: Increases by exactly one with every transition, so none can be replayed
+    #[serde(default)]
+    pub nonce: u64,
 }
 
-/// Legacy NFT content structure (for backward compatibility)
-/// Maps to SubscriptionState for subscription NFTs
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct NftContent {
-    pub ticker: String,
-    pub remaining: u64,
+/// Party that initiates the payments of a subscription
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum AuthorizationMode {
+    /// The payer pushes each payment to the merchant
+    #[default]
+    Push,
+    /// The merchant pulls each payment from the locked balance
+    Pull,
 }
 
-impl From<SubscriptionState> for NftContent {
-    fn from(state: SubscriptionState) -> Self {
-        NftContent {
-            ticker: format!("SUBSCRIPTION-{}", state.subscription_id),
-            remaining: state.remaining_balance,
-        }
-    }
+/// Hash algorithms an NFT identity can be derived with
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum HashAlgo {
+    #[default]
+    Sha256,
+    Blake3,
+    Keccak256,
 }
 
-pub fn app_contract(app: &App, tx: &Transaction, x: &Data, w: &Data) -> bool {
-    let empty = Data::empty();
-    assert_eq!(x, &empty);
-    match app.tag {
-        NFT => {
-            check!(nft_contract_satisfied(app, tx, w))
-        }
-        TOKEN => {
-            check!(token_contract_satisfied(app, tx))
+/// Unit the per-cycle amount of a subscription is expressed in
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
+pub enum CurrencyUnit {
+    /// Satoshis, one token each
+    #[default]
+    Satoshi,
+    /// Thousandths of a satoshi, a thousand to the token
+    MilliSatoshi,
+    /// Base units of the payment token, whatever its denomination
+    TokenBase,
+}
+
+impl CurrencyUnit {
+    /// Number of tokens `amount` of this unit is worth; millisatoshis must add up to whole tokens
+    pub fn to_tokens(self, amount: u64) -> Result<u64, ValidationError> {
+        match self {
+            CurrencyUnit::Satoshi | CurrencyUnit::TokenBase => Ok(amount),
+            CurrencyUnit::MilliSatoshi => {
+                ensure!(
+                    amount.is_multiple_of(1000),
+                    ValidationError::FractionalAmount { amount }
+                );
+                Ok(amount / 1000)
+            }
         }
-        _ => unreachable!(),
     }
-    true
 }
 
-// NFT contract validation
-fn nft_contract_satisfied(app: &App, tx: &Transaction, w: &Data) -> bool {
-    let token_app = &App {
-        tag: TOKEN,
-        identity: app.identity.clone(),
-        vk: app.vk.clone(),
-    };
-    check!(can_mint_nft(app, tx, w) || can_mint_token(&token_app, tx));
-    true
-}
+impl MinimalSubscriptionState {
+    /// Start building a new subscription state
+    pub fn builder() -> MinimalSubscriptionStateBuilder {
+        MinimalSubscriptionStateBuilder::default()
+    }
 
-fn can_mint_nft(nft_app: &App, tx: &Transaction, w: &Data) -> bool {
-    let w_str: Option<String> = w.value().ok();
+    /// First block at which the next payment can be recorded
+    /// The first charge after a trial is due when the trial ends; saturates instead of overflowing
+    pub fn next_due_block(&self) -> u32 {
+        self.last_payment_block
+            .saturating_add(self.billing_interval_blocks)
+            .max(self.trial_end_block)
+    }
 
-    check!(w_str.is_some());
-    let w_str = w_str.unwrap();
+    /// Whether the next payment can be recorded at `current_block`
+    pub fn is_due(&self, current_block: u32) -> bool {
+        current_block >= self.next_due_block()
+    }
 
-    // can only mint an NFT with this contract if the hash of `w` is the identity of the NFT.
-    check!(hash(&w_str) == nft_app.identity);
+    /// Whether the grace period of the next payment is over at `current_block`, so it can no
+    /// longer be recorded; saturates instead of overflowing
+    pub fn is_lapsed(&self, current_block: u32) -> bool {
+        current_block
+            > self
+                .next_due_block()
+                .saturating_add(self.grace_period_blocks)
+    }
 
-    // can only mint an NFT with this contract if spending a UTXO with the same ID as passed in `w`.
-    let w_utxo_id = UtxoId::from_str(&w_str).unwrap();
-    check!(tx.ins.iter().any(|(utxo_id, _)| utxo_id == &w_utxo_id));
+    /// Number of full billing cycles the remaining balance can still pay for
+    /// Limited by `max_cycles` when set; 0 if `amount_sats` is 0
+    pub fn remaining_cycles(&self) -> u64 {
+        let amount = self.currency_unit.to_tokens(self.amount_sats).unwrap_or(0);
+        let funded = self.remaining_balance.checked_div(amount).unwrap_or(0);
+        match self.max_cycles {
+            0 => funded,
+            max_cycles => funded.min(max_cycles.saturating_sub(self.cycles_paid) as u64),
+        }
+    }
 
-    let nft_charms = charm_values(nft_app, tx.outs.iter()).collect::<Vec<_>>();
+    /// Next state after paying one cycle at `current_block`: `amount_sats`, plus the late fee
+    /// when the payment is overdue
+    /// Mirrors the checks of the payment contract, so the result is a valid outgoing state
+    /// for a fixed-amount payment (charges within a band or with a coupon are built by hand)
+    pub fn apply_payment(
+        &self,
+        current_block: u32,
+    ) -> Result<MinimalSubscriptionState, ValidationError> {
+        ensure!(self.is_active, ValidationError::SubscriptionInactive);
+        ensure!(!self.paused, ValidationError::SubscriptionPaused);
+        ensure!(
+            self.max_cycles == 0 || self.cycles_paid < self.max_cycles,
+            ValidationError::MaxCyclesReached
+        );
+        ensure!(
+            current_block >= self.trial_end_block,
+            ValidationError::TrialNotEnded
+        );
+        let next_due_block = self
+            .last_payment_block
+            .checked_add(self.billing_interval_blocks)
+            .ok_or(ValidationError::Overflow)?
+            .max(self.trial_end_block);
+        ensure!(
+            current_block >= next_due_block,
+            ValidationError::IntervalNotElapsed
+        );
+        let grace_end_block = next_due_block
+            .checked_add(self.grace_period_blocks)
+            .ok_or(ValidationError::Overflow)?;
+        ensure!(
+            current_block <= grace_end_block,
+            ValidationError::SubscriptionLapsed
+        );
+        ensure!(
+            self.expiry_block == 0 || current_block <= self.expiry_block,
+            ValidationError::SubscriptionExpired
+        );
 
-    // can mint exactly one NFT.
-    check!(nft_charms.len() == 1);
-    // the NFT has the correct structure.
-    // Try to parse as MinimalSubscriptionState first, fall back to NftContent
-    let charm_data = &nft_charms[0];
-    if charm_data.value::<MinimalSubscriptionState>().is_ok() {
-        // New format with full state
-        return true;
+        // A payment after the due block carries the late fee, seat credit is deducted first
+        let late_fee_sats = if current_block > next_due_block {
+            self.late_fee_sats
+        } else {
+            0
+        };
+        let amount = self.currency_unit.to_tokens(self.amount_sats)?;
+        let credit = self.seat_credit_sats.min(amount);
+        let charge = add_or_reject(amount - credit, late_fee_sats)?;
+        // The balance earns its yield before it is charged
+        let credited = add_or_reject(self.remaining_balance, self.accrued_yield(current_block)?)?;
+        // If allowed, a balance short of the charge is paid out whole and ends the subscription
+        let partial_final = self.allow_partial_final && credited < charge;
+        let charge = if partial_final { credited } else { charge };
+        let remaining_balance =
+            sub_or_reject(credited, charge).map_err(|_| ValidationError::InsufficientBalance {
+                required: charge,
+                available: credited,
+            })?;
+        let total_paid_sats = add_or_reject(self.total_paid_sats, charge)?;
+        let merchant_withdrawable_sats = add_or_reject(
+            self.merchant_withdrawable_sats,
+            accrued_payment(self, charge)?,
+        )?;
+        let cycles_paid = self
+            .cycles_paid
+            .checked_add(1)
+            .ok_or(ValidationError::Overflow)?;
+        Ok(MinimalSubscriptionState {
+            last_payment_block: current_block,
+            remaining_balance,
+            total_paid_sats,
+            cycles_paid,
+            prepaid_cycles: self.prepaid_cycles.saturating_sub(1),
+            failed_attempts: 0,
+            seat_credit_sats: self.seat_credit_sats - credit,
+            merchant_withdrawable_sats,
+            last_charge_sats: charge,
+            nonce: self.next_nonce()?,
+            // The final allowed cycle, or a final partial payment, ends the subscription
+            is_active: !partial_final && (self.max_cycles == 0 || cycles_paid < self.max_cycles),
+            ..self.clone()
+        })
     }
-    // Legacy format
-    check!(charm_data.value::<NftContent>().is_ok());
-    true
-}
 
-pub(crate) fn hash(data: &str) -> B32 {
-    let hash = Sha256::digest(data);
-    B32(hash.into())
-}
+    /// Check the invariants the builder enforces at creation: valid payer and merchant keys,
+    /// an amount at or above the dust limit, a non-zero interval, ordered amount bounds, a fee
+    /// within the amount, a balance covering at least one cycle, amounts within the optional
+    /// cap and a prepaid counter matching that balance
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        self.validate_with_dust_limit(DUST_LIMIT_SATS)
+    }
 
-// Subscription payment contract logic
-fn token_contract_satisfied(token_app: &App, tx: &Transaction) -> bool {
-    check!(can_mint_token(token_app, tx) || can_execute_subscription_payment(token_app, tx));
-    true
-}
+    /// Like `validate`, with the dust threshold of the payment outputs' type
+    pub fn validate_with_dust_limit(&self, dust_limit_sats: u64) -> Result<(), ValidationError> {
+        validate_pubkey(&self.payer_pubkey)?;
+        Recipient::parse(&self.merchant_pubkey)?;
+        ensure!(
+            !self.subscription_id.is_empty(),
+            ValidationError::EmptySubscriptionId
+        );
+        self.validate_funder()?;
+        ensure!(self.amount_sats > 0, ValidationError::ZeroAmount);
+        self.validate_dust_limit(dust_limit_sats)?;
+        ensure!(
+            self.billing_interval_blocks > 0,
+            ValidationError::ZeroInterval
+        );
+        ensure!(
+            self.min_amount_sats <= self.max_amount_sats,
+            ValidationError::InvalidAmountBounds {
+                min: self.min_amount_sats,
+                max: self.max_amount_sats
+            }
+        );
+        let cycle_tokens = self.currency_unit.to_tokens(self.amount_sats)?;
+        ensure!(
+            self.platform_fee_sats <= cycle_tokens,
+            ValidationError::FeeExceedsAmount {
+                fee: self.platform_fee_sats,
+                amount: cycle_tokens
+            }
+        );
+        ensure!(
+            self.remaining_balance >= cycle_tokens,
+            ValidationError::InsufficientBalance {
+                required: cycle_tokens,
+                available: self.remaining_balance
+            }
+        );
+        self.validate_slippage()?;
+        self.validate_merchant_allowed()?;
+        self.validate_amount_cap()?;
+        self.validate_seats()?;
+        self.validate_prepaid_cycles()?;
+        Ok(())
+    }
 
-fn can_mint_token(token_app: &App, tx: &Transaction) -> bool {
-    let nft_app = App {
-        tag: NFT,
-        identity: token_app.identity.clone(),
-        vk: token_app.vk.clone(),
-    };
+    /// Check that the merchant is among the `allowed_merchants`, if any are listed
+    pub fn validate_merchant_allowed(&self) -> Result<(), ValidationError> {
+        ensure!(
+            self.is_merchant_allowed(&self.merchant_pubkey),
+            ValidationError::MerchantNotAllowed
+        );
+        Ok(())
+    }
 
-    // Check if there's an NFT in inputs
-    let incoming_nft: Option<NftContent> =
-        charm_values(&nft_app, tx.ins.iter().map(|(_, v)| v)).find_map(|data| data.value().ok());
-    
-    // Check if there's an NFT in outputs
-    let Some(outgoing_nft): Option<NftContent> =
-        charm_values(&nft_app, tx.outs.iter()).find_map(|data| data.value().ok())
-    else {
-        eprintln!("could not determine outgoing remaining supply");
-        return false;
-    };
-    let outgoing_supply = outgoing_nft.remaining;
+    /// Check that the slippage of a USD-priced payment is at most the whole price
+    pub fn validate_slippage(&self) -> Result<(), ValidationError> {
+        ensure!(
+            self.slippage_bps as u32 <= BASIS_POINTS,
+            ValidationError::InvalidSlippage
+        );
+        Ok(())
+    }
 
-    let Some(input_token_amount) = sum_token_amount(&token_app, tx.ins.iter().map(|(_, v)| v)).ok()
-    else {
-        eprintln!("could not determine input total token amount");
-        return false;
-    };
-    let Some(output_token_amount) = sum_token_amount(&token_app, tx.outs.iter()).ok() else {
-        eprintln!("could not determine output total token amount");
-        return false;
-    };
+    /// Check that a subscription billed per seat has seats and charges exactly for them
+    pub fn validate_seats(&self) -> Result<(), ValidationError> {
+        if self.price_per_seat_sats == 0 {
+            return Ok(());
+        }
+        ensure!(self.seats > 0, ValidationError::ZeroSeats);
+        let expected = mul_or_reject(self.price_per_seat_sats, self.seats as u64)?;
+        ensure!(
+            self.amount_sats == expected,
+            ValidationError::SeatAmountMismatch {
+                expected,
+                actual: self.amount_sats
+            }
+        );
+        Ok(())
+    }
 
-    // Case 1: NFT in inputs (normal token minting controlled by NFT)
-    if let Some(incoming_nft) = incoming_nft {
-        let incoming_supply = incoming_nft.remaining;
-        if !(incoming_supply >= outgoing_supply) {
-            eprintln!("incoming remaining supply must be >= outgoing remaining supply");
-            return false;
+    /// Check that neither the per-cycle amount nor the remaining balance exceeds
+    /// `max_amount_cap`, if one is set
+    pub fn validate_amount_cap(&self) -> Result<(), ValidationError> {
+        let Some(cap) = self.max_amount_cap else {
+            return Ok(());
+        };
+        for amount in [self.amount_sats, self.remaining_balance] {
+            ensure!(
+                amount <= cap,
+                ValidationError::AmountAboveCap { amount, cap }
+            );
         }
-        // can mint no more than what's allowed by the managing NFT state change.
-        return output_token_amount - input_token_amount == incoming_supply - outgoing_supply;
+        Ok(())
     }
 
-    // Case 2: No NFT in inputs (initial creation - minting NFT and tokens together)
-    // When creating a subscription, we mint both NFT and tokens at the same time
-    // Allow minting tokens equal to the NFT's remaining supply (total locked amount)
-    if input_token_amount == 0 && output_token_amount == outgoing_supply {
-        // Initial creation: minting tokens equal to NFT remaining supply
-        return true;
+    /// Check that a gift's funder is a compressed public key or a valid bech32 address
+    pub fn validate_funder(&self) -> Result<(), ValidationError> {
+        if !self.funder_pubkey.is_empty() {
+            Recipient::parse(&self.funder_pubkey)?;
+        }
+        Ok(())
     }
 
-    false
-}
+    /// Check that each cycle's payment output carries at least `dust_limit_sats`
+    pub fn validate_dust_limit(&self, dust_limit_sats: u64) -> Result<(), ValidationError> {
+        let amount = self.currency_unit.to_tokens(self.amount_sats)?;
+        ensure!(
+            amount >= dust_limit_sats,
+            ValidationError::BelowDustLimit {
+                amount,
+                dust_limit: dust_limit_sats
+            }
+        );
+        Ok(())
+    }
 
-// Subscription payment: validates payment execution with full state checks
-fn can_execute_subscription_payment(token_app: &App, tx: &Transaction) -> bool {
-    let nft_app = App {
-        tag: NFT,
-        identity: token_app.identity.clone(),
-        vk: token_app.vk.clone(),
-    };
+    /// Check that a declared number of prepaid cycles is exactly covered by the remaining balance
+    pub fn validate_prepaid_cycles(&self) -> Result<(), ValidationError> {
+        if self.prepaid_cycles == 0 {
+            return Ok(());
+        }
+        let cycle_tokens = self.currency_unit.to_tokens(self.amount_sats)?;
+        let expected = mul_or_reject(cycle_tokens, self.prepaid_cycles as u64)?;
+        ensure!(
+            self.remaining_balance == expected,
+            ValidationError::PrepaidBalanceMismatch {
+                expected,
+                actual: self.remaining_balance
+            }
+        );
+        Ok(())
+    }
 
-    // Try to parse as MinimalSubscriptionState (new format)
-    let incoming_state: Option<MinimalSubscriptionState> = charm_values(&nft_app, tx.ins.iter().map(|(_, v)| v))
-        .find_map(|data| data.value().ok());
-    
-    let outgoing_state: Option<MinimalSubscriptionState> = charm_values(&nft_app, tx.outs.iter())
-        .find_map(|data| data.value().ok());
+    /// Next state after changing to `seats` seats at `current_block`, before the current cycle
+    /// falls due: `amount_sats` follows the seat count, and the price difference for the rest of
+    /// the cycle is charged from the balance (more seats) or credited to the next payment (fewer)
+    pub fn change_seats(
+        &self,
+        seats: u32,
+        current_block: u32,
+    ) -> Result<MinimalSubscriptionState, ValidationError> {
+        ensure!(self.price_per_seat_sats > 0, ValidationError::NotSeatBased);
+        ensure!(seats > 0, ValidationError::ZeroSeats);
+        let amount_sats = mul_or_reject(self.price_per_seat_sats, seats as u64)?;
+        Ok(MinimalSubscriptionState {
+            seats,
+            ..self.reprice(amount_sats, current_block)?
+        })
+    }
 
-    // If we have full state, validate with all checks
-    if let (Some(in_state), Some(out_state)) = (incoming_state, outgoing_state) {
-        return validate_subscription_payment_full(&in_state, &out_state, token_app, tx);
+    /// Next state after switching to a plan costing `amount_sats` per cycle at `current_block`,
+    /// before the current cycle falls due: the price difference for the rest of the cycle is
+    /// charged from the balance (upgrade) or credited to the next payment (downgrade). Seat-based
+    /// subscriptions change their amount through [`Self::change_seats`] only
+    /// The revealed `plan_change_auth_hash` is left for the caller to replace with a fresh one
+    pub fn change_plan(
+        &self,
+        amount_sats: u64,
+        current_block: u32,
+    ) -> Result<MinimalSubscriptionState, ValidationError> {
+        ensure!(
+            self.price_per_seat_sats == 0,
+            ValidationError::ImmutableFieldChanged("amount_sats")
+        );
+        ensure!(amount_sats > 0, ValidationError::ZeroAmount);
+        self.reprice(amount_sats, current_block)
     }
 
-    // Fall back to legacy format (NftContent)
-    let Some(incoming_nft): Option<NftContent> =
-        charm_values(&nft_app, tx.ins.iter().map(|(_, v)| v)).find_map(|data| data.value().ok())
-    else {
-        return false; // No NFT in inputs, not a subscription payment
-    };
+    // Switch to `amount_sats` per cycle at `current_block`, prorating the difference
+    fn reprice(
+        &self,
+        amount_sats: u64,
+        current_block: u32,
+    ) -> Result<MinimalSubscriptionState, ValidationError> {
+        ensure!(self.is_active, ValidationError::SubscriptionInactive);
+        ensure!(!self.paused, ValidationError::SubscriptionPaused);
+        ensure!(!self.is_due(current_block), ValidationError::PaymentDue);
 
-    let Some(outgoing_nft): Option<NftContent> =
-        charm_values(&nft_app, tx.outs.iter()).find_map(|data| data.value().ok())
-    else {
-        return false; // No NFT in outputs, not a subscription payment
-    };
+        // Prorated in tokens over the blocks left until the next due block
+        let current_tokens = self.currency_unit.to_tokens(self.amount_sats)?;
+        let next_tokens = self.currency_unit.to_tokens(amount_sats)?;
+        let remaining_blocks = self
+            .next_due_block()
+            .saturating_sub(current_block)
+            .min(self.billing_interval_blocks);
+        let prorate = |difference: u64| {
+            (difference as u128 * remaining_blocks as u128 / self.billing_interval_blocks as u128)
+                as u64
+        };
+        let mut next = MinimalSubscriptionState {
+            amount_sats,
+            nonce: self.next_nonce()?,
+            ..self.clone()
+        };
+        if next_tokens > current_tokens {
+            let charge = prorate(next_tokens - current_tokens);
+            next.remaining_balance =
+                sub_or_reject(self.remaining_balance, charge).map_err(|_| {
+                    ValidationError::InsufficientBalance {
+                        required: charge,
+                        available: self.remaining_balance,
+                    }
+                })?;
+            next.total_paid_sats = add_or_reject(self.total_paid_sats, charge)?;
+        } else {
+            let credit = prorate(current_tokens - next_tokens);
+            next.seat_credit_sats = add_or_reject(self.seat_credit_sats, credit)?;
+        }
+        next.validate_dust_limit(DUST_LIMIT_SATS)?;
+        next.validate_amount_cap()?;
+        Ok(next)
+    }
 
-    // Legacy validation: NFT remaining must decrease
-    if !(incoming_nft.remaining >= outgoing_nft.remaining) {
-        eprintln!("NFT remaining must decrease or stay same for subscription payment");
-        return false;
+    /// Next state after recording a failed payment attempt: the counter grows by one and the
+    /// subscription is deactivated once it reaches `max_attempts`; no funds move
+    pub fn record_failed_attempt(&self) -> Result<MinimalSubscriptionState, ValidationError> {
+        ensure!(self.max_attempts > 0, ValidationError::DunningDisabled);
+        ensure!(self.is_active, ValidationError::SubscriptionInactive);
+        ensure!(!self.paused, ValidationError::SubscriptionPaused);
+        let failed_attempts = self
+            .failed_attempts
+            .checked_add(1)
+            .ok_or(ValidationError::Overflow)?;
+        Ok(MinimalSubscriptionState {
+            failed_attempts,
+            is_active: failed_attempts < self.max_attempts,
+            nonce: self.next_nonce()?,
+            ..self.clone()
+        })
     }
 
-    let payment_amount = incoming_nft.remaining - outgoing_nft.remaining;
+    /// Whether the subscription was deactivated by reaching its cap of failed attempts
+    pub fn is_dunning_exhausted(&self) -> bool {
+        self.max_attempts > 0 && self.failed_attempts >= self.max_attempts && !self.is_active
+    }
 
-    // Calculate token amounts
-    let Some(input_token_amount) = sum_token_amount(&token_app, tx.ins.iter().map(|(_, v)| v)).ok()
-    else {
-        eprintln!("could not determine input total token amount");
-        return false;
-    };
-    let Some(output_token_amount) = sum_token_amount(&token_app, tx.outs.iter()).ok() else {
-        eprintln!("could not determine output total token amount");
-        return false;
-    };
+    /// Unambiguous encoding of every field in declaration order, independent of any serde
+    /// format: integers big-endian, strings length-prefixed, options and enums tagged by a byte
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        // Destructured so a new field cannot be left out of the encoding
+        let MinimalSubscriptionState {
+            payer_pubkey,
+            merchant_pubkey,
+            amount_sats,
+            billing_interval_blocks,
+            last_payment_block,
+            is_active,
+            remaining_balance,
+            paused,
+            grace_period_blocks,
+            trial_end_block,
+            platform_pubkey,
+            platform_fee_sats,
+            coupon_hash,
+            coupon_used,
+            hash_algo,
+            max_cycles,
+            cycles_paid,
+            expiry_block,
+            transfer_auth_hash,
+            min_amount_sats,
+            max_amount_sats,
+            total_paid_sats,
+            late_fee_sats,
+            prepaid_cycles,
+            denomination_token,
+            failed_attempts,
+            max_attempts,
+            authorization_mode,
+            payer_auth_hash,
+            merchant_auth_hash,
+            payer_reassign_auth_hash,
+            merchant_reassign_auth_hash,
+            plan_change_auth_hash,
+            max_amount_cap,
+            funder_pubkey,
+            seats,
+            price_per_seat_sats,
+            seat_credit_sats,
+            escrow_release_hash,
+            dispute_window_blocks,
+            last_charge_sats,
+            allow_partial_final,
+            accrue_payments,
+            merchant_withdrawable_sats,
+            usd_amount_cents,
+            slippage_bps,
+            oracle_auth_hash,
+            lapsed,
+            setup_fee_sats,
+            min_commitment_cycles,
+            early_termination_fee_sats,
+            contract_version,
+            allowed_merchants,
+            last_state_change_block,
+            currency_unit,
+            subscription_id,
+            annual_yield_bps,
+            nonce,
+        } = self;
 
-    // For subscription payments: tokens are transferred (output == input)
-    if output_token_amount == input_token_amount {
-        return true;
+        let mut bytes = Vec::new();
+        let put_str = |bytes: &mut Vec<u8>, value: &str| {
+            bytes.extend_from_slice(&(value.len() as u64).to_be_bytes());
+            bytes.extend_from_slice(value.as_bytes());
+        };
+        let put_hash = |bytes: &mut Vec<u8>, value: &Option<B32>| match value {
+            Some(value) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&value.0);
+            }
+            None => bytes.push(0),
+        };
+
+        put_str(&mut bytes, payer_pubkey);
+        put_str(&mut bytes, merchant_pubkey);
+        bytes.extend_from_slice(&amount_sats.to_be_bytes());
+        bytes.extend_from_slice(&billing_interval_blocks.to_be_bytes());
+        bytes.extend_from_slice(&last_payment_block.to_be_bytes());
+        bytes.push(*is_active as u8);
+        bytes.extend_from_slice(&remaining_balance.to_be_bytes());
+        bytes.push(*paused as u8);
+        bytes.extend_from_slice(&grace_period_blocks.to_be_bytes());
+        bytes.extend_from_slice(&trial_end_block.to_be_bytes());
+        put_str(&mut bytes, platform_pubkey);
+        bytes.extend_from_slice(&platform_fee_sats.to_be_bytes());
+        put_hash(&mut bytes, coupon_hash);
+        bytes.push(*coupon_used as u8);
+        bytes.push(*hash_algo as u8);
+        bytes.extend_from_slice(&max_cycles.to_be_bytes());
+        bytes.extend_from_slice(&cycles_paid.to_be_bytes());
+        bytes.extend_from_slice(&expiry_block.to_be_bytes());
+        put_hash(&mut bytes, transfer_auth_hash);
+        bytes.extend_from_slice(&min_amount_sats.to_be_bytes());
+        bytes.extend_from_slice(&max_amount_sats.to_be_bytes());
+        bytes.extend_from_slice(&total_paid_sats.to_be_bytes());
+        bytes.extend_from_slice(&late_fee_sats.to_be_bytes());
+        bytes.extend_from_slice(&prepaid_cycles.to_be_bytes());
+        put_hash(&mut bytes, denomination_token);
+        bytes.push(*failed_attempts);
+        bytes.push(*max_attempts);
+        bytes.push(*authorization_mode as u8);
+        put_hash(&mut bytes, payer_auth_hash);
+        put_hash(&mut bytes, merchant_auth_hash);
+        put_hash(&mut bytes, payer_reassign_auth_hash);
+        put_hash(&mut bytes, merchant_reassign_auth_hash);
+        put_hash(&mut bytes, plan_change_auth_hash);
+        match max_amount_cap {
+            Some(cap) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&cap.to_be_bytes());
+            }
+            None => bytes.push(0),
+        }
+        put_str(&mut bytes, funder_pubkey);
+        bytes.extend_from_slice(&seats.to_be_bytes());
+        bytes.extend_from_slice(&price_per_seat_sats.to_be_bytes());
+        bytes.extend_from_slice(&seat_credit_sats.to_be_bytes());
+        put_hash(&mut bytes, escrow_release_hash);
+        bytes.extend_from_slice(&dispute_window_blocks.to_be_bytes());
+        bytes.extend_from_slice(&last_charge_sats.to_be_bytes());
+        bytes.push(*allow_partial_final as u8);
+        bytes.push(*accrue_payments as u8);
+        bytes.extend_from_slice(&merchant_withdrawable_sats.to_be_bytes());
+        bytes.extend_from_slice(&usd_amount_cents.to_be_bytes());
+        bytes.extend_from_slice(&slippage_bps.to_be_bytes());
+        put_hash(&mut bytes, oracle_auth_hash);
+        bytes.push(*lapsed as u8);
+        bytes.extend_from_slice(&setup_fee_sats.to_be_bytes());
+        bytes.extend_from_slice(&min_commitment_cycles.to_be_bytes());
+        bytes.extend_from_slice(&early_termination_fee_sats.to_be_bytes());
+        bytes.extend_from_slice(&contract_version.to_be_bytes());
+        bytes.extend_from_slice(&(allowed_merchants.len() as u64).to_be_bytes());
+        for merchant in allowed_merchants {
+            put_str(&mut bytes, merchant);
+        }
+        bytes.extend_from_slice(&last_state_change_block.to_be_bytes());
+        bytes.push(*currency_unit as u8);
+        put_str(&mut bytes, subscription_id);
+        bytes.extend_from_slice(&annual_yield_bps.to_be_bytes());
+        bytes.extend_from_slice(&nonce.to_be_bytes());
+        bytes
     }
 
-    false
-}
+    /// Hash of the canonical encoding: equal states always hash the same
+    pub fn state_hash(&self) -> B32 {
+        hash_bytes(&self.canonical_bytes())
+    }
 
-// Full validation for subscription payment with MinimalSubscriptionState
-fn validate_subscription_payment_full(
-    in_state: &MinimalSubscriptionState,
-    out_state: &MinimalSubscriptionState,
-    token_app: &App,
-    tx: &Transaction,
-) -> bool {
-    // 1. Validate subscription is active
-    check!(in_state.is_active);
-    check!(out_state.is_active); // Should remain active after payment
-
-    // 2. Validate immutable fields don't change
-    check!(in_state.payer_pubkey == out_state.payer_pubkey);
-    check!(in_state.merchant_pubkey == out_state.merchant_pubkey);
-    check!(in_state.amount_sats == out_state.amount_sats);
-    check!(in_state.billing_interval_blocks == out_state.billing_interval_blocks);
-
-    // 3. Validate payment amount matches subscription amount
-    let payment_amount = in_state.remaining_balance - out_state.remaining_balance;
-    check!(payment_amount == in_state.amount_sats);
-
-    // 4. Validate remaining balance decreases correctly
-    check!(in_state.remaining_balance >= out_state.remaining_balance);
-    check!(out_state.remaining_balance == in_state.remaining_balance - in_state.amount_sats);
-
-    // 5. Validate last_payment_block is updated (should increase)
-    // Note: We can't check current block in contract, but we can ensure it's updated
-    check!(out_state.last_payment_block >= in_state.last_payment_block);
-
-    // 6. Validate token amounts match
-    let Some(input_token_amount) = sum_token_amount(&token_app, tx.ins.iter().map(|(_, v)| v)).ok()
-    else {
-        eprintln!("could not determine input total token amount");
-        return false;
-    };
-    let Some(output_token_amount) = sum_token_amount(&token_app, tx.outs.iter()).ok() else {
-        eprintln!("could not determine output total token amount");
-        return false;
-    };
+    /// CBOR encoding of the state, byte for byte what the charm data layer stores
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(self, &mut bytes).expect("writing to a Vec cannot fail");
+        bytes
+    }
 
-    // Tokens should be transferred (not minted/burned)
-    check!(output_token_amount == input_token_amount);
+    /// Decode a state from CBOR; the long field names are accepted like the short keys
+    #[cfg(feature = "cbor")]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, ValidationError> {
+        ciborium::from_reader(bytes).map_err(|_| ValidationError::InvalidNftState)
+    }
 
-    true
-}
+    /// Full state for a `SubscriptionState`, which does not record the parties or the interval
+    /// What was locked but is no longer remaining counts as paid
+    pub fn from_legacy(
+        state: &SubscriptionState,
+        payer: String,
+        merchant: String,
+        interval: u32,
+    ) -> Result<Self, ValidationError> {
+        let total_paid_sats =
+            sub_or_reject(state.total_locked, state.remaining_balance).map_err(|_| {
+                ValidationError::BalanceExceedsLocked {
+                    remaining: state.remaining_balance,
+                    locked: state.total_locked,
+                }
+            })?;
+        let mut full = MinimalSubscriptionState::builder()
+            .subscription_id(state.subscription_id.clone())
+            .payer_pubkey(payer)
+            .merchant_pubkey(merchant)
+            .amount_sats(state.amount_per_cycle)
+            .billing_interval_blocks(interval)
+            .remaining_balance(state.remaining_balance)
+            .build()?;
+        full.total_paid_sats = total_paid_sats;
+        Ok(full)
+    }
 
-// Validate cancellation - only payer can cancel
-fn validate_subscription_cancellation(
-    in_state: &MinimalSubscriptionState,
-    out_state: &MinimalSubscriptionState,
-    tx: &Transaction,
-) -> bool {
-    // 1. Subscription must be active to cancel
-    check!(in_state.is_active);
+    /// Cancelled state releasing the whole remaining balance, every other field preserved
+    pub fn cancel(&self) -> Result<MinimalSubscriptionState, ValidationError> {
+        Ok(MinimalSubscriptionState {
+            is_active: false,
+            remaining_balance: 0,
+            merchant_withdrawable_sats: 0,
+            nonce: self.next_nonce()?,
+            ..self.clone()
+        })
+    }
 
-    // 2. After cancellation, is_active should be false
-    check!(!out_state.is_active);
+    /// Tokens locked with the subscription: the remaining balance and the accrued payments
+    pub fn locked_sats(&self) -> u64 {
+        self.remaining_balance
+            .saturating_add(self.merchant_withdrawable_sats)
+    }
 
-    // 3. Remaining balance should be zero
-    check!(out_state.remaining_balance == 0);
+    /// Nonce the next transition must record
+    pub fn next_nonce(&self) -> Result<u64, ValidationError> {
+        self.nonce.checked_add(1).ok_or(ValidationError::Overflow)
+    }
 
-    // 4. Immutable fields should remain the same
-    check!(in_state.payer_pubkey == out_state.payer_pubkey);
-    check!(in_state.merchant_pubkey == out_state.merchant_pubkey);
-    check!(in_state.amount_sats == out_state.amount_sats);
-    check!(in_state.billing_interval_blocks == out_state.billing_interval_blocks);
+    /// Whether the subscription may pay `merchant_pubkey`
+    pub fn is_merchant_allowed(&self, merchant_pubkey: &str) -> bool {
+        self.allowed_merchants.is_empty()
+            || self
+                .allowed_merchants
+                .iter()
+                .any(|key| key == merchant_pubkey)
+    }
 
-    // Note: Payer authorization would be validated by checking the transaction inputs
-    // This requires access to the transaction's input scripts, which is handled by Bitcoin
-    // The contract assumes only the payer can spend the UTXO
+    /// Tokens of the current cycle's amount the merchant has earned by `current_block`, prorated
+    /// over the interval ending at the next due block; the rest of the balance is the payer's
+    /// on cancellation. Nothing is earned at the start of the cycle, the whole amount (capped by
+    /// the balance) once the payment is due
+    pub fn prorated_charge(&self, current_block: u32) -> u64 {
+        let next_due_block = self.next_due_block();
+        let cycle_start = next_due_block.saturating_sub(self.billing_interval_blocks);
+        let elapsed = current_block
+            .saturating_sub(cycle_start)
+            .min(self.billing_interval_blocks);
+        let cycle_amount = self
+            .currency_unit
+            .to_tokens(self.amount_sats)
+            .unwrap_or(0)
+            .min(self.remaining_balance);
+        (cycle_amount as u128 * elapsed as u128)
+            .checked_div(self.billing_interval_blocks as u128)
+            .unwrap_or(0) as u64
+    }
+
+    /// Yield the remaining balance has earned from the last payment until `current_block`, at
+    /// most one billing interval's worth
+    pub fn accrued_yield(&self, current_block: u32) -> Result<u64, ValidationError> {
+        let elapsed = current_block
+            .saturating_sub(self.last_payment_block)
+            .min(self.billing_interval_blocks);
+        let earned = (self.remaining_balance as u128)
+            .checked_mul(self.annual_yield_bps as u128)
+            .and_then(|earned| earned.checked_mul(elapsed as u128))
+            .ok_or(ValidationError::Overflow)?
+            / (BASIS_POINTS as u128 * BLOCKS_PER_YEAR as u128);
+        u64::try_from(earned).map_err(|_| ValidationError::Overflow)
+    }
 
-    true
+    /// Block of the last payment the remaining balance covers, if every payment is made as soon
+    /// as it is due from `current_block` on; `current_block` if no cycle can be paid anymore
+    pub fn cycles_until_empty_block(&self, current_block: u32) -> u32 {
+        let remaining_cycles = self.remaining_cycles();
+        if remaining_cycles == 0 {
+            return current_block;
+        }
+        let later_cycles = u32::try_from(remaining_cycles - 1).unwrap_or(u32::MAX);
+        current_block
+            .max(self.next_due_block())
+            .saturating_add(later_cycles.saturating_mul(self.billing_interval_blocks))
+    }
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use charms_sdk::data::{App, Data, Transaction, UtxoId, B32, NFT, TOKEN};
-    use std::collections::HashMap;
+/// One-line summary of the terms and balance, with keys shortened to a prefix
+/// e.g. `sub payer=02ab.. merchant=03de.. amount=100000 interval=144 bal=900000 active`
+impl std::fmt::Display for MinimalSubscriptionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match (self.is_active, self.paused) {
+            (false, _) => "inactive",
+            (true, true) => "paused",
+            (true, false) => "active",
+        };
+        write!(
+            f,
+            "sub payer={} merchant={} amount={} interval={} bal={} {}",
+            KeyPrefix(&self.payer_pubkey),
+            KeyPrefix(&self.merchant_pubkey),
+            self.amount_sats,
+            self.billing_interval_blocks,
+            self.remaining_balance,
+            status
+        )
+    }
+}
 
-    #[test]
-    fn test_hash() {
-        let utxo_id =
-            UtxoId::from_str("dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1")
-                .unwrap();
-        let data = dbg!(utxo_id.to_string());
-        let expected = "f54f6d40bd4ba808b188963ae5d72769ad5212dd1d29517ecc4063dd9f033faa";
-        assert_eq!(&hash(&data).to_string(), expected);
+// Displays the first characters of a key followed by `..`, or the whole key if it is that short
+struct KeyPrefix<'a>(&'a str);
+
+impl std::fmt::Display for KeyPrefix<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const PREFIX_CHARS: usize = 4;
+        match self.0.char_indices().nth(PREFIX_CHARS) {
+            Some((end, _)) => write!(f, "{}..", &self.0[..end]),
+            None => f.write_str(self.0),
+        }
     }
+}
 
-    #[test]
-    fn test_subscription_state_to_nft_content() {
-        let state = SubscriptionState {
-            subscription_id: "sub_001".to_string(),
-            recipient: "bc1qtest".to_string(),
-            amount_per_cycle: 100000,
-            remaining_balance: 1000000,
-            total_locked: 1000000,
-        };
+/// JSON Schema of `MinimalSubscriptionState` as it is serialized, with the field docs as
+/// descriptions; 32-byte hashes appear as hex strings
+#[cfg(feature = "schemars")]
+pub fn schema() -> schemars::Schema {
+    schemars::schema_for!(MinimalSubscriptionState)
+}
 
-        let nft_content: NftContent = state.into();
-        assert_eq!(nft_content.ticker, "SUBSCRIPTION-sub_001");
-        assert_eq!(nft_content.remaining, 1000000);
+/// Average number of blocks mined per day, at the ten-minute target spacing
+pub const BLOCKS_PER_DAY: u32 = 144;
+
+/// Blocks in a year at `BLOCKS_PER_DAY`, the period `annual_yield_bps` is earned over
+pub const BLOCKS_PER_YEAR: u32 = BLOCKS_PER_DAY * 365;
+
+/// Number of blocks a network mines per day, used to convert billing periods to blocks
+/// Defaults to `BLOCKS_PER_DAY`; test networks whose block spacing differs can override it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlocksPerDay(pub u32);
+
+impl Default for BlocksPerDay {
+    fn default() -> Self {
+        BlocksPerDay(BLOCKS_PER_DAY)
     }
+}
 
-    #[test]
-    fn test_minimal_subscription_state() {
-        let state = MinimalSubscriptionState {
-            payer_pubkey: "02abc123...".to_string(),
-            merchant_pubkey: "03def456...".to_string(),
-            amount_sats: 100000,
-            billing_interval_blocks: 144,
-            last_payment_block: 850000,
-            is_active: true,
-            remaining_balance: 1000000,
-        };
+/// Calendar length of a billing cycle, converted to blocks for `billing_interval_blocks`
+/// The conversion is approximate: a month counts as 30 days, and real block times drift from
+/// the ten-minute target, so a cycle's wall-clock length varies by hours over a month
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BillingPeriod {
+    Days(u32),
+    Weeks(u32),
+    Months(u32),
+}
 
-        assert_eq!(state.amount_sats, 100000);
-        assert_eq!(state.is_active, true);
+impl BillingPeriod {
+    /// Length of the period in blocks at `BLOCKS_PER_DAY`; saturates instead of overflowing
+    pub fn to_blocks(&self) -> u32 {
+        self.to_blocks_with(BlocksPerDay::default())
+    }
+
+    /// Length of the period in blocks at a custom `blocks_per_day` (e.g. for test networks)
+    pub fn to_blocks_with(&self, blocks_per_day: BlocksPerDay) -> u32 {
+        self.days().saturating_mul(blocks_per_day.0)
+    }
+
+    // Length of the period in days, a month counting as 30
+    fn days(&self) -> u32 {
+        match *self {
+            BillingPeriod::Days(days) => days,
+            BillingPeriod::Weeks(weeks) => weeks.saturating_mul(7),
+            BillingPeriod::Months(months) => months.saturating_mul(30),
+        }
+    }
+}
+
+/// Smallest payment output value, in satoshis, that is not dust for any standard output type
+pub const DUST_LIMIT_SATS: u64 = 546;
+
+/// Version of this contract, recorded in the full states it creates
+/// A state written for another version is rejected instead of being misinterpreted
+pub const CONTRACT_VERSION: u32 = 1;
+
+/// Builder for MinimalSubscriptionState
+/// Enforces the creation invariants: an amount above dust, a non-zero interval, a platform fee
+/// no larger than the amount, and a remaining balance covering at least one billing cycle
+#[derive(Debug, Clone)]
+pub struct MinimalSubscriptionStateBuilder {
+    state: MinimalSubscriptionState,
+    dust_limit_sats: u64,
+    billing_period: Option<BillingPeriod>,
+    blocks_per_day: BlocksPerDay,
+}
+
+impl Default for MinimalSubscriptionStateBuilder {
+    fn default() -> Self {
+        MinimalSubscriptionStateBuilder {
+            state: MinimalSubscriptionState {
+                is_active: true,
+                contract_version: CONTRACT_VERSION,
+                ..Default::default()
+            },
+            dust_limit_sats: DUST_LIMIT_SATS,
+            billing_period: None,
+            blocks_per_day: BlocksPerDay::default(),
+        }
+    }
+}
+
+impl MinimalSubscriptionStateBuilder {
+    pub fn payer_pubkey(mut self, payer_pubkey: impl Into<String>) -> Self {
+        self.state.payer_pubkey = payer_pubkey.into();
+        self
+    }
+
+    pub fn merchant_pubkey(mut self, merchant_pubkey: impl Into<String>) -> Self {
+        self.state.merchant_pubkey = merchant_pubkey.into();
+        self
+    }
+
+    /// Fund the subscription from `funder_pubkey` on behalf of the payer, e.g. as a gift
+    pub fn funder_pubkey(mut self, funder_pubkey: impl Into<String>) -> Self {
+        self.state.funder_pubkey = funder_pubkey.into();
+        self
+    }
+
+    pub fn amount_sats(mut self, amount_sats: u64) -> Self {
+        self.state.amount_sats = amount_sats;
+        self
+    }
+
+    pub fn billing_interval_blocks(mut self, billing_interval_blocks: u32) -> Self {
+        self.state.billing_interval_blocks = billing_interval_blocks;
+        self.billing_period = None;
+        self
+    }
+
+    /// Bill `seats` seats at `price_per_seat_sats` each, setting `amount_sats` to their total
+    pub fn per_seat(mut self, seats: u32, price_per_seat_sats: u64) -> Self {
+        self.state.seats = seats;
+        self.state.price_per_seat_sats = price_per_seat_sats;
+        self.state.amount_sats = price_per_seat_sats.saturating_mul(seats as u64);
+        self
+    }
+
+    /// Bill every `billing_period`, converted to blocks at the builder's `blocks_per_day`
+    pub fn billing_period(mut self, billing_period: BillingPeriod) -> Self {
+        self.billing_period = Some(billing_period);
+        self
+    }
+
+    /// Convert the billing period at `blocks_per_day` instead of `BLOCKS_PER_DAY`, to target a
+    /// network with a different block spacing
+    pub fn blocks_per_day(mut self, blocks_per_day: BlocksPerDay) -> Self {
+        self.blocks_per_day = blocks_per_day;
+        self
+    }
+
+    pub fn last_payment_block(mut self, last_payment_block: u32) -> Self {
+        self.state.last_payment_block = last_payment_block;
+        self
+    }
+
+    pub fn is_active(mut self, is_active: bool) -> Self {
+        self.state.is_active = is_active;
+        self
+    }
+
+    pub fn remaining_balance(mut self, remaining_balance: u64) -> Self {
+        self.state.remaining_balance = remaining_balance;
+        self
+    }
+
+    pub fn grace_period_blocks(mut self, grace_period_blocks: u32) -> Self {
+        self.state.grace_period_blocks = grace_period_blocks;
+        self
+    }
+
+    pub fn trial_end_block(mut self, trial_end_block: u32) -> Self {
+        self.state.trial_end_block = trial_end_block;
+        self
+    }
+
+    pub fn platform_pubkey(mut self, platform_pubkey: impl Into<String>) -> Self {
+        self.state.platform_pubkey = platform_pubkey.into();
+        self
+    }
+
+    pub fn platform_fee_sats(mut self, platform_fee_sats: u64) -> Self {
+        self.state.platform_fee_sats = platform_fee_sats;
+        self
+    }
+
+    /// Charge `setup_fee_sats` to the merchant once, when the subscription is minted
+    pub fn setup_fee_sats(mut self, setup_fee_sats: u64) -> Self {
+        self.state.setup_fee_sats = setup_fee_sats;
+        self
+    }
+
+    /// Commit the payer to `cycles` cycles, cancelling earlier costs `early_termination_fee_sats`
+    pub fn min_commitment(mut self, cycles: u32, early_termination_fee_sats: u64) -> Self {
+        self.state.min_commitment_cycles = cycles;
+        self.state.early_termination_fee_sats = early_termination_fee_sats;
+        self
+    }
+
+    /// Restrict the merchants the subscription may ever pay to `allowed_merchants`
+    pub fn allowed_merchants(
+        mut self,
+        allowed_merchants: impl IntoIterator<Item = impl Into<String>>,
+    ) -> Self {
+        self.state.allowed_merchants = allowed_merchants.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Express `amount_sats` and its bounds in `currency_unit` instead of satoshis
+    pub fn currency_unit(mut self, currency_unit: CurrencyUnit) -> Self {
+        self.state.currency_unit = currency_unit;
+        self
+    }
+
+    pub fn subscription_id(mut self, subscription_id: impl Into<String>) -> Self {
+        self.state.subscription_id = subscription_id.into();
+        self
+    }
+
+    /// Let the remaining balance earn `annual_yield_bps` a year, credited at each payment
+    pub fn annual_yield_bps(mut self, annual_yield_bps: u16) -> Self {
+        self.state.annual_yield_bps = annual_yield_bps;
+        self
+    }
+
+    pub fn denomination_token(mut self, denomination_token: B32) -> Self {
+        self.state.denomination_token = Some(denomination_token);
+        self
+    }
+
+    pub fn prepaid_cycles(mut self, prepaid_cycles: u32) -> Self {
+        self.state.prepaid_cycles = prepaid_cycles;
+        self
+    }
+
+    pub fn late_fee_sats(mut self, late_fee_sats: u64) -> Self {
+        self.state.late_fee_sats = late_fee_sats;
+        self
+    }
+
+    pub fn max_amount_cap(mut self, max_amount_cap: u64) -> Self {
+        self.state.max_amount_cap = Some(max_amount_cap);
+        self
+    }
+
+    pub fn dispute_window_blocks(mut self, dispute_window_blocks: u32) -> Self {
+        self.state.dispute_window_blocks = dispute_window_blocks;
+        self
+    }
+
+    pub fn allow_partial_final(mut self, allow_partial_final: bool) -> Self {
+        self.state.allow_partial_final = allow_partial_final;
+        self
+    }
+
+    pub fn accrue_payments(mut self, accrue_payments: bool) -> Self {
+        self.state.accrue_payments = accrue_payments;
+        self
+    }
+
+    /// Price each cycle at `usd_amount_cents`, paid in sats within `slippage_bps` of the rate
+    /// attested by the oracle whose hash chain ends in `oracle_auth_hash`
+    /// A link reveals no rate of its own, so the oracle should keep a chain per subscription
+    pub fn usd_amount(
+        mut self,
+        usd_amount_cents: u64,
+        slippage_bps: u16,
+        oracle_auth_hash: B32,
+    ) -> Self {
+        self.state.usd_amount_cents = usd_amount_cents;
+        self.state.slippage_bps = slippage_bps;
+        self.state.oracle_auth_hash = Some(oracle_auth_hash);
+        self
+    }
+
+    pub fn max_attempts(mut self, max_attempts: u8) -> Self {
+        self.state.max_attempts = max_attempts;
+        self
+    }
+
+    /// Charge any amount within `[min_amount_sats, max_amount_sats]` instead of `amount_sats`
+    pub fn amount_bounds(mut self, min_amount_sats: u64, max_amount_sats: u64) -> Self {
+        self.state.min_amount_sats = min_amount_sats;
+        self.state.max_amount_sats = max_amount_sats;
+        self
+    }
+
+    /// Allow ownership transfers authorized by `transfer_auth`, storing only its hash
+    pub fn transfer_auth(mut self, transfer_auth: &str) -> Self {
+        self.state.transfer_auth_hash = Some(hash(transfer_auth));
+        self
+    }
+
+    pub fn authorization_mode(mut self, authorization_mode: AuthorizationMode) -> Self {
+        self.state.authorization_mode = authorization_mode;
+        self
+    }
+
+    /// Require push payments to reveal `payer_auth`, storing only its hash
+    pub fn payer_auth(mut self, payer_auth: &str) -> Self {
+        self.state.payer_auth_hash = Some(hash(payer_auth));
+        self
+    }
+
+    /// Require pull payments to reveal `merchant_auth`, storing only its hash
+    pub fn merchant_auth(mut self, merchant_auth: &str) -> Self {
+        self.state.merchant_auth_hash = Some(hash(merchant_auth));
+        self
+    }
+
+    /// Allow reassigning the merchant with the consents `payer_auth` and `merchant_auth`,
+    /// storing only their hashes
+    pub fn reassignment_auth(mut self, payer_auth: &str, merchant_auth: &str) -> Self {
+        self.state.payer_reassign_auth_hash = Some(hash(payer_auth));
+        self.state.merchant_reassign_auth_hash = Some(hash(merchant_auth));
+        self
+    }
+
+    /// Allow plan changes authorized by `plan_change_auth`, storing only its hash
+    pub fn plan_change_auth(mut self, plan_change_auth: &str) -> Self {
+        self.state.plan_change_auth_hash = Some(hash(plan_change_auth));
+        self
+    }
+
+    /// Release each payment only against `fulfillment`, storing only its hash
+    pub fn escrow_release(mut self, fulfillment: &str) -> Self {
+        self.state.escrow_release_hash = Some(hash(fulfillment));
+        self
+    }
+
+    pub fn expiry_block(mut self, expiry_block: u32) -> Self {
+        self.state.expiry_block = expiry_block;
+        self
+    }
+
+    pub fn max_cycles(mut self, max_cycles: u32) -> Self {
+        self.state.max_cycles = max_cycles;
+        self
+    }
+
+    pub fn hash_algo(mut self, hash_algo: HashAlgo) -> Self {
+        self.state.hash_algo = hash_algo;
+        self
+    }
+
+    /// Issue a one-time coupon, storing only the hash of its code
+    pub fn coupon_code(mut self, coupon_code: &str) -> Self {
+        self.state.coupon_hash = Some(hash(coupon_code));
+        self
+    }
+
+    /// Reject amounts below `dust_limit_sats` instead of `DUST_LIMIT_SATS`, for payment
+    /// outputs of a type with a different dust threshold
+    pub fn dust_limit_sats(mut self, dust_limit_sats: u64) -> Self {
+        self.dust_limit_sats = dust_limit_sats;
+        self
+    }
+
+    /// Check the creation invariants and return the state
+    pub fn build(mut self) -> Result<MinimalSubscriptionState, ValidationError> {
+        if let Some(billing_period) = self.billing_period {
+            self.state.billing_interval_blocks = billing_period.to_blocks_with(self.blocks_per_day);
+        }
+        self.state.validate_with_dust_limit(self.dust_limit_sats)?;
+        Ok(self.state)
+    }
+}
+
+/// Subscription state stored in NFT (backward compatible)
+/// This represents a subscription with locked funds
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct SubscriptionState {
+    /// Subscription identifier (e.g., "sub_001")
+    pub subscription_id: String,
+    /// Recipient address (who receives payments)
+    pub recipient: String,
+    /// Amount per billing cycle (in satoshis)
+    pub amount_per_cycle: u64,
+    /// Remaining locked balance (in satoshis)
+    pub remaining_balance: u64,
+    /// Total amount originally locked (in satoshis)
+    pub total_locked: u64,
+}
+
+/// Legacy NFT content structure (for backward compatibility)
+/// Maps to SubscriptionState for subscription NFTs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct NftContent {
+    pub ticker: String,
+    pub remaining: u64,
+}
+
+impl NftContent {
+    /// Subscription id encoded in a `SUBSCRIPTION-<id>` ticker
+    pub fn subscription_id(&self) -> Option<&str> {
+        self.ticker
+            .strip_prefix(TICKER_PREFIX)
+            .filter(|id| !id.is_empty())
+    }
+}
+
+/// Prefix of the ticker of every subscription NFT
+pub const TICKER_PREFIX: &str = "SUBSCRIPTION-";
+
+/// Longest ticker `make_ticker` produces, prefix included
+pub const MAX_TICKER_LEN: usize = 80;
+
+// Hex digits of the id's hash kept at the end of a truncated ticker
+const TICKER_HASH_LEN: usize = 16;
+
+/// Ticker of the subscription NFT for `subscription_id`: `SUBSCRIPTION-<id>`
+/// Ids may only use ASCII letters, digits, `_` and `-`; one too long for `MAX_TICKER_LEN` is
+/// truncated and suffixed with `.` and the start of its hash, so distinct ids keep distinct
+/// tickers
+pub fn make_ticker(subscription_id: &str) -> Result<String, ValidationError> {
+    ensure!(
+        !subscription_id.is_empty()
+            && subscription_id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-'),
+        ValidationError::InvalidSubscriptionId
+    );
+    let max_id_len = MAX_TICKER_LEN - TICKER_PREFIX.len();
+    if subscription_id.len() <= max_id_len {
+        return Ok(format!("{TICKER_PREFIX}{subscription_id}"));
+    }
+    let kept = &subscription_id[..max_id_len - 1 - TICKER_HASH_LEN];
+    let digest = hash(subscription_id).to_string();
+    Ok(format!(
+        "{TICKER_PREFIX}{kept}.{}",
+        &digest[..TICKER_HASH_LEN]
+    ))
+}
+
+// Ticker made by `make_ticker`, an id it rejects being replaced by its hash
+fn ticker_or_hashed(subscription_id: &str) -> String {
+    make_ticker(subscription_id)
+        .or_else(|_| make_ticker(&hash(subscription_id).to_string()))
+        .expect("a hex hash is a valid subscription id")
+}
+
+impl From<SubscriptionState> for NftContent {
+    /// The ticker is made by `make_ticker`; an id it rejects is replaced by its hash
+    fn from(state: SubscriptionState) -> Self {
+        NftContent {
+            ticker: ticker_or_hashed(&state.subscription_id),
+            remaining: state.remaining_balance,
+        }
+    }
+}
+
+impl From<&MinimalSubscriptionState> for NftContent {
+    /// The ticker is made from the subscription id like that of a [`SubscriptionState`]; states
+    /// written without one use the hash of the parties and terms, so distinct subscriptions
+    /// still get distinct tickers
+    fn from(state: &MinimalSubscriptionState) -> Self {
+        let ticker = if state.subscription_id.is_empty() {
+            let terms = MigrationTerms {
+                payer_pubkey: state.payer_pubkey.clone(),
+                merchant_pubkey: state.merchant_pubkey.clone(),
+                amount_sats: state.amount_sats,
+                billing_interval_blocks: state.billing_interval_blocks,
+            };
+            format!("{TICKER_PREFIX}{}", terms.subscription_id())
+        } else {
+            ticker_or_hashed(&state.subscription_id)
+        };
+        NftContent {
+            ticker,
+            remaining: state.remaining_balance,
+        }
+    }
+}
+
+/// Versioned subscription state stored in the NFT
+/// Tagged with a `version` key so new formats can be added without ambiguous probing
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "version")]
+pub enum SubscriptionStateV {
+    /// Legacy state: ticker and remaining supply only
+    V1(NftContent),
+    /// Full subscription state
+    V2(MinimalSubscriptionState),
+    /// Full subscription state with payments split between several recipients
+    V3(SplitSubscriptionState),
+    /// Full subscription state charged by recorded usage instead of a fixed amount
+    V4(MeteredSubscriptionState),
+    /// Full subscription state whose per-cycle amount depends on the total paid so far
+    V5(TieredSubscriptionState),
+}
+
+// Only used to detect whether charm data carries a version tag
+#[derive(Deserialize)]
+struct VersionTag {
+    #[serde(default)]
+    version: Option<serde::de::IgnoredAny>,
+}
+
+impl SubscriptionStateV {
+    /// Parse subscription state from NFT charm data
+    /// Untagged data written before versioning is decoded as V2 first, then V1
+    /// Split (V3), metered (V4) and tiered (V5) states are only ever written with their version tag
+    pub fn parse(data: &Data) -> Result<Self, ValidationError> {
+        if let Ok(state) = data.value::<SubscriptionStateV>() {
+            return Ok(state);
+        }
+        // A version tag we could not decode is never reinterpreted as a legacy state
+        if let Ok(VersionTag { version: Some(_) }) = data.value::<VersionTag>() {
+            return Err(ValidationError::UnknownVersion);
+        }
+        if let Ok(state) = data.value::<MinimalSubscriptionState>() {
+            return Ok(SubscriptionStateV::V2(state));
+        }
+        data.value::<NftContent>()
+            .map(SubscriptionStateV::V1)
+            .map_err(|_| ValidationError::InvalidNftState)
+    }
+
+    /// Remaining locked balance, which is also the token supply managed by the NFT
+    pub fn remaining_supply(&self) -> u64 {
+        match self {
+            SubscriptionStateV::V1(content) => content.remaining,
+            SubscriptionStateV::V2(state) => state.remaining_balance,
+            SubscriptionStateV::V3(state) => state.subscription.remaining_balance,
+            SubscriptionStateV::V4(state) => state.subscription.remaining_balance,
+            SubscriptionStateV::V5(state) => state.subscription.remaining_balance,
+        }
+    }
+
+    /// Subscription terms and state, unless this is a legacy state which does not record them
+    pub fn subscription(&self) -> Option<&MinimalSubscriptionState> {
+        match self {
+            SubscriptionStateV::V1(_) => None,
+            SubscriptionStateV::V2(state) => Some(state),
+            SubscriptionStateV::V3(state) => Some(&state.subscription),
+            SubscriptionStateV::V4(state) => Some(&state.subscription),
+            SubscriptionStateV::V5(state) => Some(&state.subscription),
+        }
+    }
+
+    /// Algorithm the NFT identity is derived with; legacy states always use SHA-256
+    pub fn hash_algo(&self) -> HashAlgo {
+        self.subscription()
+            .map_or(HashAlgo::Sha256, |state| state.hash_algo)
+    }
+
+    /// Subscription id off-chain indexers key the NFT by: the legacy ticker id, the recorded
+    /// id of full states, or the hash of the parties and terms for those written without one
+    pub fn subscription_id(&self) -> Option<String> {
+        match self.subscription() {
+            Some(state) if !state.subscription_id.is_empty() => Some(state.subscription_id.clone()),
+            Some(state) => NftContent::from(state)
+                .subscription_id()
+                .map(str::to_string),
+            None => match self {
+                SubscriptionStateV::V1(content) => content.subscription_id().map(str::to_string),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// Basis points in a whole payment, used for split shares and discounts
+pub const BASIS_POINTS: u32 = 10000;
+
+/// How many sats a split recipient may be short of its exact share due to rounding
+pub const SPLIT_ROUNDING_TOLERANCE_SATS: u64 = 1;
+
+/// Subscription whose payments are shared between several recipients
+/// (e.g. a merchant and a platform operator)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SplitSubscriptionState {
+    /// Underlying subscription terms and state
+    pub subscription: MinimalSubscriptionState,
+
+    /// Payment recipients as (pubkey, basis points), summing to `BASIS_POINTS`
+    /// Immutable: Set at creation, never changes
+    pub splits: Vec<(String, u16)>,
+}
+
+impl SplitSubscriptionState {
+    /// Check that the shares add up to the whole payment and name each recipient once
+    pub fn validate_splits(&self) -> Result<(), ValidationError> {
+        let total_bps: u32 = self.splits.iter().map(|(_, bps)| *bps as u32).sum();
+        ensure!(total_bps == BASIS_POINTS, ValidationError::InvalidSplits);
+        let mut recipients: Vec<&String> = self.splits.iter().map(|(pubkey, _)| pubkey).collect();
+        recipients.sort();
+        recipients.dedup();
+        ensure!(
+            recipients.len() == self.splits.len(),
+            ValidationError::InvalidSplits
+        );
+        Ok(())
+    }
+}
+
+/// Subscription charged per unit of recorded usage rather than a fixed `amount_sats`
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MeteredSubscriptionState {
+    /// Underlying subscription terms and state; `amount_sats` is not used for charging
+    pub subscription: MinimalSubscriptionState,
+
+    /// Price of one unit of usage (in satoshis)
+    /// Immutable: Set at creation, never changes
+    pub price_per_unit_sats: u64,
+
+    /// Maximum number of units that can be charged in a single cycle
+    /// Immutable: Set at creation, never changes
+    pub max_units_per_cycle: u32,
+
+    /// Units charged so far in the current cycle
+    /// Mutable: Grows with usage charged within the cycle, restarts when a new cycle is paid
+    #[serde(default)]
+    pub cycle_usage: u32,
+
+    /// Beneficiaries sharing the balance as (pubkey, per-cycle unit quota); when not empty,
+    /// every charge is made by one of them
+    /// Immutable: Set at creation, never changes
+    #[serde(default)]
+    pub beneficiaries: Vec<(String, u32)>,
+
+    /// Units charged so far in the current cycle by each beneficiary, in `beneficiaries` order
+    /// Mutable: Grows with the charging beneficiary's usage, restarts when a new cycle is paid
+    #[serde(default)]
+    pub beneficiary_usage: Vec<u32>,
+}
+
+/// Subscription with volume discounts: the per-cycle amount drops as the total paid grows
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct TieredSubscriptionState {
+    /// Underlying subscription terms and state; `amount_sats` applies below the first threshold
+    pub subscription: MinimalSubscriptionState,
+
+    /// Pricing tiers as (`total_paid_sats` threshold, per-cycle amount), sorted by threshold
+    /// Immutable: Set at creation, never changes
+    pub tiers: Vec<(u64, u64)>,
+}
+
+impl TieredSubscriptionState {
+    /// Create a tiered subscription, rejecting an unsorted tiers list
+    pub fn new(
+        subscription: MinimalSubscriptionState,
+        tiers: Vec<(u64, u64)>,
+    ) -> Result<Self, ValidationError> {
+        let state = TieredSubscriptionState {
+            subscription,
+            tiers,
+        };
+        state.validate_tiers()?;
+        Ok(state)
+    }
+
+    /// Check that thresholds are strictly increasing and every tier charges something
+    pub fn validate_tiers(&self) -> Result<(), ValidationError> {
+        ensure!(
+            self.tiers.windows(2).all(|pair| pair[0].0 < pair[1].0),
+            ValidationError::InvalidTiers
+        );
+        ensure!(
+            self.tiers.iter().all(|(_, amount)| *amount > 0),
+            ValidationError::InvalidTiers
+        );
+        Ok(())
+    }
+
+    /// Per-cycle amount for the current cumulative payment
+    /// The last tier whose threshold has been reached applies, `amount_sats` before any
+    pub fn current_amount(&self) -> u64 {
+        self.tiers
+            .iter()
+            .rev()
+            .find(|(threshold, _)| self.subscription.total_paid_sats >= *threshold)
+            .map_or(self.subscription.amount_sats, |(_, amount)| *amount)
+    }
+}
+
+/// Witness for minting an NFT whose identity is derived from the subscription terms
+/// (see `derive_subscription_identity`) or from its subscription id
+/// (see `derive_subscription_id_identity`); a bare funding UTXO id string keeps the legacy
+/// UTXO-only identity
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MintWitness {
+    /// UTXO id spent to fund the subscription
+    pub funding_utxo: String,
+    /// Subscription id the NFT identity is bound to, selecting the id-based identity
+    #[serde(default)]
+    pub subscription_id: Option<String>,
+}
+
+/// Witness supplied alongside subscription state transitions
+/// Carries data the contract cannot observe on its own (e.g. the current block height)
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct SubscriptionWitness {
+    /// Block height at which the transition is executed
+    /// Required for payments: must equal the outgoing `last_payment_block`
+    pub current_block: Option<u32>,
+    /// Subscription terms supplied when migrating a legacy NftContent state, which must be the
+    /// terms its ticker id was derived from
+    pub migration: Option<MigrationTerms>,
+    /// Terms a legacy NftContent ticker id was derived from
+    /// Required for legacy payments with such a ticker: the terms bound the payment to a single
+    /// cycle
+    pub legacy_terms: Option<MigrationTerms>,
+    /// Units consumed during the cycle being charged
+    /// Required for metered payments
+    pub units: Option<u32>,
+    /// Coupon redeemed by this payment, if any
+    pub coupon: Option<CouponRedemption>,
+    /// What happens to the locked tokens when the subscription is cancelled
+    pub cancellation_mode: CancellationMode,
+    /// Preimage of the subscription's `transfer_auth_hash`
+    /// Required for ownership transfers
+    pub transfer_auth: Option<String>,
+    /// Preimage of the authorization hash of the party the subscription's mode names
+    /// Required for payments when that party committed to an authorization
+    pub payment_auth: Option<String>,
+    /// Preimage of the subscription's `escrow_release_hash`
+    /// Required for payments of subscriptions held in escrow
+    pub fulfillment: Option<String>,
+    /// Block of the payment before the one being reversed
+    /// Required for payment reversals: becomes the outgoing `last_payment_block` again
+    pub prior_payment_block: Option<u32>,
+    /// Hex-encoded witness script of the P2WSH output holding the spent subscription NFT,
+    /// starting with `<n> OP_CHECKSEQUENCEVERIFY`
+    /// Optional for payments: when given, the relative timelock also enforces the billing
+    /// interval, on top of the check of `current_block`
+    pub timelock_script: Option<String>,
+    /// Preimage of the subscription's `payer_reassign_auth_hash`
+    /// Required for merchant reassignments, alongside `merchant_auth`
+    pub payer_auth: Option<String>,
+    /// Preimage of the subscription's `merchant_reassign_auth_hash`
+    /// Required for merchant reassignments, alongside `payer_auth`
+    pub merchant_auth: Option<String>,
+    /// Preimage of the subscription's `plan_change_auth_hash`
+    /// Required for plan changes
+    pub plan_change_auth: Option<String>,
+    /// Pubkey of the beneficiary the usage is charged for
+    /// Required for metered payments of subscriptions with beneficiaries: the transaction must
+    /// spend an input locked to it
+    pub beneficiary: Option<String>,
+    /// Exchange rate attested by the subscription's price oracle
+    /// Required for payments of subscriptions priced in USD
+    pub oracle_price: Option<OraclePrice>,
+}
+
+/// How the remaining balance is released on cancellation
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum CancellationMode {
+    /// The remaining tokens are moved to an output paying the payer
+    #[default]
+    Refund,
+    /// The remaining tokens are destroyed
+    Burn,
+}
+
+/// One-time discount applied to a single payment
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CouponRedemption {
+    /// Coupon code whose hash must match the subscription's `coupon_hash`
+    pub coupon_code: String,
+    /// Hash of the coupon code, as recorded in the subscription
+    pub coupon_hash: B32,
+    /// Discount on `amount_sats` in basis points
+    pub discount_bps: u16,
+}
+
+/// Rolling-average exchange rate attested by a price oracle at a given block
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OraclePrice {
+    /// Sats per US dollar
+    pub sats_per_usd: u64,
+    /// Block the rate was attested at
+    pub block: u32,
+    /// Link of the oracle's hash chain hashing to the subscription's `oracle_auth_hash`
+    pub oracle_auth: B32,
+}
+
+/// How many blocks an oracle rate stays valid for payments after it was attested
+pub const ORACLE_MAX_AGE_BLOCKS: u32 = 6;
+
+/// How many blocks must pass between two cancellations, pauses, resumes or payment reversals
+pub const COOLDOWN_BLOCKS: u32 = 6;
+
+/// Terms a legacy subscription is upgraded with, since NftContent does not record them
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MigrationTerms {
+    pub payer_pubkey: String,
+    pub merchant_pubkey: String,
+    pub amount_sats: u64,
+    pub billing_interval_blocks: u32,
+}
+
+impl MigrationTerms {
+    /// Subscription id of the terms: the hash of the parties, amount and interval
+    pub fn subscription_id(&self) -> String {
+        hash(&format!(
+            "{}:{}:{}:{}",
+            self.payer_pubkey, self.merchant_pubkey, self.amount_sats, self.billing_interval_blocks
+        ))
+        .to_string()
+    }
+}
+
+/// Reason a subscription transaction was rejected by the contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// The witness is missing or could not be decoded
+    InvalidWitness,
+    /// The NFT identity is not the hash of the witness
+    IdentityMismatch,
+    /// The minted NFT does not carry the subscription id its identity is bound to
+    SubscriptionIdMismatch,
+    /// The UTXO named by the witness is not spent by the transaction
+    FundingUtxoNotSpent,
+    /// The funding UTXO is not locked to the subscription's `funder_pubkey`
+    FundingNotFromFunder,
+    /// The native value a mint, top-up or yield credit locks with the subscription does not cover
+    /// the supply it adds
+    InsufficientFunding { required: u64, funded: u64 },
+    /// Seats can only be changed on a subscription billed per seat
+    NotSeatBased,
+    /// A subscription billed per seat must keep at least one seat
+    ZeroSeats,
+    /// `amount_sats` is not the seat count times the price per seat
+    SeatAmountMismatch { expected: u64, actual: u64 },
+    /// The current cycle's payment is due and must be made first (before seat changes or
+    /// further usage charges within the cycle)
+    PaymentDue,
+    /// Minting must produce exactly one subscription NFT
+    UnexpectedNftCount(usize),
+    /// A payment outputs a subscription NFT whose identity is not among the spent ones
+    NftIdentityMismatch,
+    /// The NFT does not hold a recognized subscription state
+    InvalidNftState,
+    /// The NFT state carries a version tag this contract does not know
+    UnknownVersion,
+    /// Incoming and outgoing NFT states use different versions
+    VersionMismatch,
+    /// The legacy ticker does not encode a subscription id
+    InvalidTicker,
+    /// A subscription id is empty or uses characters other than ASCII letters, digits, `_`
+    /// and `-`
+    InvalidSubscriptionId,
+    /// A migration must be accompanied by the subscription terms in the witness
+    MissingMigrationTerms,
+    /// A legacy payment must be accompanied by the terms its ticker id was derived from
+    MissingLegacyTerms,
+    /// Split shares must name each recipient once and add up to `BASIS_POINTS`
+    InvalidSplits,
+    /// No subscription NFT among the transaction inputs
+    NoNftInInput,
+    /// No subscription NFT among the transaction outputs
+    NoNftInOutput,
+    /// The transaction carries neither the subscription NFT nor its token
+    EmptyTransaction,
+    /// The transaction moves subscription tokens without the NFT that manages them
+    TokensWithoutNft,
+    /// Legacy and full subscription states are mixed in a transaction other than a migration
+    MixedStateFormats,
+    /// A transition that does not grow the locked balance creates subscription tokens
+    TokensMinted { minted: u64 },
+    /// A withdrawal moves more than the merchant's accrued payments
+    WithdrawalExceedsAccrued { accrued: u64, withdrawn: u64 },
+    /// Token amounts could not be summed
+    InvalidTokenAmount,
+    /// The remaining supply (locked balance) increased
+    SupplyIncreased,
+    /// An amount differs from what the subscription terms require
+    AmountMismatch { expected: u64, actual: u64 },
+    /// A field that is fixed at creation was modified
+    ImmutableFieldChanged(&'static str),
+    /// The subscription must be active for this transition
+    SubscriptionInactive,
+    /// A payment must leave the subscription active
+    UnexpectedDeactivation,
+    /// The payment witness does not carry the current block height
+    MissingBlockHeight,
+    /// The recorded payment block differs from the witness block height
+    BlockHeightMismatch { expected: u32, actual: u32 },
+    /// The billing interval has not elapsed since the last payment
+    IntervalNotElapsed,
+    /// The payment arrived after the grace period and the subscription has lapsed
+    SubscriptionLapsed,
+    /// A lapse can only be recorded once the grace period of the next payment is over
+    SubscriptionNotLapsed,
+    /// No payment can be taken before the free trial ends
+    TrialNotEnded,
+    /// A party did not receive the tokens owed to them by the transition
+    Underpaid {
+        recipient: &'static str,
+        expected: u64,
+        actual: u64,
+    },
+    /// An amount in millisatoshis does not add up to a whole number of tokens
+    FractionalAmount { amount: u64 },
+    /// A new subscription must carry a subscription id
+    EmptySubscriptionId,
+    /// The transaction spends the same UTXO more than once
+    DuplicateInput,
+    /// A legacy state claims more remaining balance than was ever locked
+    BalanceExceedsLocked { remaining: u64, locked: u64 },
+    /// A cancellation, pause, resume or reversal came too soon after the last one
+    CooldownNotElapsed { until: u32 },
+    /// The merchant is not among the subscription's `allowed_merchants`
+    MerchantNotAllowed,
+    /// The state was written for another version of the contract
+    ContractVersionMismatch { expected: u32, actual: u32 },
+    /// Cancelling before the commitment ends requires paying the early termination fee
+    CommitmentNotMet { required: u32, paid: u32 },
+    /// Block height or amount arithmetic overflowed
+    Overflow,
+    /// An amount subtraction would have underflowed
+    Underflow,
+    /// The per-cycle amount must be greater than zero
+    ZeroAmount,
+    /// The per-cycle amount would produce a payment output below the dust threshold
+    BelowDustLimit { amount: u64, dust_limit: u64 },
+    /// The per-cycle amount or the locked balance exceeds the subscription's `max_amount_cap`
+    AmountAboveCap { amount: u64, cap: u64 },
+    /// The billing interval must be greater than zero
+    ZeroInterval,
+    /// The remaining balance cannot cover a billing cycle
+    InsufficientBalance { required: u64, available: u64 },
+    /// A top-up must strictly increase the remaining balance
+    BalanceNotIncreased,
+    /// A field that the transition must leave untouched was modified
+    UnexpectedFieldChange(&'static str),
+    /// Payments are not accepted while the subscription is paused
+    SubscriptionPaused,
+    /// The platform fee cannot be larger than the per-cycle amount
+    FeeExceedsAmount { fee: u64, amount: u64 },
+    /// A metered payment must report the units consumed in the witness
+    MissingUsage,
+    /// Slippage tolerance must not exceed `BASIS_POINTS`
+    InvalidSlippage,
+    /// A payment of a subscription priced in USD must carry an oracle rate in the witness
+    MissingOraclePrice,
+    /// The oracle rate does not reveal the link matching `oracle_auth_hash`
+    OraclePriceNotAuthorized,
+    /// The oracle rate was attested after the payment or more than `ORACLE_MAX_AGE_BLOCKS` before
+    StaleOraclePrice,
+    /// More units were reported than a metered cycle allows
+    UnitCapExceeded { max: u32, actual: u32 },
+    /// The usage is not charged for one of the subscription's beneficiaries
+    BeneficiaryNotListed,
+    /// The transaction does not spend an input locked to the charging beneficiary
+    BeneficiaryNotAuthorized,
+    /// More units were charged for a beneficiary than its per-cycle quota allows
+    BeneficiaryQuotaExceeded { quota: u32, actual: u32 },
+    /// Tier thresholds must be strictly increasing and tier amounts non-zero
+    InvalidTiers,
+    /// The coupon does not match the subscription or grants more than a full discount
+    InvalidCoupon,
+    /// The subscription's coupon has already been redeemed
+    CouponAlreadyUsed,
+    /// Every billing cycle allowed by `max_cycles` has already been paid
+    MaxCyclesReached,
+    /// The payment is recorded after the subscription's `expiry_block`
+    SubscriptionExpired,
+    /// The balance can only be refunded once the subscription's `expiry_block` has passed
+    SubscriptionNotExpired,
+    /// The payer can only change with an authorization matching `transfer_auth_hash`
+    TransferNotAuthorized,
+    /// The merchant can only change with the authorizations of both parties; names the party
+    /// whose authorization is missing or wrong
+    ReassignmentNotAuthorized(&'static str),
+    /// A consent revealed by the transition must be replaced by a fresh hash, or anyone could
+    /// replay it; names the party whose hash was kept
+    AuthorizationNotRotated(&'static str),
+    /// The plan can only change with the payer's authorization matching `plan_change_auth_hash`
+    PlanChangeNotAuthorized,
+    /// The payment witness does not reveal the authorization of the party the mode requires
+    PaymentNotAuthorized,
+    /// The payment witness does not reveal the fulfillment matching `escrow_release_hash`
+    FulfillmentNotProven,
+    /// A payment can only be reversed within `dispute_window_blocks` of being made
+    OutsideDisputeWindow,
+    /// A reversal witness must carry the block of the payment before the reversed one
+    MissingPriorPaymentBlock,
+    /// The last payment recorded no charge, so there is nothing a reversal could refund
+    NoChargeToReverse,
+    /// The timelock script is not the spent NFT output's P2WSH script, or does not start with a
+    /// block-based relative timelock
+    InvalidTimelockScript,
+    /// The relative timelock of the spent NFT output is shorter than the billing interval
+    TimelockTooShort { required: u32, actual: u32 },
+    /// Tokens still locked in the subscription were moved away from the subscription NFT
+    TokensNotLocked { expected: u64, actual: u64 },
+    /// An outgoing subscription in a batch has no single incoming subscription of its id
+    UnpairedSubscription,
+    /// The contract takes no public input
+    UnexpectedPublicInput,
+    /// The app is neither the subscription NFT nor its token
+    UnknownAppTag(char),
+    /// A transition must record exactly the next nonce, so it cannot be replayed
+    NonceNotIncremented { expected: u64, actual: u64 },
+    /// The payment is not made in the token the subscription is denominated in
+    WrongPaymentToken,
+    /// The transaction carries a token of an app other than the subscription's own token and
+    /// the token it is denominated in
+    ForeignToken,
+    /// A failed payment attempt was recorded for a subscription without a retry cap
+    DunningDisabled,
+    /// A payer or merchant key is not a hex-encoded compressed public key
+    InvalidPubkey,
+    /// A merchant address has a segwit prefix but is not a valid bech32/bech32m address
+    InvalidRecipient,
+    /// The remaining balance does not cover exactly the declared prepaid cycles
+    PrepaidBalanceMismatch { expected: u64, actual: u64 },
+    /// The minimum charge of a band cannot exceed its maximum
+    InvalidAmountBounds { min: u64, max: u64 },
+    /// The charge falls outside the subscription's amount band
+    AmountOutOfRange { min: u64, max: u64, actual: u64 },
+}
+
+pub fn app_contract(app: &App, tx: &Transaction, x: &Data, w: &Data) -> bool {
+    let empty = Data::empty();
+    assert_eq!(x, &empty);
+    let result = match app.tag {
+        NFT => nft_contract_satisfied(app, tx, w),
+        TOKEN => token_contract_satisfied(app, tx, w),
+        _ => unreachable!(),
+    };
+    // Single point where validation errors are reported and turned into a verdict
+    #[cfg(feature = "tracing")]
+    if let Err(err) = &result {
+        tracing::warn!(app_tag = %app.tag, error = ?err, "subscription contract not satisfied");
+    }
+    result.is_ok()
+}
+
+/// Run the contract like `app_contract`, but return the first failing check
+/// For client tooling simulating a transaction before broadcasting it; never used for consensus
+pub fn dry_run(app: &App, tx: &Transaction, x: &Data, w: &Data) -> Result<(), ValidationError> {
+    ensure!(x == &Data::empty(), ValidationError::UnexpectedPublicInput);
+    match app.tag {
+        NFT => nft_contract_satisfied(app, tx, w),
+        TOKEN => token_contract_satisfied(app, tx, w),
+        tag => Err(ValidationError::UnknownAppTag(tag)),
+    }
+}
+
+/// Lifecycle step a subscription transaction performs, for off-chain indexers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionEvent {
+    /// A new subscription NFT was minted
+    Created,
+    /// One billing cycle was paid, releasing `amount` from the locked balance
+    Payment { amount: u64 },
+    /// The locked balance grew by `amount`
+    TopUp { amount: u64 },
+    /// Billing was suspended
+    Paused,
+    /// Billing was resumed after a pause
+    Resumed,
+    /// A payment attempt failed, making it the `failed_attempts`-th in a row
+    PaymentFailed { failed_attempts: u8 },
+    /// The subscription was deactivated and its balance released
+    Cancelled,
+    /// The subscription was deactivated for missing its payment, keeping its balance locked
+    Lapsed,
+    /// The merchant withdrew `amount` of accrued payments
+    Withdrawn { amount: u64 },
+}
+
+/// Categorize a transaction of the subscription `app` (NFT or token) by the transition it makes
+/// Read-only and independent of validation: the shape is recognized the way the contract
+/// dispatches it, but the transaction is not checked; None if it is not a lifecycle step
+pub fn classify_tx(app: &App, tx: &Transaction) -> Option<SubscriptionEvent> {
+    let nft_app = &sibling_app(app, NFT);
+    let outgoing_state = find_subscription_state(nft_app, tx.outs.iter())?;
+    let Some(incoming_state) = find_subscription_state(nft_app, tx.ins.iter().map(|(_, v)| v))
+    else {
+        return Some(SubscriptionEvent::Created);
+    };
+
+    let released = incoming_state
+        .remaining_supply()
+        .checked_sub(outgoing_state.remaining_supply());
+    let (Some(in_state), Some(out_state)) =
+        (incoming_state.subscription(), outgoing_state.subscription())
+    else {
+        // Legacy states only record payments, as a decrease of the remaining supply
+        return match (incoming_state, outgoing_state) {
+            (SubscriptionStateV::V1(_), SubscriptionStateV::V1(_)) => {
+                released.map(|amount| SubscriptionEvent::Payment { amount })
+            }
+            _ => None,
+        };
+    };
+    if is_ownership_transfer(in_state, out_state) {
+        return None;
+    }
+    if is_payment_retry(in_state, out_state) {
+        return Some(SubscriptionEvent::PaymentFailed {
+            failed_attempts: out_state.failed_attempts,
+        });
+    }
+    if is_merchant_withdrawal(in_state, out_state) {
+        let amount = in_state.merchant_withdrawable_sats - out_state.merchant_withdrawable_sats;
+        return Some(SubscriptionEvent::Withdrawn { amount });
+    }
+    if is_lapse(in_state, out_state) {
+        return Some(SubscriptionEvent::Lapsed);
+    }
+    if is_cancellation(in_state, out_state) {
+        return Some(SubscriptionEvent::Cancelled);
+    }
+    if is_pause_toggle(in_state, out_state) {
+        return Some(if out_state.paused {
+            SubscriptionEvent::Paused
+        } else {
+            SubscriptionEvent::Resumed
+        });
+    }
+    if is_top_up(in_state, out_state) {
+        let amount = out_state.remaining_balance - in_state.remaining_balance;
+        return Some(SubscriptionEvent::TopUp { amount });
+    }
+    if out_state.cycles_paid > in_state.cycles_paid {
+        return released.map(|amount| SubscriptionEvent::Payment { amount });
+    }
+    None
+}
+
+// NFT contract validation
+fn nft_contract_satisfied(app: &App, tx: &Transaction, w: &Data) -> Result<(), ValidationError> {
+    let token_app = &sibling_app(app, TOKEN);
+    // The NFT carries the subscription state, so any state transition the token contract
+    // accepts (mint, payment, cancellation) is also acceptable for the NFT.
+    can_mint_nft(app, tx, w).or_else(|_| token_contract_satisfied(token_app, tx, w))
+}
+
+fn can_mint_nft(nft_app: &App, tx: &Transaction, w: &Data) -> Result<(), ValidationError> {
+    // `w` is either the funding UTXO id, or a MintWitness selecting the terms-derived or
+    // id-derived identity.
+    let (w_str, mint) = match w.value::<String>() {
+        Ok(w_str) => (w_str, None),
+        Err(_) => {
+            let mint: MintWitness = w.value().map_err(|_| ValidationError::InvalidWitness)?;
+            (mint.funding_utxo.clone(), Some(mint))
+        }
+    };
+    ensure_distinct_inputs(tx)?;
+    ensure_no_foreign_tokens(&sibling_app(nft_app, TOKEN), nft_app, tx)?;
+    // can mint exactly one NFT.
+    let nft_count = count_nft_charms(nft_app, tx.outs.iter());
+    ensure!(
+        nft_count == 1,
+        ValidationError::UnexpectedNftCount(nft_count)
+    );
+    // the NFT has the correct structure.
+    let nft_charm = charm_values(nft_app, tx.outs.iter())
+        .next()
+        .ok_or(ValidationError::UnexpectedNftCount(0))?;
+    let state = SubscriptionStateV::parse(nft_charm)?;
+    if let Some(subscription) = state.subscription() {
+        ensure_contract_version(subscription)?;
+    }
+
+    // can only mint an NFT with this contract if the hash of `w` is the identity of the NFT,
+    // using the algorithm recorded in the NFT state, or if the identity is derived from the
+    // subscription terms or the subscription id and `w` when the witness asks for it.
+    let identity = match mint {
+        // Each id is bound to the one UTXO that minted it, so no two NFTs can claim it
+        Some(MintWitness {
+            subscription_id: Some(subscription_id),
+            ..
+        }) => {
+            ensure!(
+                state.subscription_id().as_deref() == Some(subscription_id.as_str()),
+                ValidationError::SubscriptionIdMismatch
+            );
+            derive_subscription_id_identity(&subscription_id, &w_str)
+        }
+        Some(_) => {
+            let terms = state
+                .subscription()
+                .ok_or(ValidationError::InvalidNftState)?;
+            derive_subscription_identity(
+                &terms.payer_pubkey,
+                &terms.merchant_pubkey,
+                terms.amount_sats,
+                terms.billing_interval_blocks,
+                &w_str,
+            )
+        }
+        None => hash_with(state.hash_algo(), &w_str),
+    };
+    ensure!(
+        ct_eq(&identity, &nft_app.identity),
+        ValidationError::IdentityMismatch
+    );
+
+    // can only mint an NFT with this contract if spending a UTXO with the same ID as passed in `w`.
+    let w_utxo_id = UtxoId::from_str(&w_str).map_err(|_| ValidationError::InvalidWitness)?;
+    let funding_input = tx
+        .ins
+        .iter()
+        .position(|(utxo_id, _)| utxo_id == &w_utxo_id)
+        .ok_or(ValidationError::FundingUtxoNotSpent)?;
+
+    if let Some(subscription) = state.subscription() {
+        validate_pubkey(&subscription.payer_pubkey)?;
+        Recipient::parse(&subscription.merchant_pubkey)?;
+        ensure!(
+            !subscription.subscription_id.is_empty(),
+            ValidationError::EmptySubscriptionId
+        );
+        subscription.validate_funder()?;
+        ensure_funded_by_funder(subscription, tx, funding_input)?;
+        ensure_setup_fee_paid(subscription, tx)?;
+        subscription.validate_dust_limit(DUST_LIMIT_SATS)?;
+        subscription.validate_slippage()?;
+        subscription.validate_merchant_allowed()?;
+        subscription.validate_amount_cap()?;
+        subscription.validate_seats()?;
+        subscription.validate_prepaid_cycles()?;
+    }
+    match state {
+        SubscriptionStateV::V3(state) => state.validate_splits()?,
+        SubscriptionStateV::V5(state) => state.validate_tiers()?,
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Domain-separation prefix digested before every identity or coupon hash, so identities
+/// cannot collide with those another charm app derives from the same UTXO id.
+/// Identities derived before the prefix was introduced hashed the bare UTXO id; such
+/// NFTs were minted under that rule and are not re-checked, but new app ids must be
+/// derived as `hash("charmpay-v1:" || utxo_id)`.
+pub const DOMAIN_TAG: &[u8] = b"charmpay-v1:";
+
+pub(crate) fn hash(data: &str) -> B32 {
+    hash_bytes(data.as_bytes())
+}
+
+/// Hash binary `data`, such as a serialized subscription state, the way [`hash`] hashes its
+/// UTF-8 encoding
+pub fn hash_bytes(data: &[u8]) -> B32 {
+    hash_bytes_with(HashAlgo::Sha256, data)
+}
+
+/// Compare two hashes in constant time, so checking a revealed preimage or an identity does
+/// not leak through timing how many leading bytes matched
+pub fn ct_eq(a: &B32, b: &B32) -> bool {
+    let diff = a.0.iter().zip(&b.0).fold(0u8, |acc, (x, y)| acc | (x ^ y));
+    std::hint::black_box(diff) == 0
+}
+
+/// NFT identity bound to the subscription terms and the UTXO funding it, so clients can
+/// precompute it and subscriptions funded by the same UTXO get distinct identities
+pub fn derive_subscription_identity(
+    payer_pubkey: &str,
+    merchant_pubkey: &str,
+    amount_sats: u64,
+    billing_interval_blocks: u32,
+    funding_utxo: &str,
+) -> B32 {
+    hash(&format!(
+        "{}:{}:{}:{}:{}",
+        payer_pubkey, merchant_pubkey, amount_sats, billing_interval_blocks, funding_utxo
+    ))
+}
+
+/// NFT identity bound to a subscription id and the UTXO funding it, so each id is tied to
+/// a single mint
+pub fn derive_subscription_id_identity(subscription_id: &str, funding_utxo: &str) -> B32 {
+    hash(&format!("{}:{}", subscription_id, funding_utxo))
+}
+
+/// Hash `data` into an NFT identity with the given algorithm
+pub fn hash_with(algo: HashAlgo, data: &str) -> B32 {
+    hash_bytes_with(algo, data.as_bytes())
+}
+
+/// Hash binary `data` into an NFT identity with the given algorithm
+pub fn hash_bytes_with(algo: HashAlgo, data: &[u8]) -> B32 {
+    match algo {
+        HashAlgo::Sha256 => B32(Sha256::new()
+            .chain_update(DOMAIN_TAG)
+            .chain_update(data)
+            .finalize()
+            .into()),
+        HashAlgo::Blake3 => B32(blake3::Hasher::new()
+            .update(DOMAIN_TAG)
+            .update(data)
+            .finalize()
+            .into()),
+        HashAlgo::Keccak256 => B32(Keccak256::new()
+            .chain_update(DOMAIN_TAG)
+            .chain_update(data)
+            .finalize()
+            .into()),
+    }
+}
+
+// Subscription payment contract logic
+fn token_contract_satisfied(
+    token_app: &App,
+    tx: &Transaction,
+    w: &Data,
+) -> Result<(), ValidationError> {
+    let witness: SubscriptionWitness = w.value().unwrap_or_default();
+    // Built once here and borrowed by every check below
+    let nft_app = &sibling_app(token_app, NFT);
+    ensure_distinct_inputs(tx)?;
+    ensure_contract_versions(nft_app, tx)?;
+    let shape = tx_shape(token_app, nft_app, tx)?;
+    ensure_no_foreign_tokens(token_app, nft_app, tx)?;
+    // Only a mint creates the NFT from nothing; every other transaction carries it over
+    if shape == TxShape::Mint {
+        return can_mint_token(token_app, nft_app, tx);
+    }
+    ensure_single_state_format(nft_app, tx)?;
+    // A batch pays every subscription it spends, checking exactly the yields they mint
+    if nft_output_count(nft_app, tx) > 1 {
+        return validate_batch_payment(token_app, nft_app, tx, &witness);
+    }
+
+    let states = subscription_states(nft_app, tx);
+    // Tokens are only minted to fund a larger balance (or by a legacy state's supply), or as
+    // the yield a payment credits, which the payment checks exactly: no other transition can
+    // pay out of the balance and inflate the supply at the same time
+    let mints = match &states {
+        Some((SubscriptionStateV::V1(_), SubscriptionStateV::V1(_))) => true,
+        Some((incoming_state, outgoing_state)) => {
+            outgoing_state.remaining_supply() > incoming_state.remaining_supply()
+                || earns_yield(incoming_state, outgoing_state)
+        }
+        None => false,
+    };
+    if !mints {
+        ensure_no_tokens_minted(token_app, tx)?;
+    }
+
+    let nonces = states
+        .as_ref()
+        .and_then(|(incoming_state, outgoing_state)| {
+            Some((
+                incoming_state.subscription()?,
+                outgoing_state.subscription()?,
+            ))
+        });
+    validate_transition(token_app, nft_app, tx, &witness, states.as_ref())?;
+    // Whatever its kind, a valid transition moves the nonce forward by exactly one,
+    // so the same transition can never be applied twice
+    if let Some((in_state, out_state)) = nonces {
+        ensure_nonce_incremented(in_state, out_state)?;
+    }
+    Ok(())
+}
+
+// Each UTXO is spent once: an input listed twice would have its tokens counted twice while
+// the NFT it carries is only read once
+fn ensure_distinct_inputs(tx: &Transaction) -> Result<(), ValidationError> {
+    let mut seen = BTreeSet::new();
+    ensure!(
+        tx.ins.iter().all(|(utxo_id, _)| seen.insert(utxo_id)),
+        ValidationError::DuplicateInput
+    );
+    Ok(())
+}
+
+// A payment of a subscription whose balance earns yield
+fn earns_yield(incoming_state: &SubscriptionStateV, outgoing_state: &SubscriptionStateV) -> bool {
+    match (incoming_state.subscription(), outgoing_state.subscription()) {
+        (Some(in_state), Some(out_state)) => {
+            in_state.annual_yield_bps > 0 && out_state.cycles_paid > in_state.cycles_paid
+        }
+        _ => false,
+    }
+}
+
+// Every token in the transaction is the subscription's own token or one a subscription state
+// declares as its denomination, so a stray token of another app cannot confuse the sums
+fn ensure_no_foreign_tokens(
+    token_app: &App,
+    nft_app: &App,
+    tx: &Transaction,
+) -> Result<(), ValidationError> {
+    let strings_of_charms = || tx.ins.iter().map(|(_, v)| v).chain(tx.outs.iter());
+    let denominations = charm_values(nft_app, strings_of_charms())
+        .filter_map(|data| SubscriptionStateV::parse(data).ok())
+        .filter_map(|state| state.subscription()?.denomination_token.clone())
+        .collect::<Vec<_>>();
+    ensure!(
+        strings_of_charms()
+            .flat_map(|charms| charms.keys())
+            .filter(|app| app.tag == TOKEN)
+            .all(|app| app == token_app || denominations.contains(&app.identity)),
+        ValidationError::ForeignToken
+    );
+    Ok(())
+}
+
+fn ensure_no_tokens_minted(token_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    let (input_token_amount, output_token_amount) = token_totals(token_app, tx)?;
+    ensure!(
+        output_token_amount <= input_token_amount,
+        ValidationError::TokensMinted {
+            minted: output_token_amount - input_token_amount
+        }
+    );
+    Ok(())
+}
+
+// Where a transaction carries the subscription NFT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TxShape {
+    // The NFT appears in the outputs only
+    Mint,
+    // The NFT is spent and output again: a payment, top-up, cancellation or other transition
+    Transition,
+}
+
+// Classify a transaction by where the subscription NFT appears, rejecting the shapes no
+// subscription transaction has
+fn tx_shape(token_app: &App, nft_app: &App, tx: &Transaction) -> Result<TxShape, ValidationError> {
+    let inputs = || tx.ins.iter().map(|(_, charms)| charms);
+    let spends_nft = charm_values(nft_app, inputs()).next().is_some();
+    let outputs_nft = charm_values(nft_app, tx.outs.iter()).next().is_some();
+    match (spends_nft, outputs_nft) {
+        (false, true) => Ok(TxShape::Mint),
+        (true, true) => Ok(TxShape::Transition),
+        (true, false) => {
+            // The spent state may have been written under another subscription's identity
+            ensure_nft_identity_conserved(nft_app, tx)?;
+            Err(ValidationError::NoNftInOutput)
+        }
+        (false, false) => {
+            let moves_tokens = charm_values(token_app, inputs().chain(&tx.outs))
+                .next()
+                .is_some();
+            Err(if moves_tokens {
+                ValidationError::TokensWithoutNft
+            } else {
+                ValidationError::EmptyTransaction
+            })
+        }
+    }
+}
+
+// Dispatch a single-subscription transaction to the validator for its kind of transition
+fn validate_transition(
+    token_app: &App,
+    nft_app: &App,
+    tx: &Transaction,
+    witness: &SubscriptionWitness,
+    states: Option<&(SubscriptionStateV, SubscriptionStateV)>,
+) -> Result<(), ValidationError> {
+    // Split, metered and tiered subscriptions go through the same transitions as their
+    // underlying subscription, as long as their own terms and usage are left unchanged; any
+    // other change of theirs can only be a payment
+    let transition = match states {
+        Some((SubscriptionStateV::V2(in_state), SubscriptionStateV::V2(out_state))) => {
+            Some((in_state, out_state))
+        }
+        Some((SubscriptionStateV::V3(in_split), SubscriptionStateV::V3(out_split)))
+            if in_split.splits == out_split.splits =>
+        {
+            Some((&in_split.subscription, &out_split.subscription))
+        }
+        Some((SubscriptionStateV::V4(in_metered), SubscriptionStateV::V4(out_metered)))
+            if *out_metered
+                == (MeteredSubscriptionState {
+                    subscription: out_metered.subscription.clone(),
+                    ..in_metered.clone()
+                }) =>
+        {
+            Some((&in_metered.subscription, &out_metered.subscription))
+        }
+        Some((SubscriptionStateV::V5(in_tiered), SubscriptionStateV::V5(out_tiered)))
+            if in_tiered.tiers == out_tiered.tiers =>
+        {
+            Some((&in_tiered.subscription, &out_tiered.subscription))
+        }
+        // Upgrading a legacy state is the only transition allowed to change the version
+        Some((SubscriptionStateV::V1(legacy), SubscriptionStateV::V2(out_state))) => {
+            let scan = &scan_tx(token_app, nft_app, tx)?;
+            return can_migrate_subscription(legacy, out_state, scan, witness);
+        }
+        _ => None,
+    };
+
+    // Cancellation, top-up and payment are mutually exclusive: a transition that deactivates
+    // the subscription without counting a cycle is only ever validated as a cancellation
+    // (or as the failed attempt that reaches the retry cap), and one that grows the balance
+    // only as a top-up, never as a payment.
+    if let Some((in_state, out_state)) = transition {
+        let scan = &scan_tx(token_app, nft_app, tx)?;
+        if is_ownership_transfer(in_state, out_state) {
+            return validate_ownership_transfer(in_state, out_state, scan, witness);
+        }
+        if is_merchant_reassignment(in_state, out_state) {
+            return validate_merchant_reassignment(in_state, out_state, scan, witness);
+        }
+        if is_payment_retry(in_state, out_state) {
+            return validate_payment_retry(in_state, out_state, scan, witness);
+        }
+        if is_seat_change(in_state, out_state) {
+            return validate_seat_change(in_state, out_state, token_app, scan, witness);
+        }
+        if is_plan_change(in_state, out_state) {
+            return validate_plan_change(in_state, out_state, token_app, scan, witness);
+        }
+        if is_payment_reversal(in_state, out_state) {
+            return validate_payment_reversal(in_state, out_state, token_app, scan, witness);
+        }
+        if is_merchant_withdrawal(in_state, out_state) {
+            return validate_merchant_withdrawal(in_state, out_state, token_app, scan);
+        }
+        if is_lapse(in_state, out_state) {
+            return validate_mark_lapsed(in_state, out_state, token_app, scan, witness);
+        }
+        if is_expired_refund(in_state, out_state, witness) {
+            return validate_expired_refund(in_state, out_state, token_app, scan, witness);
+        }
+        if is_cancellation(in_state, out_state) {
+            return validate_subscription_cancellation(
+                in_state, out_state, token_app, scan, witness,
+            );
+        }
+        if is_pause_toggle(in_state, out_state) {
+            return validate_subscription_pause_resume(in_state, out_state, witness);
+        }
+        if is_top_up(in_state, out_state) {
+            return can_top_up_subscription(in_state, out_state, token_app, scan);
+        }
+    }
+    can_mint_token(token_app, nft_app, tx)
+        .or_else(|_| can_execute_subscription_payment(token_app, nft_app, tx, witness))
+}
+
+fn ensure_nonce_incremented(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> Result<(), ValidationError> {
+    let expected = in_state.next_nonce()?;
+    ensure!(
+        out_state.nonce == expected,
+        ValidationError::NonceNotIncremented {
+            expected,
+            actual: out_state.nonce
+        }
+    );
+    Ok(())
+}
+
+// Extract the incoming and outgoing subscription states of `nft_app`, if both exist
+fn subscription_states(
+    nft_app: &App,
+    tx: &Transaction,
+) -> Option<(SubscriptionStateV, SubscriptionStateV)> {
+    let incoming_state = find_subscription_state(nft_app, tx.ins.iter().map(|(_, v)| v))?;
+    let outgoing_state = find_subscription_state(nft_app, tx.outs.iter())?;
+    Some((incoming_state, outgoing_state))
+}
+
+// A subscription transaction carries at most one subscription NFT in its outputs,
+// so a second NFT cannot be smuggled alongside a valid transition
+// (several payments settled together are validated as a batch instead)
+fn ensure_single_nft_output(nft_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    let nft_count = nft_output_count(nft_app, tx);
+    ensure!(
+        nft_count <= 1,
+        ValidationError::UnexpectedNftCount(nft_count)
+    );
+    Ok(())
+}
+
+fn nft_output_count(nft_app: &App, tx: &Transaction) -> usize {
+    count_nft_charms(nft_app, tx.outs.iter())
+}
+
+/// Number of charms of the subscription NFT `nft_app` in the given strings of charms
+pub fn count_nft_charms<'a>(
+    nft_app: &App,
+    strings_of_charms: impl Iterator<Item = &'a Charms>,
+) -> usize {
+    strings_of_charms
+        .filter(|charms| charms.contains_key(nft_app))
+        .count()
+}
+
+/// Number of charms of the subscription token `token_app` in the given strings of charms
+pub fn count_token_charms<'a>(
+    token_app: &App,
+    strings_of_charms: impl Iterator<Item = &'a Charms>,
+) -> usize {
+    strings_of_charms
+        .filter(|charms| charms.contains_key(token_app))
+        .count()
+}
+
+// The app sharing `app`'s identity and verification key under `tag`: this is how the
+// subscription NFT and its token find each other
+fn sibling_app(app: &App, tag: char) -> App {
+    App {
+        tag,
+        identity: app.identity.clone(),
+        vk: app.vk.clone(),
+    }
+}
+
+// First subscription state of `nft_app` found in the given strings of charms
+fn find_subscription_state<'a>(
+    nft_app: &'a App,
+    strings_of_charms: impl Iterator<Item = &'a Charms>,
+) -> Option<SubscriptionStateV> {
+    charm_values(nft_app, strings_of_charms).find_map(|data| SubscriptionStateV::parse(data).ok())
+}
+
+// Subscription states, NFT output count and token sums of a transaction
+struct TxScan<'a> {
+    tx: &'a Transaction,
+    nft_app: &'a App,
+    // First parseable subscription state among the inputs and among the outputs
+    incoming_state: Option<SubscriptionStateV>,
+    outgoing_state: Option<SubscriptionStateV>,
+    nft_outputs: usize,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+// Walk the inputs and outputs of `tx` once, collecting what the payment validators read
+fn scan_tx<'a>(
+    token_app: &App,
+    nft_app: &'a App,
+    tx: &'a Transaction,
+) -> Result<TxScan<'a>, ValidationError> {
+    let mut scan = TxScan {
+        tx,
+        nft_app,
+        incoming_state: None,
+        outgoing_state: None,
+        nft_outputs: 0,
+        input_tokens: 0,
+        output_tokens: 0,
+    };
+    for (_, charms) in &tx.ins {
+        if scan.incoming_state.is_none() {
+            scan.incoming_state = charms
+                .get(nft_app)
+                .and_then(|data| SubscriptionStateV::parse(data).ok());
+        }
+        scan.input_tokens = add_token_amount(scan.input_tokens, charms.get(token_app))?;
+    }
+    for charms in &tx.outs {
+        if let Some(data) = charms.get(nft_app) {
+            scan.nft_outputs += 1;
+            if scan.outgoing_state.is_none() {
+                scan.outgoing_state = SubscriptionStateV::parse(data).ok();
+            }
+        }
+        scan.output_tokens = add_token_amount(scan.output_tokens, charms.get(token_app))?;
+    }
+    Ok(scan)
+}
+
+// Tokens are moved between outputs, never minted or burned
+fn ensure_tokens_conserved(scan: &TxScan) -> Result<(), ValidationError> {
+    ensure!(
+        scan.output_tokens == scan.input_tokens,
+        ValidationError::AmountMismatch {
+            expected: scan.input_tokens,
+            actual: scan.output_tokens
+        }
+    );
+    Ok(())
+}
+
+// A full state is either untagged, written before versioning, or tagged with this contract's
+// version: one written for another version may mean something else by the same fields
+fn ensure_contract_version(state: &MinimalSubscriptionState) -> Result<(), ValidationError> {
+    ensure!(
+        state.contract_version == 0 || state.contract_version == CONTRACT_VERSION,
+        ValidationError::ContractVersionMismatch {
+            expected: CONTRACT_VERSION,
+            actual: state.contract_version
+        }
+    );
+    Ok(())
+}
+
+fn ensure_contract_versions(nft_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    let strings_of_charms = tx.ins.iter().map(|(_, v)| v).chain(tx.outs.iter());
+    charm_values(nft_app, strings_of_charms)
+        .filter_map(|data| SubscriptionStateV::parse(data).ok())
+        .try_for_each(|state| match state.subscription() {
+            Some(subscription) => ensure_contract_version(subscription),
+            None => Ok(()),
+        })
+}
+
+// Whether the subscription states among the given strings of charms include legacy and full ones
+fn state_formats<'a>(
+    nft_app: &'a App,
+    strings_of_charms: impl Iterator<Item = &'a Charms>,
+) -> (bool, bool) {
+    charm_values(nft_app, strings_of_charms)
+        .filter_map(|data| SubscriptionStateV::parse(data).ok())
+        .fold((false, false), |(legacy, full), state| match state {
+            SubscriptionStateV::V1(_) => (true, full),
+            _ => (legacy, true),
+        })
+}
+
+// A transaction carries a single representation of the subscription state: legacy and full
+// states are never mixed on the same side, which would leave the validators picking one and
+// ignoring the other, and the only change of format is the migration from legacy to full
+fn ensure_single_state_format(nft_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    let (legacy_in, full_in) = state_formats(nft_app, tx.ins.iter().map(|(_, v)| v));
+    let (legacy_out, full_out) = state_formats(nft_app, tx.outs.iter());
+    let mixed = (legacy_in && full_in) || (legacy_out && full_out);
+    let downgraded = full_in && legacy_out;
+    ensure!(!mixed && !downgraded, ValidationError::MixedStateFormats);
+    Ok(())
+}
+
+// A payment carries its subscription NFT over, so the state it outputs cannot be taken from
+// another subscription of this contract: every such NFT in the outputs has an identity that
+// the inputs spend
+fn ensure_nft_identity_conserved(nft_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    let contract_nfts = |charms: &'_ Charms| {
+        charms
+            .keys()
+            .filter(|app| app.tag == nft_app.tag && app.vk == nft_app.vk)
+            .map(|app| app.identity.clone())
+            .collect::<Vec<_>>()
+    };
+    let spent = tx
+        .ins
+        .iter()
+        .flat_map(|(_, charms)| contract_nfts(charms))
+        .collect::<Vec<_>>();
+    ensure!(
+        tx.outs
+            .iter()
+            .flat_map(contract_nfts)
+            .all(|identity| spent.contains(&identity)),
+        ValidationError::NftIdentityMismatch
+    );
+    Ok(())
+}
+
+fn add_token_amount(total: u64, amount: Option<&Data>) -> Result<u64, ValidationError> {
+    let Some(amount) = amount else {
+        return Ok(total);
+    };
+    let amount = amount
+        .value::<u64>()
+        .map_err(|_| ValidationError::InvalidTokenAmount)?;
+    add_or_reject(total, amount)
+}
+
+// A cancellation moves an active subscription to inactive without paying a cycle
+// (the final payment of a fixed-term subscription also deactivates it, but counts a cycle);
+// a subscription deactivated by failed attempts or a lapse can still be cancelled to release
+// its balance
+fn is_cancellation(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    (in_state.is_active || in_state.is_dunning_exhausted() || is_lapse_recorded(in_state))
+        && !out_state.is_active
+        && in_state.cycles_paid == out_state.cycles_paid
+}
+
+// A lapse deactivates an active subscription while its balance stays locked
+fn is_lapse(in_state: &MinimalSubscriptionState, out_state: &MinimalSubscriptionState) -> bool {
+    in_state.is_active
+        && !out_state.is_active
+        && in_state.cycles_paid == out_state.cycles_paid
+        && in_state.remaining_balance > 0
+        && out_state.remaining_balance == in_state.remaining_balance
+}
+
+// A subscription deactivated by a recorded lapse still holds its balance until cancelled
+fn is_lapse_recorded(state: &MinimalSubscriptionState) -> bool {
+    state.lapsed && !state.is_active
+}
+
+// A merchant withdrawal releases accrued payments from a subscription that stays as it was
+fn is_merchant_withdrawal(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    out_state.merchant_withdrawable_sats < in_state.merchant_withdrawable_sats
+        && out_state.is_active == in_state.is_active
+        && out_state.cycles_paid == in_state.cycles_paid
+}
+
+// An expired refund deactivates a subscription with an expiry without paying a cycle, once
+// the expiry has passed or when it could not be cancelled anyway
+fn is_expired_refund(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> bool {
+    let expired = witness
+        .current_block
+        .is_some_and(|block| block > in_state.expiry_block);
+    in_state.expiry_block != 0
+        && !out_state.is_active
+        && in_state.cycles_paid == out_state.cycles_paid
+        && (expired || !is_cancellation(in_state, out_state))
+}
+
+// A payment reversal takes back paid cycles
+fn is_payment_reversal(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    out_state.cycles_paid < in_state.cycles_paid
+}
+
+// A seat change adjusts the number of seats billed
+fn is_seat_change(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.seats != out_state.seats
+}
+
+// A plan change reprices a subscription the payer can authorize, keeping its seat count
+fn is_plan_change(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.plan_change_auth_hash.is_some()
+        && in_state.amount_sats != out_state.amount_sats
+        && in_state.seats == out_state.seats
+}
+
+// A payment retry records a failed attempt by changing the failed attempts counter without
+// paying a cycle (a successful payment changes it too, resetting it to zero)
+fn is_payment_retry(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.failed_attempts != out_state.failed_attempts
+        && in_state.cycles_paid == out_state.cycles_paid
+}
+
+// An ownership transfer reassigns the payer
+fn is_ownership_transfer(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.payer_pubkey != out_state.payer_pubkey
+}
+
+// A merchant reassignment changes the recipient of the payments; without both parties'
+// authorization hashes, the merchant is simply immutable
+fn is_merchant_reassignment(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.payer_reassign_auth_hash.is_some()
+        && in_state.merchant_reassign_auth_hash.is_some()
+        && in_state.merchant_pubkey != out_state.merchant_pubkey
+}
+
+// A pause or resume flips the paused flag
+fn is_pause_toggle(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> bool {
+    in_state.paused != out_state.paused
+}
+
+// A top-up grows the remaining balance of an active subscription
+fn is_top_up(in_state: &MinimalSubscriptionState, out_state: &MinimalSubscriptionState) -> bool {
+    in_state.is_active
+        && out_state.is_active
+        && out_state.remaining_balance > in_state.remaining_balance
+}
+
+// Sum the tokens of `token_app` on the input and output side of `tx`
+fn token_totals(token_app: &App, tx: &Transaction) -> Result<(u64, u64), ValidationError> {
+    let input_token_amount = sum_token_amount(token_app, tx.ins.iter().map(|(_, v)| v))
+        .map_err(|_| ValidationError::InvalidTokenAmount)?;
+    let output_token_amount = sum_token_amount(token_app, tx.outs.iter())
+        .map_err(|_| ValidationError::InvalidTokenAmount)?;
+    Ok((input_token_amount, output_token_amount))
+}
+
+// Checked balance and token arithmetic, rejecting the transaction instead of wrapping: sums and
+// products that overflow fail with `Overflow`, differences that underflow with `Underflow`
+mod arith {
+    use super::ValidationError;
+
+    pub(crate) fn add_or_reject(a: u64, b: u64) -> Result<u64, ValidationError> {
+        a.checked_add(b).ok_or(ValidationError::Overflow)
+    }
+
+    pub(crate) fn sub_or_reject(a: u64, b: u64) -> Result<u64, ValidationError> {
+        a.checked_sub(b).ok_or(ValidationError::Underflow)
+    }
+
+    pub(crate) fn mul_or_reject(a: u64, b: u64) -> Result<u64, ValidationError> {
+        a.checked_mul(b).ok_or(ValidationError::Overflow)
+    }
+}
+
+/// Length in bytes of a compressed secp256k1 public key
+pub const COMPRESSED_PUBKEY_LEN: usize = 33;
+
+/// Check that `pubkey` is a hex-encoded compressed public key (33 bytes, 66 hex characters)
+pub fn validate_pubkey(pubkey: &str) -> Result<(), ValidationError> {
+    let bytes = hex::decode(pubkey).map_err(|_| ValidationError::InvalidPubkey)?;
+    ensure!(
+        bytes.len() == COMPRESSED_PUBKEY_LEN,
+        ValidationError::InvalidPubkey
+    );
+    Ok(())
+}
+
+// P2WPKH output script (`OP_0 <HASH160(pubkey)>`) paying a hex-encoded compressed pubkey
+fn p2wpkh_script(pubkey: &str) -> Option<Vec<u8>> {
+    let pubkey = hex::decode(pubkey).ok()?;
+    let pubkey_hash = Ripemd160::digest(Sha256::digest(pubkey));
+    let mut script = vec![0x00, 0x14];
+    script.extend_from_slice(&pubkey_hash);
+    Some(script)
+}
+
+// BIP-68 flags of a relative timelock: disabled, and measured in time instead of blocks
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_LOCKTIME_MASK: u32 = 0xffff;
+const OP_CHECKSEQUENCEVERIFY: u8 = 0xb2;
+
+// Block delta the spent subscription NFT was timelocked for, read from the witness script of its
+// P2WSH output; None if the witness carries no timelock script
+fn relative_timelock_blocks(
+    nft_app: &App,
+    tx: &Transaction,
+    witness: &SubscriptionWitness,
+) -> Result<Option<u32>, ValidationError> {
+    let Some(script) = &witness.timelock_script else {
+        return Ok(None);
+    };
+    let script = hex::decode(script).map_err(|_| ValidationError::InvalidTimelockScript)?;
+
+    // The script must be the one the NFT input's output committed to
+    let nft_input = tx
+        .ins
+        .iter()
+        .position(|(_, charms)| charms.contains_key(nft_app))
+        .ok_or(ValidationError::InvalidTimelockScript)?;
+    let mut p2wsh = vec![0x00, 0x20];
+    p2wsh.extend_from_slice(&Sha256::digest(&script));
+    let spent = tx.coin_ins.as_ref().and_then(|coins| coins.get(nft_input));
+    ensure!(
+        spent.is_some_and(|coin| coin.dest == p2wsh),
+        ValidationError::InvalidTimelockScript
+    );
+
+    let sequence = csv_sequence(&script).ok_or(ValidationError::InvalidTimelockScript)?;
+    ensure!(
+        sequence & (SEQUENCE_DISABLE_FLAG | SEQUENCE_TYPE_FLAG) == 0,
+        ValidationError::InvalidTimelockScript
+    );
+    Ok(Some(sequence & SEQUENCE_LOCKTIME_MASK))
+}
+
+// Sequence operand of a script starting with `<n> OP_CHECKSEQUENCEVERIFY`, with `n` pushed as a
+// minimally encoded, non-negative script number
+fn csv_sequence(script: &[u8]) -> Option<u32> {
+    let (sequence, rest) = match *script.first()? {
+        // OP_0, and OP_1 to OP_16
+        0x00 => (0, &script[1..]),
+        opcode @ 0x51..=0x60 => ((opcode - 0x50) as u32, &script[1..]),
+        len @ 1..=5 => {
+            let bytes = script.get(1..1 + len as usize)?;
+            let last = bytes[bytes.len() - 1];
+            // A trailing zero byte is only allowed to clear the sign bit of the byte before it
+            let minimal = last != 0 || (bytes.len() > 1 && bytes[bytes.len() - 2] & 0x80 != 0);
+            if last & 0x80 != 0 || !minimal {
+                return None;
+            }
+            let value = bytes
+                .iter()
+                .rev()
+                .fold(0u64, |value, &byte| value << 8 | byte as u64);
+            (u32::try_from(value).ok()?, &script[1 + len as usize..])
+        }
+        _ => return None,
+    };
+    (rest.first() == Some(&OP_CHECKSEQUENCEVERIFY)).then_some(sequence)
+}
+
+/// Where payouts are sent: a hex-encoded compressed public key, paid to its P2WPKH script,
+/// or a bech32/bech32m segwit address, paid to its witness program
+/// Both forms are stored as plain strings, so a parsed recipient displays as its original text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recipient {
+    Pubkey(String),
+    Bech32(String),
+}
+
+impl Recipient {
+    /// Parse either form; strings starting with a segwit prefix (`bc1`, `tb1`, `bcrt1`) must be
+    /// valid addresses, anything else must be a compressed pubkey
+    pub fn parse(recipient: &str) -> Result<Self, ValidationError> {
+        let lower = recipient.to_ascii_lowercase();
+        if SEGWIT_HRPS.iter().any(|hrp| {
+            lower
+                .strip_prefix(hrp)
+                .is_some_and(|rest| rest.starts_with('1'))
+        }) {
+            segwit_script(recipient).ok_or(ValidationError::InvalidRecipient)?;
+            return Ok(Recipient::Bech32(recipient.to_string()));
+        }
+        validate_pubkey(recipient)?;
+        Ok(Recipient::Pubkey(recipient.to_string()))
+    }
+
+    /// Output script that payouts to this recipient must be locked to
+    pub fn script_pubkey(&self) -> Option<Vec<u8>> {
+        match self {
+            Recipient::Pubkey(pubkey) => p2wpkh_script(pubkey),
+            Recipient::Bech32(address) => segwit_script(address),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Recipient::Pubkey(recipient) | Recipient::Bech32(recipient) => recipient,
+        }
+    }
+}
+
+impl std::fmt::Display for Recipient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// Human-readable parts of mainnet, testnet/signet and regtest segwit addresses
+const SEGWIT_HRPS: [&str; 3] = ["bc", "tb", "bcrt"];
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+// Checksum constants of BIP-173 (witness v0) and BIP-350 (witness v1+)
+const BECH32_CONST: u32 = 1;
+const BECH32M_CONST: u32 = 0x2bc830a3;
+
+// Output script (`OP_n <program>`) of a segwit address, or None if the address is malformed
+fn segwit_script(address: &str) -> Option<Vec<u8>> {
+    let mixed_case = address.bytes().any(|c| c.is_ascii_lowercase())
+        && address.bytes().any(|c| c.is_ascii_uppercase());
+    if mixed_case || address.len() > 90 {
+        return None;
+    }
+    let address = address.to_ascii_lowercase();
+    let (hrp, data) = address.rsplit_once('1')?;
+    if !SEGWIT_HRPS.contains(&hrp) || data.len() < 7 {
+        return None;
+    }
+    let values = data
+        .bytes()
+        .map(|c| BECH32_CHARSET.iter().position(|&v| v == c).map(|v| v as u8))
+        .collect::<Option<Vec<u8>>>()?;
+    let (&version, program) = values[..values.len() - 6].split_first()?;
+    let checksum = if version == 0 {
+        BECH32_CONST
+    } else {
+        BECH32M_CONST
+    };
+    if bech32_polymod(hrp, &values) != checksum {
+        return None;
+    }
+    let program = regroup_bits(program)?;
+    let valid_length = match version {
+        0 => program.len() == 20 || program.len() == 32,
+        1..=16 => (2..=40).contains(&program.len()),
+        _ => false,
+    };
+    if !valid_length {
+        return None;
+    }
+    let opcode = if version == 0 { 0x00 } else { 0x50 + version };
+    let mut script = vec![opcode, program.len() as u8];
+    script.extend_from_slice(&program);
+    Some(script)
+}
+
+// BIP-173 checksum over the expanded human-readable part followed by the data values
+fn bech32_polymod(hrp: &str, values: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+    hrp.bytes()
+        .map(|c| c >> 5)
+        .chain([0])
+        .chain(hrp.bytes().map(|c| c & 31))
+        .chain(values.iter().copied())
+        .fold(1u32, |checksum, value| {
+            let top = checksum >> 25;
+            let checksum = ((checksum & 0x1ffffff) << 5) ^ value as u32;
+            GENERATOR
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| (top >> i) & 1 == 1)
+                .fold(checksum, |checksum, (_, generator)| checksum ^ generator)
+        })
+}
+
+// Regroup 5-bit values into bytes, rejecting more than 4 bits of padding or non-zero padding
+fn regroup_bits(values: &[u8]) -> Option<Vec<u8>> {
+    let mut acc = 0u32;
+    let mut bits = 0;
+    let mut bytes = Vec::with_capacity(values.len() * 5 / 8);
+    for &value in values {
+        acc = ((acc << 5) | value as u32) & 0xfff;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    if bits >= 5 || acc & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(bytes)
+}
+
+// Output script paying a recipient given in either supported form
+fn recipient_script(recipient: &str) -> Option<Vec<u8>> {
+    Recipient::parse(recipient).ok()?.script_pubkey()
+}
+
+// A gift is funded by its funder: the funding input must be locked to the funder's script,
+// as recorded in the native input at the same position
+fn ensure_funded_by_funder(
+    state: &MinimalSubscriptionState,
+    tx: &Transaction,
+    funding_input: usize,
+) -> Result<(), ValidationError> {
+    if state.funder_pubkey.is_empty() {
+        return Ok(());
+    }
+    let script = recipient_script(&state.funder_pubkey);
+    let funding_coin = tx
+        .coin_ins
+        .as_ref()
+        .and_then(|coins| coins.get(funding_input));
+    ensure!(
+        funding_coin.is_some_and(|coin| Some(&coin.dest) == script.as_ref()),
+        ValidationError::FundingNotFromFunder
+    );
+    Ok(())
+}
+
+// Sum the tokens of `token_app` carried by outputs locked to `recipient`
+// Outputs are matched against the destination script of the corresponding native output
+fn tokens_paid_to(token_app: &App, tx: &Transaction, recipient: &str) -> u64 {
+    let (Some(coin_outs), Some(script)) = (&tx.coin_outs, recipient_script(recipient)) else {
+        return 0;
+    };
+    tx.outs
+        .iter()
+        .zip(coin_outs)
+        .filter(|(_, coin_out)| coin_out.dest == script)
+        .filter_map(|(charms, _)| charms.get(token_app)?.value::<u64>().ok())
+        .fold(0u64, |total, amount| total.saturating_add(amount))
+}
+
+// Sum the native value of the outputs locked to `recipient`
+fn sats_paid_to(tx: &Transaction, recipient: &str) -> u64 {
+    let (Some(coin_outs), Some(script)) = (&tx.coin_outs, recipient_script(recipient)) else {
+        return 0;
+    };
+    coin_outs
+        .iter()
+        .filter(|coin_out| coin_out.dest == script)
+        .fold(0u64, |total, coin_out| {
+            total.saturating_add(coin_out.amount)
+        })
+}
+
+// The setup fee is paid to the merchant by the mint, in native value outside the locked balance
+fn ensure_setup_fee_paid(
+    state: &MinimalSubscriptionState,
+    tx: &Transaction,
+) -> Result<(), ValidationError> {
+    let paid_to_merchant = sats_paid_to(tx, &state.merchant_pubkey);
+    ensure!(
+        paid_to_merchant >= state.setup_fee_sats,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: state.setup_fee_sats,
+            actual: paid_to_merchant
+        }
+    );
+    Ok(())
+}
+
+// Fields fixed at creation must be carried over unchanged by every transition
+fn ensure_immutable_fields(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> Result<(), ValidationError> {
+    ensure!(
+        in_state.payer_pubkey == out_state.payer_pubkey,
+        ValidationError::ImmutableFieldChanged("payer_pubkey")
+    );
+    ensure!(
+        in_state.merchant_pubkey == out_state.merchant_pubkey,
+        ValidationError::ImmutableFieldChanged("merchant_pubkey")
+    );
+    ensure!(
+        in_state.amount_sats == out_state.amount_sats,
+        ValidationError::ImmutableFieldChanged("amount_sats")
+    );
+    ensure!(
+        in_state.billing_interval_blocks == out_state.billing_interval_blocks,
+        ValidationError::ImmutableFieldChanged("billing_interval_blocks")
+    );
+    ensure!(
+        in_state.grace_period_blocks == out_state.grace_period_blocks,
+        ValidationError::ImmutableFieldChanged("grace_period_blocks")
+    );
+    ensure!(
+        in_state.trial_end_block == out_state.trial_end_block,
+        ValidationError::ImmutableFieldChanged("trial_end_block")
+    );
+    ensure!(
+        in_state.platform_pubkey == out_state.platform_pubkey,
+        ValidationError::ImmutableFieldChanged("platform_pubkey")
+    );
+    ensure!(
+        in_state.platform_fee_sats == out_state.platform_fee_sats,
+        ValidationError::ImmutableFieldChanged("platform_fee_sats")
+    );
+    ensure!(
+        in_state.coupon_hash == out_state.coupon_hash,
+        ValidationError::ImmutableFieldChanged("coupon_hash")
+    );
+    ensure!(
+        in_state.hash_algo == out_state.hash_algo,
+        ValidationError::ImmutableFieldChanged("hash_algo")
+    );
+    ensure!(
+        in_state.max_cycles == out_state.max_cycles,
+        ValidationError::ImmutableFieldChanged("max_cycles")
+    );
+    ensure!(
+        in_state.expiry_block == out_state.expiry_block,
+        ValidationError::ImmutableFieldChanged("expiry_block")
+    );
+    ensure!(
+        in_state.transfer_auth_hash == out_state.transfer_auth_hash,
+        ValidationError::ImmutableFieldChanged("transfer_auth_hash")
+    );
+    ensure!(
+        in_state.min_amount_sats == out_state.min_amount_sats,
+        ValidationError::ImmutableFieldChanged("min_amount_sats")
+    );
+    ensure!(
+        in_state.max_amount_sats == out_state.max_amount_sats,
+        ValidationError::ImmutableFieldChanged("max_amount_sats")
+    );
+    ensure!(
+        in_state.late_fee_sats == out_state.late_fee_sats,
+        ValidationError::ImmutableFieldChanged("late_fee_sats")
+    );
+    ensure!(
+        in_state.denomination_token == out_state.denomination_token,
+        ValidationError::ImmutableFieldChanged("denomination_token")
+    );
+    ensure!(
+        in_state.max_attempts == out_state.max_attempts,
+        ValidationError::ImmutableFieldChanged("max_attempts")
+    );
+    ensure!(
+        in_state.authorization_mode == out_state.authorization_mode,
+        ValidationError::ImmutableFieldChanged("authorization_mode")
+    );
+    ensure!(
+        in_state.payer_auth_hash == out_state.payer_auth_hash,
+        ValidationError::ImmutableFieldChanged("payer_auth_hash")
+    );
+    ensure!(
+        in_state.merchant_auth_hash == out_state.merchant_auth_hash,
+        ValidationError::ImmutableFieldChanged("merchant_auth_hash")
+    );
+    ensure!(
+        in_state.payer_reassign_auth_hash == out_state.payer_reassign_auth_hash,
+        ValidationError::ImmutableFieldChanged("payer_reassign_auth_hash")
+    );
+    ensure!(
+        in_state.merchant_reassign_auth_hash == out_state.merchant_reassign_auth_hash,
+        ValidationError::ImmutableFieldChanged("merchant_reassign_auth_hash")
+    );
+    ensure!(
+        in_state.plan_change_auth_hash == out_state.plan_change_auth_hash,
+        ValidationError::ImmutableFieldChanged("plan_change_auth_hash")
+    );
+    ensure!(
+        in_state.max_amount_cap == out_state.max_amount_cap,
+        ValidationError::ImmutableFieldChanged("max_amount_cap")
+    );
+    ensure!(
+        in_state.funder_pubkey == out_state.funder_pubkey,
+        ValidationError::ImmutableFieldChanged("funder_pubkey")
+    );
+    ensure!(
+        in_state.price_per_seat_sats == out_state.price_per_seat_sats,
+        ValidationError::ImmutableFieldChanged("price_per_seat_sats")
+    );
+    ensure!(
+        in_state.escrow_release_hash == out_state.escrow_release_hash,
+        ValidationError::ImmutableFieldChanged("escrow_release_hash")
+    );
+    ensure!(
+        in_state.dispute_window_blocks == out_state.dispute_window_blocks,
+        ValidationError::ImmutableFieldChanged("dispute_window_blocks")
+    );
+    ensure!(
+        in_state.allow_partial_final == out_state.allow_partial_final,
+        ValidationError::ImmutableFieldChanged("allow_partial_final")
+    );
+    ensure!(
+        in_state.accrue_payments == out_state.accrue_payments,
+        ValidationError::ImmutableFieldChanged("accrue_payments")
+    );
+    ensure!(
+        in_state.usd_amount_cents == out_state.usd_amount_cents,
+        ValidationError::ImmutableFieldChanged("usd_amount_cents")
+    );
+    ensure!(
+        in_state.slippage_bps == out_state.slippage_bps,
+        ValidationError::ImmutableFieldChanged("slippage_bps")
+    );
+    ensure!(
+        in_state.oracle_auth_hash == out_state.oracle_auth_hash,
+        ValidationError::ImmutableFieldChanged("oracle_auth_hash")
+    );
+    ensure!(
+        in_state.setup_fee_sats == out_state.setup_fee_sats,
+        ValidationError::ImmutableFieldChanged("setup_fee_sats")
+    );
+    ensure!(
+        in_state.min_commitment_cycles == out_state.min_commitment_cycles,
+        ValidationError::ImmutableFieldChanged("min_commitment_cycles")
+    );
+    ensure!(
+        in_state.early_termination_fee_sats == out_state.early_termination_fee_sats,
+        ValidationError::ImmutableFieldChanged("early_termination_fee_sats")
+    );
+    ensure!(
+        in_state.contract_version == out_state.contract_version,
+        ValidationError::ImmutableFieldChanged("contract_version")
+    );
+    ensure!(
+        in_state.allowed_merchants == out_state.allowed_merchants,
+        ValidationError::ImmutableFieldChanged("allowed_merchants")
+    );
+    ensure!(
+        in_state.currency_unit == out_state.currency_unit,
+        ValidationError::ImmutableFieldChanged("currency_unit")
+    );
+    ensure!(
+        in_state.subscription_id == out_state.subscription_id,
+        ValidationError::ImmutableFieldChanged("subscription_id")
+    );
+    ensure!(
+        in_state.annual_yield_bps == out_state.annual_yield_bps,
+        ValidationError::ImmutableFieldChanged("annual_yield_bps")
+    );
+    Ok(())
+}
+
+fn can_mint_token(token_app: &App, nft_app: &App, tx: &Transaction) -> Result<(), ValidationError> {
+    // Check if there's an NFT in inputs
+    let incoming_state = find_subscription_state(nft_app, tx.ins.iter().map(|(_, v)| v));
+
+    // Check if there's exactly one NFT in outputs
+    ensure_single_nft_output(nft_app, tx)?;
+    let outgoing_state =
+        find_subscription_state(nft_app, tx.outs.iter()).ok_or(ValidationError::NoNftInOutput)?;
+    let outgoing_supply = outgoing_state.remaining_supply();
+
+    let (input_token_amount, output_token_amount) = token_totals(token_app, tx)?;
+
+    // Case 1: NFT in inputs (normal token minting controlled by NFT)
+    // Only legacy states manage a mintable supply, full states use dedicated transitions
+    if let Some(incoming_state) = incoming_state {
+        let (SubscriptionStateV::V1(incoming_nft), SubscriptionStateV::V1(_)) =
+            (&incoming_state, &outgoing_state)
+        else {
+            return Err(ValidationError::InvalidNftState);
+        };
+        let incoming_supply = incoming_nft.remaining;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            incoming_supply,
+            outgoing_supply,
+            input_token_amount,
+            output_token_amount,
+            "token mint controlled by a legacy subscription"
+        );
+        // incoming remaining supply must be >= outgoing remaining supply
+        let expected = sub_or_reject(incoming_supply, outgoing_supply)
+            .map_err(|_| ValidationError::SupplyIncreased)?;
+        // can mint no more than what's allowed by the managing NFT state change.
+        let actual = sub_or_reject(output_token_amount, input_token_amount)?;
+        ensure!(
+            actual == expected,
+            ValidationError::AmountMismatch { expected, actual }
+        );
+        return Ok(());
+    }
+
+    // Case 2: No NFT in inputs (initial creation - minting NFT and tokens together)
+    // When creating a subscription, we mint both NFT and tokens at the same time
+    // Allow minting tokens equal to the NFT's remaining supply (total locked amount)
+    ensure!(
+        input_token_amount == 0,
+        ValidationError::AmountMismatch {
+            expected: 0,
+            actual: input_token_amount
+        }
+    );
+    ensure!(
+        output_token_amount == outgoing_supply,
+        ValidationError::AmountMismatch {
+            expected: outgoing_supply,
+            actual: output_token_amount
+        }
+    );
+    // The declared supply must be backed by native value locked with the subscription: coins
+    // returned as change or paid out elsewhere, such as the setup fee, do not count
+    let funded = locked_native_value(token_app, nft_app, tx.outs.iter(), &tx.coin_outs)?;
+    ensure!(
+        funded >= outgoing_supply,
+        ValidationError::InsufficientFunding {
+            required: outgoing_supply,
+            funded
+        }
+    );
+    if let Some(subscription) = outgoing_state.subscription() {
+        ensure_setup_fee_paid(subscription, tx)?;
+    }
+    Ok(())
+}
+
+// Native value of the inputs or outputs holding the subscription NFT or its tokens, given
+// their charms and the parallel native coins
+fn locked_native_value<'a>(
+    token_app: &App,
+    nft_app: &App,
+    charms: impl Iterator<Item = &'a Charms>,
+    coins: &Option<Vec<NativeOutput>>,
+) -> Result<u64, ValidationError> {
+    charms
+        .zip(coins.iter().flatten())
+        .filter(|(charms, _)| charms.contains_key(nft_app) || charms.contains_key(token_app))
+        .try_fold(0, |locked, (_, coin)| add_or_reject(locked, coin.amount))
+}
+
+// Subscription payment: validates payment execution with full state checks
+fn can_execute_subscription_payment(
+    token_app: &App,
+    nft_app: &App,
+    tx: &Transaction,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // States and token sums are read in a single pass over the transaction
+    let scan = scan_tx(token_app, nft_app, tx)?;
+
+    // No NFT in inputs or outputs means this is not a subscription payment
+    ensure!(
+        scan.nft_outputs <= 1,
+        ValidationError::UnexpectedNftCount(scan.nft_outputs)
+    );
+    ensure_nft_identity_conserved(nft_app, tx)?;
+    let incoming_state = scan
+        .incoming_state
+        .as_ref()
+        .ok_or(ValidationError::NoNftInInput)?;
+    let outgoing_state = scan
+        .outgoing_state
+        .as_ref()
+        .ok_or(ValidationError::NoNftInOutput)?;
+
+    // A subscription denominated in another token is paid, and its tokens counted, in that token
+    let payment_token = payment_token_app(token_app, tx, incoming_state)?;
+    let denominated_scan;
+    let payment_scan = if payment_token == token_app {
+        &scan
+    } else {
+        denominated_scan = scan_tx(payment_token, nft_app, tx)?;
+        &denominated_scan
+    };
+
+    let (incoming_nft, outgoing_nft) = match (incoming_state, outgoing_state) {
+        // If we have full state, validate with all checks
+        (SubscriptionStateV::V2(in_state), SubscriptionStateV::V2(out_state)) => {
+            return validate_subscription_payment_full(
+                in_state,
+                out_state,
+                payment_token,
+                payment_scan,
+                witness,
+            );
+        }
+        (SubscriptionStateV::V3(in_state), SubscriptionStateV::V3(out_state)) => {
+            return validate_split_payment(
+                in_state,
+                out_state,
+                payment_token,
+                payment_scan,
+                witness,
+            );
+        }
+        (SubscriptionStateV::V4(in_state), SubscriptionStateV::V4(out_state)) => {
+            return validate_metered_payment(
+                in_state,
+                out_state,
+                payment_token,
+                payment_scan,
+                witness,
+            );
+        }
+        (SubscriptionStateV::V5(in_state), SubscriptionStateV::V5(out_state)) => {
+            return validate_tiered_payment(
+                in_state,
+                out_state,
+                payment_token,
+                payment_scan,
+                witness,
+            );
+        }
+        // Fall back to legacy format (NftContent)
+        (SubscriptionStateV::V1(incoming_nft), SubscriptionStateV::V1(outgoing_nft)) => {
+            (incoming_nft, outgoing_nft)
+        }
+        _ => return Err(ValidationError::VersionMismatch),
+    };
+
+    // Legacy validation: NFT remaining must decrease
+    ensure!(
+        incoming_nft.remaining >= outgoing_nft.remaining,
+        ValidationError::SupplyIncreased
+    );
+
+    // For subscription payments: tokens are transferred (output == input)
+    ensure_tokens_conserved(&scan)?;
+    // and the remaining supply stays locked with the subscription
+    assert_tokens_locked(token_app, nft_app, tx, outgoing_nft.remaining)?;
+
+    // Legacy states do not record the per-cycle amount. A ticker id derived from the terms
+    // commits to it: the witness supplies those terms, and the supply decreases by exactly one
+    // cycle (or what is left of it). A human-readable id (`SUBSCRIPTION-sub_001`) commits to no
+    // terms, so those states keep the original rule that the supply only decreases.
+    let ticker_id = incoming_nft
+        .subscription_id()
+        .ok_or(ValidationError::InvalidTicker)?;
+    if !is_terms_hash(ticker_id) {
+        return Ok(());
+    }
+    let terms = witness
+        .legacy_terms
+        .as_ref()
+        .ok_or(ValidationError::MissingLegacyTerms)?;
+    ensure!(
+        ticker_id == terms.subscription_id(),
+        ValidationError::InvalidTicker
+    );
+    let expected = terms.amount_sats.min(incoming_nft.remaining);
+    let decrement = incoming_nft.remaining - outgoing_nft.remaining;
+    ensure!(
+        decrement == expected,
+        ValidationError::AmountMismatch {
+            expected,
+            actual: decrement
+        }
+    );
+    Ok(())
+}
+
+// Whether a legacy ticker id is the hash of the subscription terms (`MigrationTerms`)
+fn is_terms_hash(subscription_id: &str) -> bool {
+    subscription_id.len() == 64 && subscription_id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+// Token app the payments of `state` are made in: the managing `token_app`, unless the state
+// declares a denomination, which must then be held by exactly one token app in the transaction
+fn payment_token_app<'a>(
+    token_app: &'a App,
+    tx: &'a Transaction,
+    state: &SubscriptionStateV,
+) -> Result<&'a App, ValidationError> {
+    let Some(denomination) = state
+        .subscription()
+        .and_then(|s| s.denomination_token.as_ref())
+    else {
+        return Ok(token_app);
+    };
+    let mut candidates = tx
+        .ins
+        .iter()
+        .map(|(_, charms)| charms)
+        .chain(&tx.outs)
+        .flat_map(|charms| charms.keys())
+        .filter(|app| app.tag == TOKEN && &app.identity == denomination);
+    let payment_token = candidates
+        .next()
+        .ok_or(ValidationError::WrongPaymentToken)?;
+    ensure!(
+        candidates.all(|app| app == payment_token),
+        ValidationError::WrongPaymentToken
+    );
+    Ok(payment_token)
+}
+
+// Tokens still locked in subscriptions must stay with the subscription NFTs: they are
+// carried by the NFT outputs themselves or by outputs sent to the same destination as an NFT
+// output. An output without a known destination only counts if it holds an NFT
+fn assert_tokens_locked(
+    token_app: &App,
+    nft_app: &App,
+    tx: &Transaction,
+    locked: u64,
+) -> Result<(), ValidationError> {
+    let dest_of = |index: usize| {
+        tx.coin_outs
+            .as_ref()
+            .and_then(|coin_outs| coin_outs.get(index))
+            .map(|coin_out| &coin_out.dest)
+            .filter(|dest| !dest.is_empty())
+    };
+    let holds_nft = |charms: &Charms| charms.contains_key(nft_app);
+    ensure!(
+        tx.outs.iter().any(holds_nft),
+        ValidationError::NoNftInOutput
+    );
+    let nft_dests = tx
+        .outs
+        .iter()
+        .enumerate()
+        .filter(|(_, charms)| holds_nft(charms))
+        .filter_map(|(index, _)| dest_of(index))
+        .collect::<Vec<_>>();
+    let kept = tx
+        .outs
+        .iter()
+        .enumerate()
+        .filter(|(index, charms)| {
+            holds_nft(charms) || dest_of(*index).is_some_and(|dest| nft_dests.contains(&dest))
+        })
+        .filter_map(|(_, charms)| charms.get(token_app)?.value::<u64>().ok())
+        .fold(0u64, |total, amount| total.saturating_add(amount));
+    ensure!(
+        kept >= locked,
+        ValidationError::TokensNotLocked {
+            expected: locked,
+            actual: kept
+        }
+    );
+    Ok(())
+}
+
+// Full validation for subscription payment with MinimalSubscriptionState
+fn validate_subscription_payment_full(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    let charge = cycle_charge(in_state, out_state, witness)?;
+    let payment_amount =
+        validate_payment_transition(in_state, out_state, token_app, scan, witness, charge)?;
+
+    // 7. The platform fee and the rest of the payment are routed to the platform and merchant
+    ensure_payouts(
+        token_app,
+        scan.tx,
+        &payment_payouts(in_state, payment_amount)?,
+    )
+}
+
+// Who receives a payment of `payment_amount`: the platform fee goes to the platform
+// and the rest to the merchant, as (recipient, pubkey, amount)
+fn payment_payouts(
+    in_state: &MinimalSubscriptionState,
+    payment_amount: u64,
+) -> Result<Vec<(&'static str, &str, u64)>, ValidationError> {
+    let fee = in_state.platform_fee_sats;
+    let merchant_share =
+        sub_or_reject(payment_amount, fee).map_err(|_| ValidationError::FeeExceedsAmount {
+            fee,
+            amount: payment_amount,
+        })?;
+    let mut payouts = Vec::new();
+    if fee > 0 {
+        payouts.push(("platform", in_state.platform_pubkey.as_str(), fee));
+    }
+    // An accruing merchant is paid by a later withdrawal instead
+    if !in_state.accrue_payments {
+        payouts.push((
+            "merchant",
+            in_state.merchant_pubkey.as_str(),
+            merchant_share,
+        ));
+    }
+    Ok(payouts)
+}
+
+// Part of a payment of `payment_amount` accruing to the merchant: the payment net of the
+// platform fee for accruing subscriptions, nothing otherwise
+fn accrued_payment(
+    state: &MinimalSubscriptionState,
+    payment_amount: u64,
+) -> Result<u64, ValidationError> {
+    if !state.accrue_payments {
+        return Ok(0);
+    }
+    sub_or_reject(payment_amount, state.platform_fee_sats).map_err(|_| {
+        ValidationError::FeeExceedsAmount {
+            fee: state.platform_fee_sats,
+            amount: payment_amount,
+        }
+    })
+}
+
+// Every payout is carried by outputs locked to its pubkey (possibly split across several outputs)
+// Payouts to the same pubkey are added up, so the same outputs cannot cover several of them
+fn ensure_payouts(
+    token_app: &App,
+    tx: &Transaction,
+    payouts: &[(&'static str, &str, u64)],
+) -> Result<(), ValidationError> {
+    let mut dues: Vec<(&'static str, &str, u64)> = Vec::new();
+    for (recipient, pubkey, amount) in payouts {
+        match dues
+            .iter_mut()
+            .find(|(_, due_pubkey, _)| due_pubkey == pubkey)
+        {
+            Some((_, _, due)) => *due = add_or_reject(*due, *amount)?,
+            None => dues.push((recipient, pubkey, *amount)),
+        }
+    }
+    for (recipient, pubkey, due) in dues {
+        let paid = tokens_paid_to(token_app, tx, pubkey);
+        ensure!(
+            paid >= due,
+            ValidationError::Underpaid {
+                recipient,
+                expected: due,
+                actual: paid
+            }
+        );
+    }
+    Ok(())
+}
+
+// Validate several subscription payments settled in one transaction
+// The subscriptions are NFTs of the same app identity (minted together), told apart by their
+// subscription ids: each outgoing state is paired with the one incoming state of its id and
+// validated on its own, while tokens, payouts and locked balances are checked over the whole
+// transaction
+fn validate_batch_payment(
+    token_app: &App,
+    nft_app: &App,
+    tx: &Transaction,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    let full_states = |strings_of_charms: Vec<&Charms>| {
+        charm_values(nft_app, strings_of_charms.into_iter())
+            .map(|data| match SubscriptionStateV::parse(data)? {
+                SubscriptionStateV::V2(state) => Ok(state),
+                _ => Err(ValidationError::VersionMismatch),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let incoming_states = full_states(tx.ins.iter().map(|(_, v)| v).collect())?;
+    let outgoing_states = full_states(tx.outs.iter().collect())?;
+
+    // 1. Every subscription spent is carried over, and no subscription appears out of nowhere
+    ensure!(
+        incoming_states.len() == outgoing_states.len(),
+        ValidationError::UnexpectedNftCount(outgoing_states.len())
+    );
+
+    // 2. Pair the states by subscription id and validate each payment
+    let mut paired = vec![false; incoming_states.len()];
+    let mut payouts = Vec::new();
+    let mut locked: u64 = 0;
+    let mut yields: u64 = 0;
+    for out_state in &outgoing_states {
+        let mut matches = (0..incoming_states.len()).filter(|&index| {
+            !out_state.subscription_id.is_empty()
+                && incoming_states[index].subscription_id == out_state.subscription_id
+        });
+        let index = matches
+            .next()
+            .ok_or(ValidationError::UnpairedSubscription)?;
+        // An id spent twice, or claimed by two outgoing states, cannot be paired unambiguously
+        ensure!(
+            matches.next().is_none() && !paired[index],
+            ValidationError::UnpairedSubscription
+        );
+        paired[index] = true;
+        let in_state = &incoming_states[index];
+        ensure_nonce_incremented(in_state, out_state)?;
+        // Batches are settled in the managing token only
+        ensure!(
+            in_state.denomination_token.is_none(),
+            ValidationError::WrongPaymentToken
+        );
+
+        let charge = cycle_charge(in_state, out_state, witness)?;
+        // Batches spend several NFT outputs, so their interval is checked against the witness
+        let payment_amount = validate_payment_state(in_state, out_state, witness, charge, None)?;
+        payouts.extend(payment_payouts(in_state, payment_amount)?);
+        locked = add_or_reject(locked, out_state.locked_sats())?;
+        yields = add_or_reject(yields, payment_yield(in_state, witness)?)?;
+    }
+
+    // 3. Tokens are transferred (not burned), only the credited yields being minted, balances
+    // stay locked and payouts are made
+    ensure_yield_minted(token_app, &scan_tx(token_app, nft_app, tx)?, yields)?;
+    assert_tokens_locked(token_app, nft_app, tx, locked)?;
+    ensure_payouts(token_app, tx, &payouts)
+}
+
+// Amounts that can be charged for the cycle: `amount_sats`, the subscription's band or its USD
+// price in tokens, adjusted like any cycle's charge
+fn cycle_charge(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<RangeInclusive<u64>, ValidationError> {
+    // Amounts of the state are converted from its unit, the oracle's rate is already in sats
+    let in_tokens = |amount| in_state.currency_unit.to_tokens(amount);
+    let base = if in_state.usd_amount_cents > 0 {
+        usd_charge(in_state, witness)?
+    } else if in_state.max_amount_sats > 0 {
+        in_tokens(in_state.min_amount_sats)?..=in_tokens(in_state.max_amount_sats)?
+    } else {
+        let amount = in_tokens(in_state.amount_sats)?;
+        amount..=amount
+    };
+    adjusted_charge(in_state, out_state, witness, base)
+}
+
+// Charge of a cycle priced at `base` tokens, once discounted by a redeemed coupon, reduced by
+// the seat credit and increased by the late fee
+fn adjusted_charge(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+    base: RangeInclusive<u64>,
+) -> Result<RangeInclusive<u64>, ValidationError> {
+    // A redeemed coupon discounts whatever the cycle would otherwise charge
+    let base = match &witness.coupon {
+        Some(coupon) => {
+            let discount_bps = coupon_discount(in_state, out_state, coupon)?;
+            let discounted = |amount: &u64| {
+                (*amount as u128 * (BASIS_POINTS - discount_bps as u32) as u128
+                    / BASIS_POINTS as u128) as u64
+            };
+            discounted(base.start())..=discounted(base.end())
+        }
+        None => {
+            ensure!(
+                in_state.coupon_used == out_state.coupon_used,
+                ValidationError::UnexpectedFieldChange("coupon_used")
+            );
+            base
+        }
+    };
+
+    // Credit from a seat decrease is deducted from the charge until it is used up
+    let credit = in_state.seat_credit_sats.min(*base.start());
+    ensure!(
+        out_state.seat_credit_sats == in_state.seat_credit_sats - credit,
+        ValidationError::UnexpectedFieldChange("seat_credit_sats")
+    );
+
+    // Late payments are only accepted within the grace period, which the schedule checks enforce
+    let late_fee_sats = if out_state.last_payment_block > in_state.next_due_block() {
+        in_state.late_fee_sats
+    } else {
+        0
+    };
+    let with_late_fee = |amount: &u64| add_or_reject(amount - credit, late_fee_sats);
+    Ok(with_late_fee(base.start())?..=with_late_fee(base.end())?)
+}
+
+// Sats the USD price of a cycle converts to at the oracle's rate, give or take the slippage;
+// the rate must be attested by the subscription's oracle within `ORACLE_MAX_AGE_BLOCKS`
+fn usd_charge(
+    state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<RangeInclusive<u64>, ValidationError> {
+    let price = witness
+        .oracle_price
+        .as_ref()
+        .ok_or(ValidationError::MissingOraclePrice)?;
+    ensure!(
+        state
+            .oracle_auth_hash
+            .as_ref()
+            .is_some_and(|auth_hash| ct_eq(&hash_bytes(&price.oracle_auth.0), auth_hash)),
+        ValidationError::OraclePriceNotAuthorized
+    );
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(
+        price.block <= current_block && current_block - price.block <= ORACLE_MAX_AGE_BLOCKS,
+        ValidationError::StaleOraclePrice
+    );
+
+    let required = u64::try_from(state.usd_amount_cents as u128 * price.sats_per_usd as u128 / 100)
+        .map_err(|_| ValidationError::Overflow)?;
+    let tolerance = (required as u128 * state.slippage_bps as u128 / BASIS_POINTS as u128) as u64;
+    Ok(required.saturating_sub(tolerance)..=add_or_reject(required, tolerance)?)
+}
+
+// Discount of the coupon, which must be the subscription's and unused
+fn coupon_discount(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    coupon: &CouponRedemption,
+) -> Result<u16, ValidationError> {
+    // The code must hash to the coupon issued with the subscription
+    ensure!(
+        in_state
+            .coupon_hash
+            .as_ref()
+            .is_some_and(|coupon_hash| ct_eq(coupon_hash, &coupon.coupon_hash)),
+        ValidationError::InvalidCoupon
+    );
+    ensure!(
+        ct_eq(&hash(&coupon.coupon_code), &coupon.coupon_hash),
+        ValidationError::InvalidCoupon
+    );
+    ensure!(
+        coupon.discount_bps as u32 <= BASIS_POINTS,
+        ValidationError::InvalidCoupon
+    );
+
+    // A coupon can be redeemed only once
+    ensure!(!in_state.coupon_used, ValidationError::CouponAlreadyUsed);
+    ensure!(
+        out_state.coupon_used,
+        ValidationError::UnexpectedFieldChange("coupon_used")
+    );
+    Ok(coupon.discount_bps)
+}
+
+// State, schedule and token checks shared by every payment, independent of who gets paid
+// `charge` holds the amounts the balance may decrease by; returns the amount charged for the cycle
+fn validate_payment_transition(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+    charge: RangeInclusive<u64>,
+) -> Result<u64, ValidationError> {
+    let relative_timelock = relative_timelock_blocks(scan.nft_app, scan.tx, witness)?;
+    let payment_amount =
+        validate_payment_state(in_state, out_state, witness, charge, relative_timelock)?;
+
+    // 6. Tokens should be transferred (not burned), only the credited yield being minted, and
+    // the balance staying locked
+    ensure_yield_minted(token_app, scan, payment_yield(in_state, witness)?)?;
+    assert_tokens_locked(token_app, scan.nft_app, scan.tx, out_state.locked_sats())?;
+    Ok(payment_amount)
+}
+
+// Tokens are conserved but for the credited `yield_sats`, which are minted against native value
+// the transaction newly locks with the subscription's tokens: the yield is paid in by whoever
+// funds it (typically the merchant), not created from nothing
+fn ensure_yield_minted(
+    token_app: &App,
+    scan: &TxScan,
+    yield_sats: u64,
+) -> Result<(), ValidationError> {
+    let expected = add_or_reject(scan.input_tokens, yield_sats)?;
+    ensure!(
+        scan.output_tokens == expected,
+        ValidationError::AmountMismatch {
+            expected,
+            actual: scan.output_tokens
+        }
+    );
+    ensure_newly_locked(token_app, scan, yield_sats)
+}
+
+// The native value of the outputs holding the subscription NFT or its tokens exceeds that of
+// the inputs holding them by at least `minted`, so the tokens minted are backed
+fn ensure_newly_locked(token_app: &App, scan: &TxScan, minted: u64) -> Result<(), ValidationError> {
+    if minted == 0 {
+        return Ok(());
+    }
+    let tx = scan.tx;
+    let locked_before = locked_native_value(
+        token_app,
+        scan.nft_app,
+        tx.ins.iter().map(|(_, charms)| charms),
+        &tx.coin_ins,
+    )?;
+    let locked_after = locked_native_value(token_app, scan.nft_app, tx.outs.iter(), &tx.coin_outs)?;
+    let funded = locked_after.saturating_sub(locked_before);
+    ensure!(
+        funded >= minted,
+        ValidationError::InsufficientFunding {
+            required: minted,
+            funded
+        }
+    );
+    Ok(())
+}
+
+// Yield a payment credits to the balance before charging it, up to the witnessed block
+fn payment_yield(
+    in_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<u64, ValidationError> {
+    if in_state.annual_yield_bps == 0 {
+        return Ok(0);
+    }
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    in_state.accrued_yield(current_block)
+}
+
+// State and schedule checks of a payment, independent of the transaction's tokens
+// `relative_timelock` is the block delta enforced on the spent NFT output, if it was timelocked
+fn validate_payment_state(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+    charge: RangeInclusive<u64>,
+    relative_timelock: Option<u32>,
+) -> Result<u64, ValidationError> {
+    // 1. Validate subscription is active, not paused and has cycles left
+    ensure!(in_state.is_active, ValidationError::SubscriptionInactive);
+    ensure!(!in_state.paused, ValidationError::SubscriptionPaused);
+    ensure!(
+        in_state.max_cycles == 0 || in_state.cycles_paid < in_state.max_cycles,
+        ValidationError::MaxCyclesReached
+    );
+    ensure!(
+        in_state.is_merchant_allowed(&in_state.merchant_pubkey),
+        ValidationError::MerchantNotAllowed
+    );
+    // The payment is counted as one more cycle
+    let cycles_paid = in_state
+        .cycles_paid
+        .checked_add(1)
+        .ok_or(ValidationError::Overflow)?;
+    ensure!(
+        out_state.cycles_paid == cycles_paid,
+        ValidationError::UnexpectedFieldChange("cycles_paid")
+    );
+    // A declared prepaid counter is used up in lockstep
+    ensure!(
+        out_state.prepaid_cycles == in_state.prepaid_cycles.saturating_sub(1),
+        ValidationError::UnexpectedFieldChange("prepaid_cycles")
+    );
+    // A successful payment clears the record of failed attempts
+    ensure!(
+        out_state.failed_attempts == 0,
+        ValidationError::UnexpectedFieldChange("failed_attempts")
+    );
+    ensure!(
+        out_state.seats == in_state.seats,
+        ValidationError::UnexpectedFieldChange("seats")
+    );
+    // If allowed, a balance short of the charge once credited with its yield is paid out whole
+    // by a final partial payment
+    let credited = add_or_reject(
+        in_state.remaining_balance,
+        payment_yield(in_state, witness)?,
+    )?;
+    let partial_final = in_state.allow_partial_final && credited < *charge.start();
+    let charge = if partial_final {
+        credited..=credited
+    } else {
+        charge
+    };
+    // Should remain active after payment, unless this was the final allowed cycle or a final
+    // partial payment
+    if partial_final || (in_state.max_cycles != 0 && cycles_paid == in_state.max_cycles) {
+        ensure!(
+            !out_state.is_active,
+            ValidationError::UnexpectedFieldChange("is_active")
+        );
+    } else {
+        ensure!(out_state.is_active, ValidationError::UnexpectedDeactivation);
+    }
+
+    // 2. Validate immutable fields don't change, but for the oracle's chain: a USD-priced
+    // payment commits to the link it revealed, which only the oracle's next link hashes to
+    let oracle_auth_hash = match &witness.oracle_price {
+        Some(price) if in_state.usd_amount_cents != 0 => Some(price.oracle_auth.clone()),
+        _ => in_state.oracle_auth_hash.clone(),
+    };
+    ensure!(
+        out_state.oracle_auth_hash == oracle_auth_hash,
+        ValidationError::AuthorizationNotRotated("oracle")
+    );
+    let expected = MinimalSubscriptionState {
+        oracle_auth_hash,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+
+    // 3. Validate remaining balance decreases, once credited with its yield
+    let payment_amount = sub_or_reject(credited, out_state.remaining_balance)
+        .map_err(|_| ValidationError::SupplyIncreased)?;
+
+    // 4. Validate payment amount matches the cycle's charge
+    ensure!(
+        charge.contains(&payment_amount),
+        if charge.start() == charge.end() {
+            ValidationError::AmountMismatch {
+                expected: *charge.start(),
+                actual: payment_amount,
+            }
+        } else {
+            ValidationError::AmountOutOfRange {
+                min: *charge.start(),
+                max: *charge.end(),
+                actual: payment_amount,
+            }
+        }
+    );
+    // The payment is added to the lifetime total
+    let total_paid_sats = add_or_reject(in_state.total_paid_sats, payment_amount)?;
+    ensure!(
+        out_state.total_paid_sats == total_paid_sats,
+        ValidationError::AmountMismatch {
+            expected: total_paid_sats,
+            actual: out_state.total_paid_sats
+        }
+    );
+    // and recorded as the charge a reversal would refund
+    ensure!(
+        out_state.last_charge_sats == payment_amount,
+        ValidationError::UnexpectedFieldChange("last_charge_sats")
+    );
+    // The merchant's share accrues instead of being paid out, if the subscription says so
+    let merchant_withdrawable_sats = add_or_reject(
+        in_state.merchant_withdrawable_sats,
+        accrued_payment(in_state, payment_amount)?,
+    )?;
+    ensure!(
+        out_state.merchant_withdrawable_sats == merchant_withdrawable_sats,
+        ValidationError::UnexpectedFieldChange("merchant_withdrawable_sats")
+    );
+
+    // 5. Validate the trial and billing interval have elapsed, but not beyond the grace period
+    // The current block is supplied by the witness and must be the recorded payment block
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(
+        out_state.last_payment_block == current_block,
+        ValidationError::BlockHeightMismatch {
+            expected: current_block,
+            actual: out_state.last_payment_block
+        }
+    );
+    ensure!(
+        out_state.last_payment_block >= in_state.trial_end_block,
+        ValidationError::TrialNotEnded
+    );
+    // The first charge after a trial is due when the trial ends
+    let next_due_block = in_state
+        .last_payment_block
+        .checked_add(in_state.billing_interval_blocks)
+        .ok_or(ValidationError::Overflow)?
+        .max(in_state.trial_end_block);
+    // The witnessed block must have reached the due block, and a relative timelock on the
+    // spent NFT additionally lets miners enforce the interval
+    ensure!(
+        out_state.last_payment_block >= next_due_block,
+        ValidationError::IntervalNotElapsed
+    );
+    if let Some(blocks) = relative_timelock {
+        ensure!(
+            blocks >= in_state.billing_interval_blocks,
+            ValidationError::TimelockTooShort {
+                required: in_state.billing_interval_blocks,
+                actual: blocks
+            }
+        );
+    }
+    let grace_end_block = next_due_block
+        .checked_add(in_state.grace_period_blocks)
+        .ok_or(ValidationError::Overflow)?;
+    ensure!(
+        out_state.last_payment_block <= grace_end_block,
+        ValidationError::SubscriptionLapsed
+    );
+    ensure!(
+        in_state.expiry_block == 0 || out_state.last_payment_block <= in_state.expiry_block,
+        ValidationError::SubscriptionExpired
+    );
+
+    // 6. Validate the payment is authorized by the party the mode names, and released from
+    // escrow by the fulfillment proof if one is required
+    validate_payment_authorization(in_state, witness)?;
+    validate_escrow_release(in_state, witness)?;
+
+    Ok(payment_amount)
+}
+
+// The payer authorizes push payments and the merchant pull payments, by revealing the preimage
+// of the hash they committed to at creation; a party without a commitment is not checked
+fn validate_payment_authorization(
+    state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    let auth_hash = match state.authorization_mode {
+        AuthorizationMode::Push => &state.payer_auth_hash,
+        AuthorizationMode::Pull => &state.merchant_auth_hash,
+    };
+    let Some(auth_hash) = auth_hash else {
+        return Ok(());
+    };
+    let auth = witness
+        .payment_auth
+        .as_deref()
+        .ok_or(ValidationError::PaymentNotAuthorized)?;
+    ensure!(
+        ct_eq(&hash(auth), auth_hash),
+        ValidationError::PaymentNotAuthorized
+    );
+    Ok(())
+}
+
+// A payment held in escrow is released to the merchant only by revealing the preimage of the
+// fulfillment hash committed to at creation
+fn validate_escrow_release(
+    state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    let Some(release_hash) = &state.escrow_release_hash else {
+        return Ok(());
+    };
+    let fulfillment = witness
+        .fulfillment
+        .as_deref()
+        .ok_or(ValidationError::FulfillmentNotProven)?;
+    ensure!(
+        ct_eq(&hash(fulfillment), release_hash),
+        ValidationError::FulfillmentNotProven
+    );
+    Ok(())
+}
+
+// Validate a metered payment - the charge is the usage reported in the witness times the unit price
+fn validate_metered_payment(
+    in_state: &MeteredSubscriptionState,
+    out_state: &MeteredSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The pricing terms are fixed at creation
+    ensure!(
+        in_state.price_per_unit_sats == out_state.price_per_unit_sats,
+        ValidationError::ImmutableFieldChanged("price_per_unit_sats")
+    );
+    ensure!(
+        in_state.max_units_per_cycle == out_state.max_units_per_cycle,
+        ValidationError::ImmutableFieldChanged("max_units_per_cycle")
+    );
+    ensure!(
+        in_state.beneficiaries == out_state.beneficiaries,
+        ValidationError::ImmutableFieldChanged("beneficiaries")
+    );
+
+    // 2. Compute the charge from the reported usage, within what is left of the cycle's quota
+    // A payment counting a new cycle restarts the usage, a charge within the cycle adds to it
+    let units = witness.units.ok_or(ValidationError::MissingUsage)?;
+    let new_cycle = out_state.subscription.cycles_paid != in_state.subscription.cycles_paid;
+    let prior_usage = if new_cycle { 0 } else { in_state.cycle_usage };
+    let cycle_usage = prior_usage
+        .checked_add(units)
+        .ok_or(ValidationError::Overflow)?;
+    ensure!(
+        cycle_usage <= in_state.max_units_per_cycle,
+        ValidationError::UnitCapExceeded {
+            max: in_state.max_units_per_cycle,
+            actual: cycle_usage
+        }
+    );
+    let subscription = &in_state.subscription;
+    let charge = subscription
+        .currency_unit
+        .to_tokens(mul_or_reject(in_state.price_per_unit_sats, units as u64)?)?;
+    let beneficiary_usage = beneficiary_usage(in_state, scan.tx, witness, units, new_cycle)?;
+    ensure!(
+        out_state.beneficiary_usage == beneficiary_usage,
+        ValidationError::UnexpectedFieldChange("beneficiary_usage")
+    );
+
+    // 3. The underlying subscription follows the regular payment rules for that charge, or
+    // the usage charge rules before the cycle is due
+    let payouts = if new_cycle {
+        let charge = adjusted_charge(
+            subscription,
+            &out_state.subscription,
+            witness,
+            charge..=charge,
+        )?;
+        let payment_amount = validate_payment_transition(
+            subscription,
+            &out_state.subscription,
+            token_app,
+            scan,
+            witness,
+            charge,
+        )?;
+        payment_payouts(subscription, payment_amount)?
+    } else {
+        let payment_amount = validate_usage_charge(
+            subscription,
+            &out_state.subscription,
+            token_app,
+            scan,
+            witness,
+            charge,
+        )?;
+        vec![(
+            "merchant",
+            subscription.merchant_pubkey.as_str(),
+            payment_amount,
+        )]
+    };
+    ensure!(
+        out_state.cycle_usage == cycle_usage,
+        ValidationError::UnexpectedFieldChange("cycle_usage")
+    );
+
+    // 4. A cycle's payment is routed like any other, a charge within the cycle to the merchant
+    ensure_payouts(token_app, scan.tx, &payouts)
+}
+
+// Per-beneficiary usage after charging `units` for the beneficiary named in the witness, who
+// must be listed, spend an input locked to its pubkey and stay within its quota for the cycle
+fn beneficiary_usage(
+    state: &MeteredSubscriptionState,
+    tx: &Transaction,
+    witness: &SubscriptionWitness,
+    units: u32,
+    new_cycle: bool,
+) -> Result<Vec<u32>, ValidationError> {
+    if state.beneficiaries.is_empty() {
+        return Ok(state.beneficiary_usage.clone());
+    }
+    let beneficiary = witness
+        .beneficiary
+        .as_deref()
+        .ok_or(ValidationError::BeneficiaryNotListed)?;
+    let index = state
+        .beneficiaries
+        .iter()
+        .position(|(pubkey, _)| pubkey == beneficiary)
+        .ok_or(ValidationError::BeneficiaryNotListed)?;
+
+    let script = recipient_script(beneficiary);
+    let spends_own_coin = tx
+        .coin_ins
+        .iter()
+        .flatten()
+        .any(|coin| Some(&coin.dest) == script.as_ref());
+    ensure!(spends_own_coin, ValidationError::BeneficiaryNotAuthorized);
+
+    let mut usage = if new_cycle {
+        Vec::new()
+    } else {
+        state.beneficiary_usage.clone()
+    };
+    usage.resize(state.beneficiaries.len(), 0);
+    usage[index] = usage[index]
+        .checked_add(units)
+        .ok_or(ValidationError::Overflow)?;
+    let quota = state.beneficiaries[index].1;
+    ensure!(
+        usage[index] <= quota,
+        ValidationError::BeneficiaryQuotaExceeded {
+            quota,
+            actual: usage[index]
+        }
+    );
+    Ok(usage)
+}
+
+// A charge for usage within the current cycle: only the balance and the total paid change, and
+// the cycle must not be due yet, so the usage restarts exactly when the next cycle is paid
+fn validate_usage_charge(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+    charge: u64,
+) -> Result<u64, ValidationError> {
+    ensure!(in_state.is_active, ValidationError::SubscriptionInactive);
+    ensure!(!in_state.paused, ValidationError::SubscriptionPaused);
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(!in_state.is_due(current_block), ValidationError::PaymentDue);
+    validate_payment_authorization(in_state, witness)?;
+    validate_escrow_release(in_state, witness)?;
+
+    let expected = MinimalSubscriptionState {
+        remaining_balance: sub_or_reject(in_state.remaining_balance, charge).map_err(|_| {
+            ValidationError::InsufficientBalance {
+                required: charge,
+                available: in_state.remaining_balance,
+            }
+        })?,
+        total_paid_sats: add_or_reject(in_state.total_paid_sats, charge)?,
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("usage charge state")
+    );
+
+    // Tokens are moved, not minted/burned, and the rest of the balance stays locked
+    ensure_tokens_conserved(scan)?;
+    assert_tokens_locked(
+        token_app,
+        scan.nft_app,
+        scan.tx,
+        out_state.remaining_balance,
+    )?;
+    Ok(charge)
+}
+
+// Validate a tiered payment - the charge is the amount of the tier reached by the total paid
+fn validate_tiered_payment(
+    in_state: &TieredSubscriptionState,
+    out_state: &TieredSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The tiers are fixed at creation and must be sorted
+    ensure!(
+        in_state.tiers == out_state.tiers,
+        ValidationError::ImmutableFieldChanged("tiers")
+    );
+    in_state.validate_tiers()?;
+
+    // 2. The underlying subscription follows the regular payment rules for the tier's amount
+    let subscription = &in_state.subscription;
+    let amount = subscription
+        .currency_unit
+        .to_tokens(in_state.current_amount())?;
+    let charge = adjusted_charge(
+        subscription,
+        &out_state.subscription,
+        witness,
+        amount..=amount,
+    )?;
+    let payment_amount = validate_payment_transition(
+        subscription,
+        &out_state.subscription,
+        token_app,
+        scan,
+        witness,
+        charge,
+    )?;
+
+    // 3. The payment is routed like any other
+    ensure_payouts(
+        token_app,
+        scan.tx,
+        &payment_payouts(subscription, payment_amount)?,
+    )
+}
+
+// Validate a split payment - the cycle's amount is shared between the split recipients
+fn validate_split_payment(
+    in_state: &SplitSubscriptionState,
+    out_state: &SplitSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The split table is fixed at creation and must be well formed
+    ensure!(
+        in_state.splits == out_state.splits,
+        ValidationError::ImmutableFieldChanged("splits")
+    );
+    in_state.validate_splits()?;
+
+    // 2. The underlying subscription follows the regular payment rules
+    let subscription = &in_state.subscription;
+    let charge = cycle_charge(subscription, &out_state.subscription, witness)?;
+    let payment_amount = validate_payment_transition(
+        subscription,
+        &out_state.subscription,
+        token_app,
+        scan,
+        witness,
+        charge,
+    )?;
+
+    // 3. The platform fee is paid like for any payment, and the merchant's share, unless it
+    // accrues for a later withdrawal, is split instead of going to the merchant
+    let payouts = payment_payouts(subscription, payment_amount)?;
+    let (merchant, platform): (Vec<_>, Vec<_>) = payouts
+        .into_iter()
+        .partition(|(recipient, _, _)| *recipient == "merchant");
+    ensure_payouts(token_app, scan.tx, &platform)?;
+    let merchant_share = merchant.first().map_or(0, |(_, _, share)| *share);
+
+    // 4. Every recipient receives its basis-point share, short by at most the rounding tolerance
+    for (pubkey, bps) in &in_state.splits {
+        let share = (merchant_share as u128 * *bps as u128 / BASIS_POINTS as u128) as u64;
+        let paid = tokens_paid_to(token_app, scan.tx, pubkey);
+        ensure!(
+            paid.saturating_add(SPLIT_ROUNDING_TOLERANCE_SATS) >= share,
+            ValidationError::Underpaid {
+                recipient: "split recipient",
+                expected: share,
+                actual: paid
+            }
+        );
+    }
+    Ok(())
+}
+
+// Validate ownership transfer - only the payer may change, authorized by the witness
+fn validate_ownership_transfer(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The witness must reveal the authorization committed to at creation
+    let auth = witness
+        .transfer_auth
+        .as_deref()
+        .ok_or(ValidationError::TransferNotAuthorized)?;
+    ensure!(
+        in_state
+            .transfer_auth_hash
+            .as_ref()
+            .is_some_and(|auth_hash| ct_eq(auth_hash, &hash(auth))),
+        ValidationError::TransferNotAuthorized
+    );
+
+    // 2. Everything but the payer stays identical, including the balance
+    let expected = MinimalSubscriptionState {
+        payer_pubkey: out_state.payer_pubkey.clone(),
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("transferred state")
+    );
+
+    // 3. Tokens stay locked (not minted/burned)
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate merchant reassignment - the payments move to a new merchant only with the consent of
+// both the payer and the current merchant
+fn validate_merchant_reassignment(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The witness must reveal both consents committed to for reassignments, and the
+    // outgoing state commits to fresh ones in their place
+    let authorizations = [
+        (
+            "payer",
+            &in_state.payer_reassign_auth_hash,
+            &out_state.payer_reassign_auth_hash,
+            &witness.payer_auth,
+        ),
+        (
+            "merchant",
+            &in_state.merchant_reassign_auth_hash,
+            &out_state.merchant_reassign_auth_hash,
+            &witness.merchant_auth,
+        ),
+    ];
+    for (party, auth_hash, next_auth_hash, auth) in authorizations {
+        let authorized = match (auth_hash, auth) {
+            (Some(auth_hash), Some(auth)) => ct_eq(&hash(auth), auth_hash),
+            _ => false,
+        };
+        ensure!(
+            authorized,
+            ValidationError::ReassignmentNotAuthorized(party)
+        );
+        ensure!(
+            next_auth_hash.is_some() && next_auth_hash != auth_hash,
+            ValidationError::AuthorizationNotRotated(party)
+        );
+    }
+
+    // 2. The new merchant must be allowed and able to receive payments
+    ensure!(
+        in_state.is_merchant_allowed(&out_state.merchant_pubkey),
+        ValidationError::MerchantNotAllowed
+    );
+    Recipient::parse(&out_state.merchant_pubkey)?;
+
+    // 3. Everything but the merchant and the consents stays identical, including the balance
+    let expected = MinimalSubscriptionState {
+        merchant_pubkey: out_state.merchant_pubkey.clone(),
+        payer_reassign_auth_hash: out_state.payer_reassign_auth_hash.clone(),
+        merchant_reassign_auth_hash: out_state.merchant_reassign_auth_hash.clone(),
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("reassigned state")
+    );
+
+    // 4. Tokens stay locked (not minted/burned)
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate seat change - the seat count and amount change together before the cycle is due,
+// the prorated difference is paid to the merchant from the balance or credited to the payer
+fn validate_seat_change(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The outgoing state is exactly the seat change at the witnessed block
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    let expected = in_state.change_seats(out_state.seats, current_block)?;
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("seat change state")
+    );
+
+    // 2. A prorated charge for added seats is paid to the merchant out of the locked balance
+    let charge = in_state.remaining_balance - expected.remaining_balance;
+    let paid_to_merchant = tokens_paid_to(token_app, scan.tx, &in_state.merchant_pubkey);
+    ensure!(
+        paid_to_merchant >= charge,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: charge,
+            actual: paid_to_merchant
+        }
+    );
+
+    // 3. Tokens are moved, not minted/burned
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate plan change - the payer switches to another amount per cycle before the cycle is
+// due, the prorated difference is paid to the merchant from the balance or credited to the payer
+fn validate_plan_change(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The witness must reveal the payer's authorization, and the outgoing state commits to
+    // a fresh one in its place
+    let authorized = match (&in_state.plan_change_auth_hash, &witness.plan_change_auth) {
+        (Some(auth_hash), Some(auth)) => ct_eq(&hash(auth), auth_hash),
+        _ => false,
+    };
+    ensure!(authorized, ValidationError::PlanChangeNotAuthorized);
+    ensure!(
+        out_state.plan_change_auth_hash.is_some()
+            && out_state.plan_change_auth_hash != in_state.plan_change_auth_hash,
+        ValidationError::AuthorizationNotRotated("payer")
+    );
+
+    // 2. The outgoing state is exactly the plan change at the witnessed block
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    let expected = MinimalSubscriptionState {
+        plan_change_auth_hash: out_state.plan_change_auth_hash.clone(),
+        ..in_state.change_plan(out_state.amount_sats, current_block)?
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("plan change state")
+    );
+
+    // 3. A prorated charge for an upgrade is paid to the merchant out of the locked balance
+    let charge = in_state.remaining_balance - expected.remaining_balance;
+    let paid_to_merchant = tokens_paid_to(token_app, scan.tx, &in_state.merchant_pubkey);
+    ensure!(
+        paid_to_merchant >= charge,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: charge,
+            actual: paid_to_merchant
+        }
+    );
+
+    // 4. Tokens are moved, not minted/burned
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate payment reversal - within the dispute window the payer takes back the last cycle's
+// amount, returned by the merchant into the locked balance, and the schedule rolls back to the
+// prior payment; fields a payment may also have changed (prepaid counter, credits) are kept
+fn validate_payment_reversal(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The reversal happens within the window after the last payment
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    let window_end = in_state
+        .last_payment_block
+        .saturating_add(in_state.dispute_window_blocks);
+    ensure!(
+        in_state.dispute_window_blocks > 0
+            && (in_state.last_payment_block..=window_end).contains(&current_block),
+        ValidationError::OutsideDisputeWindow
+    );
+    // A subscription ended by its final cycle is revived by reversing that cycle
+    let ended_by_final_cycle =
+        in_state.max_cycles != 0 && in_state.cycles_paid == in_state.max_cycles;
+    ensure!(
+        in_state.is_active || ended_by_final_cycle,
+        ValidationError::SubscriptionInactive
+    );
+
+    // 2. Exactly one cycle is undone, back to the prior payment from which it was due, and
+    // exactly what it charged is refunded
+    let prior_payment_block = witness
+        .prior_payment_block
+        .ok_or(ValidationError::MissingPriorPaymentBlock)?;
+    let refund = in_state.last_charge_sats;
+    ensure!(refund > 0, ValidationError::NoChargeToReverse);
+    let expected = MinimalSubscriptionState {
+        last_payment_block: prior_payment_block,
+        remaining_balance: add_or_reject(in_state.remaining_balance, refund)?,
+        cycles_paid: in_state
+            .cycles_paid
+            .checked_sub(1)
+            .ok_or(ValidationError::Underflow)?,
+        total_paid_sats: sub_or_reject(in_state.total_paid_sats, refund)?,
+        merchant_withdrawable_sats: sub_or_reject(
+            in_state.merchant_withdrawable_sats,
+            accrued_payment(in_state, refund)?,
+        )?,
+        last_charge_sats: 0,
+        is_active: true,
+        last_state_change_block: current_block,
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_cooldown_elapsed(in_state, current_block)?;
+    ensure!(
+        prior_payment_block < in_state.last_payment_block
+            && expected.next_due_block() <= in_state.last_payment_block,
+        ValidationError::IntervalNotElapsed
+    );
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        out_state.remaining_balance == expected.remaining_balance,
+        ValidationError::AmountMismatch {
+            expected: expected.remaining_balance,
+            actual: out_state.remaining_balance
+        }
+    );
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("reversed state")
+    );
+
+    // 3. Tokens are moved back, not minted, and the restored balance is locked again
+    ensure_tokens_conserved(scan)?;
+    assert_tokens_locked(token_app, scan.nft_app, scan.tx, out_state.locked_sats())
+}
+
+// Validate payment retry - a failed attempt is recorded without moving funds, and the
+// subscription is deactivated once the attempts reach the cap
+fn validate_payment_retry(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. A failure can only be recorded once the payment is due
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(
+        in_state.is_due(current_block),
+        ValidationError::IntervalNotElapsed
+    );
+
+    // 2. The counter grows by one, deactivating at the cap; nothing else changes
+    let expected = in_state.record_failed_attempt()?;
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        out_state.failed_attempts == expected.failed_attempts,
+        ValidationError::UnexpectedFieldChange("failed_attempts")
+    );
+    ensure!(
+        out_state.is_active == expected.is_active,
+        ValidationError::UnexpectedFieldChange("is_active")
+    );
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("retried state")
+    );
+
+    // 3. Tokens stay locked (not minted/burned)
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate pause/resume - only the paused flag may change, funds stay locked
+fn validate_subscription_pause_resume(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. Only active subscriptions can be paused or resumed, and they stay active
+    ensure!(in_state.is_active, ValidationError::SubscriptionInactive);
+    ensure!(out_state.is_active, ValidationError::UnexpectedDeactivation);
+
+    // 2. Immutable fields should remain the same
+    ensure_immutable_fields(in_state, out_state)?;
+
+    // 3. Balance and billing schedule are untouched
+    ensure!(
+        in_state.remaining_balance == out_state.remaining_balance,
+        ValidationError::UnexpectedFieldChange("remaining_balance")
+    );
+    ensure_billing_unchanged(in_state, out_state)?;
+
+    // 4. Toggles are spaced out, and each records when it happened
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure_cooldown_elapsed(in_state, current_block)?;
+    ensure!(
+        out_state.last_state_change_block == current_block,
+        ValidationError::UnexpectedFieldChange("last_state_change_block")
+    );
+    Ok(())
+}
+
+// The billing schedule and payment history, which only payments (and the transitions that
+// undo or retry them) may change
+fn ensure_billing_unchanged(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+) -> Result<(), ValidationError> {
+    ensure!(
+        in_state.last_payment_block == out_state.last_payment_block,
+        ValidationError::UnexpectedFieldChange("last_payment_block")
+    );
+    ensure!(
+        in_state.coupon_used == out_state.coupon_used,
+        ValidationError::UnexpectedFieldChange("coupon_used")
+    );
+    ensure!(
+        in_state.cycles_paid == out_state.cycles_paid,
+        ValidationError::UnexpectedFieldChange("cycles_paid")
+    );
+    ensure!(
+        in_state.total_paid_sats == out_state.total_paid_sats,
+        ValidationError::UnexpectedFieldChange("total_paid_sats")
+    );
+    ensure!(
+        in_state.prepaid_cycles == out_state.prepaid_cycles,
+        ValidationError::UnexpectedFieldChange("prepaid_cycles")
+    );
+    ensure!(
+        in_state.failed_attempts == out_state.failed_attempts,
+        ValidationError::UnexpectedFieldChange("failed_attempts")
+    );
+    ensure!(
+        in_state.seat_credit_sats == out_state.seat_credit_sats,
+        ValidationError::UnexpectedFieldChange("seat_credit_sats")
+    );
+    ensure!(
+        in_state.merchant_withdrawable_sats == out_state.merchant_withdrawable_sats,
+        ValidationError::UnexpectedFieldChange("merchant_withdrawable_sats")
+    );
+    Ok(())
+}
+
+// Cancellations, pauses, resumes and reversals cannot follow each other within
+// `COOLDOWN_BLOCKS`, so the state cannot be toggled back and forth to grief the other party
+// (payments are not state changes and are never held back)
+fn ensure_cooldown_elapsed(
+    in_state: &MinimalSubscriptionState,
+    current_block: u32,
+) -> Result<(), ValidationError> {
+    let until = in_state
+        .last_state_change_block
+        .saturating_add(COOLDOWN_BLOCKS);
+    ensure!(
+        current_block >= until,
+        ValidationError::CooldownNotElapsed { until }
+    );
+    Ok(())
+}
+
+// Validate top-up - only the remaining balance may change, backed by newly minted tokens
+fn can_top_up_subscription(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+) -> Result<(), ValidationError> {
+    // 1. Subscription must be active and stay active
+    ensure!(in_state.is_active, ValidationError::SubscriptionInactive);
+    ensure!(out_state.is_active, ValidationError::UnexpectedDeactivation);
+
+    // 2. Immutable fields should remain the same
+    ensure_immutable_fields(in_state, out_state)?;
+
+    // 3. Nothing but the balance may change
+    ensure!(
+        in_state.paused == out_state.paused,
+        ValidationError::UnexpectedFieldChange("paused")
+    );
+    ensure_billing_unchanged(in_state, out_state)?;
+
+    // 4. Remaining balance must strictly increase, staying within the cap
+    ensure!(
+        out_state.remaining_balance > in_state.remaining_balance,
+        ValidationError::BalanceNotIncreased
+    );
+    out_state.validate_amount_cap()?;
+    let top_up_amount = sub_or_reject(out_state.remaining_balance, in_state.remaining_balance)?;
+
+    // 5. Exactly the added balance is minted as new locked tokens
+    let minted = sub_or_reject(scan.output_tokens, scan.input_tokens)?;
+    ensure!(
+        minted == top_up_amount,
+        ValidationError::AmountMismatch {
+            expected: top_up_amount,
+            actual: minted
+        }
+    );
+
+    // 6. and backed, like the minted supply, by native value newly locked with the subscription
+    ensure_newly_locked(token_app, scan, top_up_amount)
+}
+
+// Validate migration - a legacy NftContent state is upgraded in place to the full state
+fn can_migrate_subscription(
+    legacy: &NftContent,
+    out_state: &MinimalSubscriptionState,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The legacy state must identify its subscription by the hash of the terms migrated to;
+    // a human-readable id commits to no terms, so those states cannot be migrated
+    let subscription_id = legacy
+        .subscription_id()
+        .ok_or(ValidationError::InvalidTicker)?;
+    let terms = witness
+        .migration
+        .as_ref()
+        .ok_or(ValidationError::MissingMigrationTerms)?;
+    ensure!(
+        subscription_id == terms.subscription_id(),
+        ValidationError::InvalidTicker
+    );
+
+    // 2. The balance carries over unchanged
+    ensure!(
+        out_state.remaining_balance == legacy.remaining,
+        ValidationError::AmountMismatch {
+            expected: legacy.remaining,
+            actual: out_state.remaining_balance
+        }
+    );
+
+    // 3. The upgraded state is exactly the active subscription described by those terms,
+    // with the billing schedule starting at the migration block
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    let expected = MinimalSubscriptionState::builder()
+        .subscription_id(subscription_id)
+        .payer_pubkey(terms.payer_pubkey.clone())
+        .merchant_pubkey(terms.merchant_pubkey.clone())
+        .amount_sats(terms.amount_sats)
+        .billing_interval_blocks(terms.billing_interval_blocks)
+        .last_payment_block(current_block)
+        .remaining_balance(legacy.remaining)
+        .build()?;
+    ensure!(
+        out_state == &expected,
+        ValidationError::UnexpectedFieldChange("migrated state")
+    );
+
+    // 4. Locked tokens stay locked (not minted/burned)
+    ensure_tokens_conserved(scan)?;
+    Ok(())
+}
+
+// Validate cancellation - only payer can cancel
+fn validate_subscription_cancellation(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. Subscription must be active to cancel, or deactivated by failed attempts or a lapse
+    ensure!(
+        in_state.is_active || in_state.is_dunning_exhausted() || is_lapse_recorded(in_state),
+        ValidationError::SubscriptionInactive
+    );
+
+    // 2. After cancellation, is_active should be false
+    ensure!(!out_state.is_active, ValidationError::InvalidNftState);
+
+    // 3. Remaining balance should be zero
+    ensure!(
+        out_state.remaining_balance == 0,
+        ValidationError::AmountMismatch {
+            expected: 0,
+            actual: out_state.remaining_balance
+        }
+    );
+
+    // 4. Immutable fields should remain the same, and accrued payments are settled
+    ensure_immutable_fields(in_state, out_state)?;
+    ensure!(
+        out_state.merchant_withdrawable_sats == 0,
+        ValidationError::UnexpectedFieldChange("merchant_withdrawable_sats")
+    );
+
+    // 5. The merchant keeps the prorated part of the current cycle and its accrued payments,
+    // the payer is owed the rest
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure_cooldown_elapsed(in_state, current_block)?;
+    // A lapsed subscription owes nothing for the cycle it never paid
+    let prorated = if is_lapse_recorded(in_state) {
+        0
+    } else {
+        in_state.prorated_charge(current_block)
+    };
+    // Cancelling within the commitment costs the termination fee, out of the refund
+    let termination_fee = if in_state.cycles_paid < in_state.min_commitment_cycles {
+        in_state
+            .early_termination_fee_sats
+            .min(in_state.remaining_balance - prorated)
+    } else {
+        0
+    };
+    let refund = in_state.remaining_balance - prorated - termination_fee;
+    let merchant_share = add_or_reject(prorated, in_state.merchant_withdrawable_sats)?;
+    let merchant_share = add_or_reject(merchant_share, termination_fee)?;
+    let paid_to_merchant = tokens_paid_to(token_app, scan.tx, &in_state.merchant_pubkey);
+    ensure!(
+        termination_fee == 0 || paid_to_merchant >= merchant_share,
+        ValidationError::CommitmentNotMet {
+            required: in_state.min_commitment_cycles,
+            paid: in_state.cycles_paid
+        }
+    );
+    ensure!(
+        paid_to_merchant >= merchant_share,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: merchant_share,
+            actual: paid_to_merchant
+        }
+    );
+
+    // 6. The refund is either paid to the payer or burned, as the witness says
+    match witness.cancellation_mode {
+        CancellationMode::Refund => {
+            // Tokens are moved, not minted/burned
+            ensure_tokens_conserved(scan)?;
+            let refunded = tokens_paid_to(token_app, scan.tx, &in_state.payer_pubkey);
+            ensure!(
+                refunded >= refund,
+                ValidationError::Underpaid {
+                    recipient: "payer",
+                    expected: refund,
+                    actual: refunded
+                }
+            );
+        }
+        CancellationMode::Burn => {
+            // Exactly the refund disappears from the outputs
+            let expected = sub_or_reject(scan.input_tokens, refund)?;
+            ensure!(
+                scan.output_tokens == expected,
+                ValidationError::AmountMismatch {
+                    expected,
+                    actual: scan.output_tokens
+                }
+            );
+        }
+    }
+
+    // Note: Payer authorization would be validated by checking the transaction inputs
+    // This requires access to the transaction's input scripts, which is handled by Bitcoin
+    // The contract assumes only the payer can spend the UTXO
+
+    Ok(())
+}
+
+// Validate mark lapsed - once the grace period of the next payment is over, the subscription is
+// recorded as inactive without moving funds; its balance is released by a later cancellation
+fn validate_mark_lapsed(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The grace period of the next payment is over
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(
+        in_state.is_lapsed(current_block),
+        ValidationError::SubscriptionNotLapsed
+    );
+
+    // 2. Only the active flag changes, and the lapse is recorded
+    let expected = MinimalSubscriptionState {
+        is_active: false,
+        lapsed: true,
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("lapsed state")
+    );
+
+    // 3. No funds move: every token stays locked with the subscription
+    ensure_tokens_conserved(scan)?;
+    assert_tokens_locked(token_app, scan.nft_app, scan.tx, out_state.locked_sats())
+}
+
+// Validate merchant withdrawal - the payments accrued so far are paid out to the merchant at
+// once, while the remaining balance stays locked
+fn validate_merchant_withdrawal(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+) -> Result<(), ValidationError> {
+    // 1. Only the accrued payments are zeroed, nothing else changes
+    let expected = MinimalSubscriptionState {
+        merchant_withdrawable_sats: 0,
+        nonce: in_state.next_nonce()?,
+        ..in_state.clone()
+    };
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("withdrawn state")
+    );
+
+    // 2. The merchant receives exactly the accrued payments, not a sat of the balance
+    let accrued = in_state.merchant_withdrawable_sats;
+    let withdrawn = tokens_paid_to(token_app, scan.tx, &in_state.merchant_pubkey);
+    ensure!(
+        withdrawn <= accrued,
+        ValidationError::WithdrawalExceedsAccrued { accrued, withdrawn }
+    );
+    ensure!(
+        withdrawn == accrued,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: accrued,
+            actual: withdrawn
+        }
+    );
+
+    // 3. Tokens are moved (not minted/burned) and the balance stays locked
+    ensure_tokens_conserved(scan)?;
+    assert_tokens_locked(token_app, scan.nft_app, scan.tx, out_state.locked_sats())
+}
+
+// Validate expired refund - once a subscription expires, the payer reclaims the whole
+// remaining balance, whether or not the subscription is still active
+fn validate_expired_refund(
+    in_state: &MinimalSubscriptionState,
+    out_state: &MinimalSubscriptionState,
+    token_app: &App,
+    scan: &TxScan,
+    witness: &SubscriptionWitness,
+) -> Result<(), ValidationError> {
+    // 1. The expiry must have passed
+    let current_block = witness
+        .current_block
+        .ok_or(ValidationError::MissingBlockHeight)?;
+    ensure!(
+        in_state.expiry_block != 0 && current_block > in_state.expiry_block,
+        ValidationError::SubscriptionNotExpired
+    );
+
+    // 2. The subscription ends with nothing left locked
+    ensure!(!out_state.is_active, ValidationError::InvalidNftState);
+    ensure!(
+        out_state.remaining_balance == 0,
+        ValidationError::AmountMismatch {
+            expected: 0,
+            actual: out_state.remaining_balance
+        }
+    );
+
+    // 3. Nothing else changes
+    let expected = in_state.cancel()?;
+    ensure_immutable_fields(&expected, out_state)?;
+    ensure!(
+        *out_state == expected,
+        ValidationError::UnexpectedFieldChange("refunded state")
+    );
+
+    // 4. Every released token goes back to the payer
+    ensure_tokens_conserved(scan)?;
+    let refunded = tokens_paid_to(token_app, scan.tx, &in_state.payer_pubkey);
+    ensure!(
+        refunded >= in_state.remaining_balance,
+        ValidationError::Underpaid {
+            recipient: "payer",
+            expected: in_state.remaining_balance,
+            actual: refunded
+        }
+    );
+    // Payments accrued before the expiry are still the merchant's
+    let paid_to_merchant = tokens_paid_to(token_app, scan.tx, &in_state.merchant_pubkey);
+    ensure!(
+        paid_to_merchant >= in_state.merchant_withdrawable_sats,
+        ValidationError::Underpaid {
+            recipient: "merchant",
+            expected: in_state.merchant_withdrawable_sats,
+            actual: paid_to_merchant
+        }
+    );
+
+    Ok(())
+}
+
+/// Helpers shared by the tests
+#[cfg(test)]
+mod test_util {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    /// Fluent builder for subscription transactions
+    /// Inputs are assigned distinct UTXO ids in order; NFT and token outputs are sent to the
+    /// holder (unaddressed unless set), and native outputs are only attached once an output is
+    /// addressed
+    pub(crate) struct TxBuilder {
+        nft_app: App,
+        token_app: App,
+        holder: Vec<u8>,
+        ins: Vec<Charms>,
+        outs: Vec<(Charms, Vec<u8>)>,
+    }
+
+    impl TxBuilder {
+        /// Transactions of the subscription NFT `nft_app` and its sibling token
+        pub fn new(nft_app: &App) -> Self {
+            TxBuilder {
+                nft_app: nft_app.clone(),
+                token_app: sibling_app(nft_app, TOKEN),
+                holder: vec![],
+                ins: vec![],
+                outs: vec![],
+            }
+        }
+
+        /// Use `token_app` instead of the subscription's own token for the token charms added next
+        pub fn with_token(mut self, token_app: &App) -> Self {
+            self.token_app = token_app.clone();
+            self
+        }
+
+        /// Send the NFT and token outputs added next to the output script of `holder`
+        pub fn held_by(mut self, holder: &str) -> Self {
+            self.holder = recipient_script(holder).unwrap();
+            self
+        }
+
+        /// Spend an input holding the subscription NFT in `state`
+        pub fn nft_input<T: Serialize>(mut self, state: &T) -> Self {
+            let charms = Charms::from([(self.nft_app.clone(), Data::from(state))]);
+            self.ins.push(charms);
+            self
+        }
+
+        /// Spend an input holding `amount` subscription tokens
+        pub fn token_input(mut self, amount: u64) -> Self {
+            let charms = Charms::from([(self.token_app.clone(), Data::from(&amount))]);
+            self.ins.push(charms);
+            self
+        }
+
+        /// Create an output holding the subscription NFT in `state`
+        pub fn nft_output<T: Serialize>(mut self, state: &T) -> Self {
+            let charms = Charms::from([(self.nft_app.clone(), Data::from(state))]);
+            self.outs.push((charms, self.holder.clone()));
+            self
+        }
+
+        /// Create an output holding `amount` subscription tokens
+        pub fn token_output(mut self, amount: u64) -> Self {
+            let charms = Charms::from([(self.token_app.clone(), Data::from(&amount))]);
+            self.outs.push((charms, self.holder.clone()));
+            self
+        }
+
+        /// Create an output paying `amount` tokens to the output script of `recipient`
+        pub fn payout(mut self, recipient: &str, amount: u64) -> Self {
+            let charms = Charms::from([(self.token_app.clone(), Data::from(&amount))]);
+            self.outs
+                .push((charms, recipient_script(recipient).unwrap()));
+            self
+        }
+
+        pub fn build(self) -> Transaction {
+            let ins = self
+                .ins
+                .into_iter()
+                .enumerate()
+                .map(|(i, charms)| {
+                    let utxo_id = UtxoId::from_str(&format!(
+                        "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:{}",
+                        i
+                    ))
+                    .unwrap();
+                    (utxo_id, charms)
+                })
+                .collect();
+            let addressed = self.outs.iter().any(|(_, dest)| !dest.is_empty());
+            let (outs, dests): (Vec<_>, Vec<_>) = self.outs.into_iter().unzip();
+            let coin_outs = addressed.then(|| {
+                dests
+                    .into_iter()
+                    .map(|dest| NativeOutput {
+                        amount: if dest.is_empty() { 0 } else { 546 },
+                        dest,
+                    })
+                    .collect()
+            });
+            Transaction {
+                ins,
+                refs: vec![],
+                outs,
+                coin_ins: None,
+                coin_outs,
+                prev_txs: BTreeMap::new(),
+                app_public_inputs: BTreeMap::new(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_util::TxBuilder;
+    use super::*;
+    use charms_sdk::data::{App, Charms, Data, NativeOutput, Transaction, UtxoId, B32, NFT, TOKEN};
+    use std::collections::BTreeMap;
+
+    const PAYER: &str = "021111111111111111111111111111111111111111111111111111111111111111";
+    const MERCHANT: &str = "032222222222222222222222222222222222222222222222222222222222222222";
+    const PLATFORM: &str = "034444444444444444444444444444444444444444444444444444444444444444";
+    const STRANGER: &str = "023333333333333333333333333333333333333333333333333333333333333333";
+    // Holds the subscription NFT and its locked balance between transactions
+    const HOLDER: &str = "025555555555555555555555555555555555555555555555555555555555555555";
+
+    fn test_apps() -> (App, App) {
+        let identity = B32([1; 32]);
+        let vk = B32([2; 32]);
+        let nft_app = App {
+            tag: NFT,
+            identity: identity.clone(),
+            vk: vk.clone(),
+        };
+        let token_app = App {
+            tag: TOKEN,
+            identity,
+            vk,
+        };
+        (nft_app, token_app)
+    }
+
+    fn active_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            payer_pubkey: PAYER.to_string(),
+            merchant_pubkey: MERCHANT.to_string(),
+            amount_sats: 100000,
+            billing_interval_blocks: 144,
+            last_payment_block: 850000,
+            is_active: true,
+            remaining_balance: 1000000,
+            contract_version: CONTRACT_VERSION,
+            subscription_id: "sub_001".to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn charms(entries: &[(&App, Data)]) -> Charms {
+        entries
+            .iter()
+            .map(|(app, data)| ((*app).clone(), data.clone()))
+            .collect()
+    }
+
+    fn test_tx(ins: Vec<Charms>, outs: Vec<Charms>) -> Transaction {
+        let ins = ins
+            .into_iter()
+            .enumerate()
+            .map(|(i, c)| {
+                let utxo_id = UtxoId::from_str(&format!(
+                    "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:{}",
+                    i
+                ))
+                .unwrap();
+                (utxo_id, c)
+            })
+            .collect();
+        Transaction {
+            ins,
+            refs: vec![],
+            outs,
+            coin_ins: None,
+            coin_outs: None,
+            prev_txs: BTreeMap::new(),
+            app_public_inputs: BTreeMap::new(),
+        }
+    }
+
+    fn block_witness(current_block: u32) -> Data {
+        Data::from(&SubscriptionWitness {
+            current_block: Some(current_block),
+            ..Default::default()
+        })
+    }
+
+    // Append an output paying `amount` tokens to the output script of `recipient`
+    fn add_payout(tx: &mut Transaction, recipient: &str, amount: u64) {
+        let (_, token_app) = test_apps();
+        let unaddressed = NativeOutput {
+            amount: 0,
+            dest: vec![],
+        };
+        let outs_len = tx.outs.len();
+        let coin_outs = tx.coin_outs.get_or_insert_with(Vec::new);
+        coin_outs.resize(outs_len, unaddressed);
+        coin_outs.push(NativeOutput {
+            amount: 546,
+            dest: recipient_script(recipient).unwrap(),
+        });
+        tx.outs.push(charms(&[(&token_app, Data::from(&amount))]));
+    }
+
+    // Build a payment tx for `in_state` -> `out_state` paying the merchant the balance decrement
+    fn payment_tx(
+        in_state: &MinimalSubscriptionState,
+        out_state: &MinimalSubscriptionState,
+    ) -> Transaction {
+        let payment = in_state.remaining_balance - out_state.remaining_balance;
+        let mut tx = transition_tx(
+            in_state,
+            out_state,
+            in_state.remaining_balance,
+            out_state.remaining_balance,
+        );
+        add_payout(&mut tx, MERCHANT, payment);
+        tx
+    }
+
+    // Build a tx moving the subscription NFT from `in_state` to `out_state`, with the given
+    // token amounts on the input and output side.
+    fn transition_tx<T: Serialize>(
+        in_state: &T,
+        out_state: &T,
+        tokens_in: u64,
+        tokens_out: u64,
+    ) -> Transaction {
+        let (nft_app, token_app) = test_apps();
+        let mut tx = test_tx(
+            vec![
+                charms(&[(&nft_app, Data::from(in_state))]),
+                charms(&[(&token_app, Data::from(&tokens_in))]),
+            ],
+            vec![
+                charms(&[(&nft_app, Data::from(out_state))]),
+                charms(&[(&token_app, Data::from(&tokens_out))]),
+            ],
+        );
+        hold_outputs(&mut tx);
+        tx
+    }
+
+    // Send all outputs of `tx` so far to the holder of the subscription NFT
+    fn hold_outputs(tx: &mut Transaction) {
+        let held = NativeOutput {
+            amount: 546,
+            dest: recipient_script(HOLDER).unwrap(),
+        };
+        tx.coin_outs = Some(vec![held; tx.outs.len()]);
+    }
+
+    // Hold the inputs of a `transition_tx` like its outputs, and lock `amount` more native value
+    // with the tokens it outputs
+    fn fund(tx: &mut Transaction, amount: u64) {
+        tx.coin_ins = tx.coin_outs.clone();
+        if let Some(token_output) = tx.coin_outs.as_mut().and_then(|coins| coins.get_mut(1)) {
+            token_output.amount += amount;
+        }
+    }
+
+    #[test]
+    fn test_hash() {
+        let utxo_id =
+            UtxoId::from_str("dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1")
+                .unwrap();
+        let data = dbg!(utxo_id.to_string());
+        // sha256("charmpay-v1:" || utxo_id)
+        let expected = "cdb23bed5274e443db31bf966fb49f30102864bb8e3d118bed21b10f21803bd6";
+        assert_eq!(&hash(&data).to_string(), expected);
+        assert_eq!(&hash_with(HashAlgo::Sha256, &data).to_string(), expected);
+    }
+
+    #[test]
+    fn test_hash_bytes_matches_utf8_hash() {
+        let data = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1";
+        assert_eq!(hash_bytes(data.as_bytes()), hash(data));
+        for algo in [HashAlgo::Sha256, HashAlgo::Blake3, HashAlgo::Keccak256] {
+            assert_eq!(
+                hash_bytes_with(algo, data.as_bytes()),
+                hash_with(algo, data)
+            );
+        }
+        // Bytes that are not UTF-8 hash without being stringified first
+        assert_ne!(hash_bytes(&[0xff, 0xfe]), hash_bytes(&[0xff]));
+    }
+
+    #[test]
+    fn test_add_or_reject() {
+        assert_eq!(add_or_reject(u64::MAX - 1, 1), Ok(u64::MAX));
+        assert_eq!(add_or_reject(u64::MAX, 1), Err(ValidationError::Overflow));
+    }
+
+    #[test]
+    fn test_sub_or_reject() {
+        assert_eq!(sub_or_reject(1, 1), Ok(0));
+        assert_eq!(sub_or_reject(0, 1), Err(ValidationError::Underflow));
+    }
+
+    #[test]
+    fn test_mul_or_reject() {
+        assert_eq!(mul_or_reject(u64::MAX / 2, 2), Ok(u64::MAX - 1));
+        assert_eq!(
+            mul_or_reject(u64::MAX / 2 + 1, 2),
+            Err(ValidationError::Overflow)
+        );
+    }
+
+    #[test]
+    fn test_charm_counts_match_collected_values() {
+        let (nft_app, token_app) = test_apps();
+        let state = active_state();
+        let tx = TxBuilder::new(&nft_app)
+            .nft_output(&state)
+            .nft_output(&state)
+            .token_output(600000)
+            .payout(MERCHANT, 400000)
+            .build();
+        let collected_nfts = charm_values(&nft_app, tx.outs.iter()).collect::<Vec<_>>();
+        let collected_tokens = charm_values(&token_app, tx.outs.iter()).collect::<Vec<_>>();
+        assert_eq!(collected_nfts.len(), 2);
+        assert_eq!(collected_tokens.len(), 2);
+
+        assert_eq!(
+            count_nft_charms(&nft_app, tx.outs.iter()),
+            collected_nfts.len()
+        );
+        assert_eq!(
+            count_token_charms(&token_app, tx.outs.iter()),
+            collected_tokens.len()
+        );
+        assert_eq!(count_nft_charms(&nft_app, [].iter()), 0);
+    }
+
+    #[test]
+    fn test_canonical_bytes_of_equal_states() {
+        let built = MinimalSubscriptionState::builder()
+            .remaining_balance(1000000)
+            .billing_interval_blocks(144)
+            .last_payment_block(850000)
+            .amount_sats(100000)
+            .merchant_pubkey(MERCHANT)
+            .payer_pubkey(PAYER)
+            .subscription_id("sub_001")
+            .build()
+            .unwrap();
+        // Decoded from charm data, which keys fields by their short names
+        let decoded: MinimalSubscriptionState = Data::from(&active_state()).value().unwrap();
+        for state in [&built, &decoded] {
+            assert_eq!(state.canonical_bytes(), active_state().canonical_bytes());
+            assert_eq!(state.state_hash(), active_state().state_hash());
+        }
+
+        // Field boundaries cannot shift between strings
+        let shifted = MinimalSubscriptionState {
+            payer_pubkey: format!("{PAYER}0"),
+            merchant_pubkey: MERCHANT[1..].to_string(),
+            ..active_state()
+        };
+        assert_ne!(shifted.canonical_bytes(), active_state().canonical_bytes());
+        assert_ne!(paid_state(&built, 850144).state_hash(), built.state_hash());
+    }
+
+    #[test]
+    fn test_ct_eq_matches_eq() {
+        let hashes = [hash("a"), hash("b"), B32([0; 32]), B32([0xff; 32])];
+        let mut last_byte_differs = B32([0; 32]);
+        last_byte_differs.0[31] = 1;
+        for a in hashes.iter().chain([&last_byte_differs]) {
+            for b in hashes.iter().chain([&last_byte_differs]) {
+                assert_eq!(ct_eq(a, b), a == b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_hash_is_domain_separated() {
+        let bare = B32(Sha256::digest("x").into());
+        assert_ne!(hash("x"), bare);
+    }
+
+    #[test]
+    fn test_hash_algos_produce_distinct_identities() {
+        let data = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1";
+        let sha256 = hash_with(HashAlgo::Sha256, data);
+        let blake3 = hash_with(HashAlgo::Blake3, data);
+        let keccak256 = hash_with(HashAlgo::Keccak256, data);
+
+        assert_ne!(sha256, blake3);
+        assert_ne!(sha256, keccak256);
+        assert_ne!(blake3, keccak256);
+    }
+
+    #[test]
+    fn test_subscription_state_to_nft_content() {
+        let state = SubscriptionState {
+            subscription_id: "sub_001".to_string(),
+            recipient: "bc1qtest".to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 1000000,
+            total_locked: 1000000,
+        };
+
+        let nft_content: NftContent = state.into();
+        assert_eq!(nft_content.ticker, "SUBSCRIPTION-sub_001");
+        assert_eq!(nft_content.remaining, 1000000);
+    }
+
+    #[test]
+    fn test_from_legacy_state() {
+        let legacy = SubscriptionState {
+            subscription_id: "sub_001".to_string(),
+            recipient: MERCHANT.to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 900000,
+            total_locked: 1000000,
+        };
+        let state =
+            MinimalSubscriptionState::from_legacy(&legacy, PAYER.into(), MERCHANT.into(), 144)
+                .unwrap();
+        assert_eq!(
+            state,
+            MinimalSubscriptionState {
+                last_payment_block: 0,
+                remaining_balance: 900000,
+                total_paid_sats: 100000,
+                ..active_state()
+            }
+        );
+    }
+
+    #[test]
+    fn test_subscription_id_is_immutable() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            subscription_id: "sub_002".to_string(),
+            ..paid_state(&in_state, 850144)
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(850144)
+            ),
+            Err(ValidationError::ImmutableFieldChanged("subscription_id"))
+        );
+    }
+
+    #[test]
+    fn test_from_legacy_balance_above_locked() {
+        let legacy = SubscriptionState {
+            subscription_id: "sub_001".to_string(),
+            recipient: MERCHANT.to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 1000001,
+            total_locked: 1000000,
+        };
+        assert_eq!(
+            MinimalSubscriptionState::from_legacy(&legacy, PAYER.into(), MERCHANT.into(), 144),
+            Err(ValidationError::BalanceExceedsLocked {
+                remaining: 1000001,
+                locked: 1000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_make_ticker() {
+        assert_eq!(
+            make_ticker("sub_001"),
+            Ok("SUBSCRIPTION-sub_001".to_string())
+        );
+
+        // Over-long ids keep their start and a hash suffix, at the maximum length
+        let long_id = "a".repeat(100);
+        let ticker = make_ticker(&long_id).unwrap();
+        assert_eq!(ticker.len(), MAX_TICKER_LEN);
+        assert!(ticker.starts_with(&format!("{TICKER_PREFIX}{}.", "a".repeat(50))));
+        assert!(ticker.ends_with(&hash(&long_id).to_string()[..16]));
+        assert_ne!(ticker, make_ticker(&"a".repeat(101)).unwrap());
+        // An id that fits exactly is kept whole
+        let fitting = "a".repeat(MAX_TICKER_LEN - TICKER_PREFIX.len());
+        assert_eq!(
+            make_ticker(&fitting),
+            Ok(format!("{TICKER_PREFIX}{fitting}"))
+        );
+    }
+
+    #[test]
+    fn test_make_ticker_rejects_invalid_characters() {
+        for id in ["", "sub 001", "sub.001", "sub/001", "süb"] {
+            assert_eq!(make_ticker(id), Err(ValidationError::InvalidSubscriptionId));
+        }
+        // Converted legacy states still get a valid ticker
+        let content = NftContent::from(SubscriptionState {
+            subscription_id: "sub 001".to_string(),
+            recipient: MERCHANT.to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 1000000,
+            total_locked: 1000000,
+        });
+        assert_eq!(
+            Ok(content.ticker),
+            make_ticker(&hash("sub 001").to_string())
+        );
+    }
+
+    #[test]
+    fn test_minimal_subscription_state() {
+        let state = MinimalSubscriptionState {
+            payer_pubkey: "02abc123...".to_string(),
+            merchant_pubkey: "03def456...".to_string(),
+            amount_sats: 100000,
+            billing_interval_blocks: 144,
+            last_payment_block: 850000,
+            is_active: true,
+            remaining_balance: 1000000,
+            ..Default::default()
+        };
+
+        assert_eq!(state.amount_sats, 100000);
+        assert!(state.is_active);
+    }
+
+    #[test]
+    fn test_payment_validation() {
+        let in_state = MinimalSubscriptionState {
+            payer_pubkey: "02abc...".to_string(),
+            merchant_pubkey: "03def...".to_string(),
+            amount_sats: 100000,
+            billing_interval_blocks: 144,
+            last_payment_block: 850000,
+            is_active: true,
+            remaining_balance: 1000000,
+            ..Default::default()
+        };
+
+        let out_state = MinimalSubscriptionState {
+            payer_pubkey: "02abc...".to_string(),
+            merchant_pubkey: "03def...".to_string(),
+            amount_sats: 100000,
+            billing_interval_blocks: 144,
+            last_payment_block: 850100, // Updated
+            is_active: true,
+            remaining_balance: 900000, // Decreased by amount_sats
+            ..Default::default()
+        };
+
+        // Payment amount should match
+        assert_eq!(
+            in_state.remaining_balance - out_state.remaining_balance,
+            in_state.amount_sats
+        );
+
+        // Immutable fields should match
+        assert_eq!(in_state.payer_pubkey, out_state.payer_pubkey);
+        assert_eq!(in_state.merchant_pubkey, out_state.merchant_pubkey);
+        assert_eq!(in_state.amount_sats, out_state.amount_sats);
+    }
+
+    #[test]
+    fn test_valid_cancellation() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            is_active: false,
+            remaining_balance: 0,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let tx = TxBuilder::new(&nft_app)
+            .nft_input(&in_state)
+            .token_input(1000000)
+            .nft_output(&out_state)
+            .token_output(0)
+            .payout(PAYER, 1000000)
+            .build();
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Ok(())
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &block_witness(850000)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_cancellation_keeping_tokens() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.cancel().unwrap();
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Err(ValidationError::Underpaid {
+                recipient: "payer",
+                expected: 1000000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_cancellation_refund_to_wrong_key() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.cancel().unwrap();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        add_payout(&mut tx, STRANGER, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Err(ValidationError::Underpaid {
+                recipient: "payer",
+                expected: 1000000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_cancel_already_inactive_subscription() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            is_active: false,
+            ..active_state()
+        };
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 0,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::SubscriptionInactive)
+        );
+    }
+
+    // Active subscription last paid at block 850000, due at 850144 with 12 blocks of grace
+    fn graced_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            grace_period_blocks: 12,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_is_lapsed_at_grace_boundary() {
+        let state = graced_state();
+        assert!(!state.is_lapsed(850144));
+        assert!(!state.is_lapsed(850156));
+        assert!(state.is_lapsed(850157));
+        // The next payment can be recorded up to the last block the predicate still allows
+        let paid = state.apply_payment(850156).unwrap();
+        assert_eq!(paid.last_payment_block, 850156);
+        assert_eq!(
+            state.apply_payment(850157),
+            Err(ValidationError::SubscriptionLapsed)
+        );
+    }
+
+    #[test]
+    fn test_mark_lapsed() {
+        let (_, token_app) = test_apps();
+        let in_state = graced_state();
+        let lapsed = MinimalSubscriptionState {
+            is_active: false,
+            lapsed: true,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &lapsed, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850157)),
+            Ok(())
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850156)),
+            Err(ValidationError::SubscriptionNotLapsed)
+        );
+        // No funds move with the lapse
+        let drained = transition_tx(&in_state, &lapsed, 1000000, 900000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &drained, &block_witness(850157)),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000000,
+                actual: 900000
+            })
+        );
+
+        // The balance is then released by cancelling, the merchant owed nothing for the lapse
+        let mut tx = transition_tx(&lapsed, &lapsed.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850200)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_cancellation_cannot_change_immutable_fields() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            is_active: false,
+            remaining_balance: 0,
+            merchant_pubkey: "03attacker...".to_string(),
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::ImmutableFieldChanged("merchant_pubkey"))
+        );
+    }
+
+    fn paid_state(in_state: &MinimalSubscriptionState, block: u32) -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            last_payment_block: block,
+            remaining_balance: in_state.remaining_balance - in_state.amount_sats,
+            cycles_paid: in_state.cycles_paid + 1,
+            total_paid_sats: in_state.total_paid_sats + in_state.amount_sats,
+            last_charge_sats: in_state.amount_sats,
+            nonce: in_state.nonce + 1,
+            ..in_state.clone()
+        }
+    }
+
+    #[test]
+    fn test_payment_before_interval_elapses() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks - 1;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+    }
+
+    #[test]
+    fn test_payment_at_interval_boundary() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_block_must_match_witness() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block + 10);
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::BlockHeightMismatch {
+                expected: block,
+                actual: block + 10
+            })
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::MissingBlockHeight)
+        );
+    }
+
+    fn legacy_nft(remaining: u64) -> Data {
+        Data::from(&NftContent {
+            ticker: "SUBSCRIPTION-sub_001".to_string(),
+            remaining,
+        })
+    }
+
+    #[test]
+    fn test_mint_token_without_nft_output() {
+        let (nft_app, token_app) = test_apps();
+        let tx = test_tx(vec![], vec![charms(&[(&token_app, Data::from(&1000u64))])]);
+
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::NoNftInOutput)
+        );
+    }
+
+    #[test]
+    fn test_mint_token_supply_increased() {
+        let (nft_app, token_app) = test_apps();
+        let tx = test_tx(
+            vec![charms(&[(&nft_app, legacy_nft(1000))])],
+            vec![charms(&[(&nft_app, legacy_nft(2000))])],
+        );
+
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::SupplyIncreased)
+        );
+    }
+
+    #[test]
+    fn test_initial_mint_amount_mismatch() {
+        let (nft_app, token_app) = test_apps();
+        let tx = test_tx(
+            vec![],
+            vec![charms(&[
+                (&nft_app, legacy_nft(1000)),
+                (&token_app, Data::from(&1500u64)),
+            ])],
+        );
+
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000,
+                actual: 1500
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_amount_mismatch() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            last_payment_block: block,
+            remaining_balance: in_state.remaining_balance - 2 * in_state.amount_sats,
+            cycles_paid: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let witness = SubscriptionWitness {
+            current_block: Some(block),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            can_execute_subscription_payment(&token_app, &nft_app, &tx, &witness),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 200000
+            })
+        );
+    }
+
+    #[test]
+    fn test_app_contract_maps_errors_to_false() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert!(app_contract(
+            &token_app,
+            &tx,
+            &Data::empty(),
+            &block_witness(block)
+        ));
+        assert!(!app_contract(
+            &token_app,
+            &tx,
+            &Data::empty(),
+            &Data::empty()
+        ));
+    }
+
+    fn test_builder() -> MinimalSubscriptionStateBuilder {
+        MinimalSubscriptionState::builder()
+            .subscription_id("sub_001")
+            .payer_pubkey(PAYER)
+            .merchant_pubkey(MERCHANT)
+            .amount_sats(100000)
+            .billing_interval_blocks(144)
+            .remaining_balance(1000000)
+    }
+
+    #[test]
+    fn test_builder_happy_path() {
+        let state = test_builder().last_payment_block(850000).build().unwrap();
+
+        assert_eq!(state, active_state());
+    }
+
+    #[test]
+    fn test_builder_defaults() {
+        let state = test_builder().build().unwrap();
+
+        assert!(state.is_active);
+        assert_eq!(state.last_payment_block, 0);
+    }
+
+    #[test]
+    fn test_builder_rejects_zero_amount() {
+        assert_eq!(
+            test_builder().amount_sats(0).build(),
+            Err(ValidationError::ZeroAmount)
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_zero_interval() {
+        assert_eq!(
+            test_builder().billing_interval_blocks(0).build(),
+            Err(ValidationError::ZeroInterval)
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_balance_below_one_cycle() {
+        assert_eq!(
+            test_builder().remaining_balance(99999).build(),
+            Err(ValidationError::InsufficientBalance {
+                required: 100000,
+                available: 99999
+            })
+        );
+    }
+
+    #[test]
+    fn test_valid_top_up() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 1500000,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 1500000);
+        fund(&mut tx, 500000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Ok(())
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &Data::empty()),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_top_up_must_increase_balance() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 900000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1100000);
+
+        assert_eq!(
+            can_top_up_subscription(
+                &in_state,
+                &out_state,
+                &token_app,
+                &scan_tx(&token_app, &nft_app, &tx).unwrap()
+            ),
+            Err(ValidationError::BalanceNotIncreased)
+        );
+        assert!(token_contract_satisfied(&token_app, &tx, &Data::empty()).is_err());
+    }
+
+    #[test]
+    fn test_top_up_cannot_change_amount() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 1500000,
+            amount_sats: 50000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1500000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::ImmutableFieldChanged("amount_sats"))
+        );
+    }
+
+    #[test]
+    fn test_top_up_must_mint_exact_delta() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 1500000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1400000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::AmountMismatch {
+                expected: 500000,
+                actual: 400000
+            })
+        );
+    }
+
+    #[test]
+    fn test_top_up_must_be_funded() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 1500000,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 1500000);
+        fund(&mut tx, 499999);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::InsufficientFunding {
+                required: 500000,
+                funded: 499999
+            })
+        );
+    }
+
+    #[test]
+    fn test_pause_subscription() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            paused: true,
+            last_state_change_block: 850000,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let w = block_witness(850000);
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), Ok(()));
+    }
+
+    #[test]
+    fn test_resume_subscription() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            paused: true,
+            ..active_state()
+        };
+        let out_state = MinimalSubscriptionState {
+            paused: false,
+            last_state_change_block: 850000,
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_pause_cannot_move_funds() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            paused: true,
+            remaining_balance: 900000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::UnexpectedFieldChange("remaining_balance"))
+        );
+    }
+
+    #[test]
+    fn test_state_change_cooldown() {
+        let (_, token_app) = test_apps();
+        let paused = MinimalSubscriptionState {
+            paused: true,
+            last_state_change_block: 850000,
+            ..active_state()
+        };
+        let resume_at = |block: u32| {
+            let resumed = MinimalSubscriptionState {
+                paused: false,
+                last_state_change_block: block,
+                nonce: 1,
+                ..paused.clone()
+            };
+            let tx = transition_tx(&paused, &resumed, 1000000, 1000000);
+            token_contract_satisfied(&token_app, &tx, &block_witness(block))
+        };
+        assert_eq!(
+            resume_at(850005),
+            Err(ValidationError::CooldownNotElapsed { until: 850006 })
+        );
+        assert_eq!(resume_at(850006), Ok(()));
+
+        // Cancelling right after a resume is held back too
+        let resumed = MinimalSubscriptionState {
+            paused: false,
+            ..paused.clone()
+        };
+        let mut tx = transition_tx(&resumed, &resumed.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850003)),
+            Err(ValidationError::CooldownNotElapsed { until: 850006 })
+        );
+    }
+
+    #[test]
+    fn test_payment_ignores_cooldown() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            last_state_change_block: 850143,
+            ..active_state()
+        };
+        let tx = payment_tx(&in_state, &paid_state(&in_state, 850144));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_currency_unit_sets_tokens_per_cycle() {
+        let (_, token_app) = test_apps();
+        let w = block_witness(850144);
+        // 600000 of each unit, paid as the tokens it is worth
+        for (unit, tokens) in [
+            (CurrencyUnit::Satoshi, 600000),
+            (CurrencyUnit::MilliSatoshi, 600),
+            (CurrencyUnit::TokenBase, 600000),
+        ] {
+            let in_state = test_builder()
+                .amount_sats(600000)
+                .last_payment_block(850000)
+                .currency_unit(unit)
+                .build()
+                .unwrap();
+            let out_state = in_state.apply_payment(850144).unwrap();
+            assert_eq!(out_state.remaining_balance, 1000000 - tokens);
+            let tx = payment_tx(&in_state, &out_state);
+            assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        }
+
+        // Paying 100000 tokens for 100000 millisatoshis overcharges the payer
+        let in_state = MinimalSubscriptionState {
+            currency_unit: CurrencyUnit::MilliSatoshi,
+            ..active_state()
+        };
+        let tx = payment_tx(&in_state, &paid_state(&in_state, 850144));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::AmountMismatch {
+                expected: 100,
+                actual: 100000
+            })
+        );
+    }
+
+    #[test]
+    fn test_fractional_millisatoshi_amount() {
+        assert_eq!(
+            test_builder()
+                .amount_sats(1000500)
+                .currency_unit(CurrencyUnit::MilliSatoshi)
+                .build(),
+            Err(ValidationError::FractionalAmount { amount: 1000500 })
+        );
+    }
+
+    // Pay the first cycle of a subscription earning 5% a year, recording `remaining_balance`
+    fn yield_payment(remaining_balance: u64) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        token_contract_satisfied(
+            &token_app,
+            &yield_tx(remaining_balance),
+            &block_witness(850144),
+        )
+    }
+
+    fn yield_tx(remaining_balance: u64) -> Transaction {
+        let in_state = MinimalSubscriptionState {
+            annual_yield_bps: 500,
+            ..active_state()
+        };
+        let out_state = MinimalSubscriptionState {
+            remaining_balance,
+            ..in_state.apply_payment(850144).unwrap()
+        };
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, remaining_balance);
+        add_payout(&mut tx, MERCHANT, 100000);
+        tx
+    }
+
+    #[test]
+    fn test_yield_accrues_over_one_interval() {
+        let state = MinimalSubscriptionState {
+            annual_yield_bps: 500,
+            ..active_state()
+        };
+        // 1000000 * 5% * 144 / 52560 blocks, rounded down
+        assert_eq!(state.accrued_yield(850144), Ok(136));
+        assert_eq!(
+            state.apply_payment(850144).unwrap().remaining_balance,
+            900136
+        );
+        assert_eq!(yield_payment(900136), Ok(()));
+
+        // A late payment earns no more than the interval's yield
+        assert_eq!(state.accrued_yield(850288), Ok(136));
+
+        let huge = MinimalSubscriptionState {
+            remaining_balance: u64::MAX,
+            annual_yield_bps: u16::MAX,
+            billing_interval_blocks: u32::MAX,
+            last_payment_block: 0,
+            ..active_state()
+        };
+        assert_eq!(huge.accrued_yield(u32::MAX), Err(ValidationError::Overflow));
+    }
+
+    #[test]
+    fn test_yield_must_be_funded() {
+        let (_, token_app) = test_apps();
+        let mut tx = yield_tx(900136);
+        // The inputs held as much native value as the outputs, so nothing backs the yield
+        let held: u64 = tx.coin_outs.iter().flatten().map(|coin| coin.amount).sum();
+        let coin = |amount| NativeOutput {
+            amount,
+            dest: recipient_script(HOLDER).unwrap(),
+        };
+        tx.coin_ins = Some(vec![coin(held), coin(0)]);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Err(ValidationError::InsufficientFunding {
+                required: 136,
+                funded: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_zero_yield_leaves_payments_unchanged() {
+        let state = active_state();
+        assert_eq!(state.accrued_yield(850144), Ok(0));
+        assert_eq!(state.apply_payment(850144), Ok(paid_state(&state, 850144)));
+    }
+
+    #[test]
+    fn test_overclaimed_yield() {
+        assert_eq!(
+            yield_payment(900137),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 99999
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_while_paused() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            paused: true,
+            ..active_state()
+        };
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::SubscriptionPaused)
+        );
+    }
+
+    fn grace_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            grace_period_blocks: 12,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_payment_within_grace_period() {
+        let (_, token_app) = test_apps();
+        let in_state = grace_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks + 6;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_at_grace_period_edge() {
+        let (_, token_app) = test_apps();
+        let in_state = grace_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks + 12;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_beyond_grace_period() {
+        let (_, token_app) = test_apps();
+        let in_state = grace_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks + 13;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::SubscriptionLapsed)
+        );
+    }
+
+    #[test]
+    fn test_grace_period_is_immutable() {
+        let (_, token_app) = test_apps();
+        let in_state = grace_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks + 100;
+        let out_state = MinimalSubscriptionState {
+            grace_period_blocks: 200,
+            ..paid_state(&in_state, block)
+        };
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::ImmutableFieldChanged(
+                "grace_period_blocks"
+            ))
+        );
+    }
+
+    fn trial_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            trial_end_block: 851000,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_payment_during_trial() {
+        let (_, token_app) = test_apps();
+        let in_state = trial_state();
+        let block = in_state.trial_end_block - 1;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::TrialNotEnded)
+        );
+    }
+
+    #[test]
+    fn test_payment_after_trial_ends() {
+        let (_, token_app) = test_apps();
+        let in_state = trial_state();
+        let block = in_state.trial_end_block;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_trial_end_block_is_immutable() {
+        let (_, token_app) = test_apps();
+        let in_state = trial_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            trial_end_block: 0,
+            ..paid_state(&in_state, block)
+        };
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::ImmutableFieldChanged("trial_end_block"))
+        );
+    }
+
+    fn due_payment() -> (MinimalSubscriptionState, MinimalSubscriptionState, u32) {
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        (in_state, out_state, block)
+    }
+
+    #[test]
+    fn test_payment_pays_merchant() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(tokens_paid_to(&token_app, &tx, MERCHANT), 100000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_to_wrong_key() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 900000);
+        add_payout(&mut tx, STRANGER, 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 100000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_split_merchant_payout() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 900000);
+        add_payout(&mut tx, MERCHANT, 60000);
+        add_payout(&mut tx, MERCHANT, 40000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_mint_token_rejects_token_underflow() {
+        let (nft_app, token_app) = test_apps();
+        let tx = test_tx(
+            vec![
+                charms(&[(&nft_app, legacy_nft(1000))]),
+                charms(&[(&token_app, Data::from(&500u64))]),
+            ],
+            vec![
+                charms(&[(&nft_app, legacy_nft(1000))]),
+                charms(&[(&token_app, Data::from(&100u64))]),
+            ],
+        );
+
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::Underflow)
+        );
+    }
+
+    #[test]
+    fn test_payment_rejects_balance_underflow() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            last_payment_block: block,
+            remaining_balance: u64::MAX,
+            cycles_paid: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let witness = SubscriptionWitness {
+            current_block: Some(block),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            can_execute_subscription_payment(&token_app, &nft_app, &tx, &witness),
+            Err(ValidationError::SupplyIncreased)
+        );
+    }
+
+    #[test]
+    fn test_legacy_payment_rejects_balance_underflow() {
+        let (nft_app, token_app) = test_apps();
+        let tx = test_tx(
+            vec![
+                charms(&[(&nft_app, legacy_nft(1000))]),
+                charms(&[(&token_app, Data::from(&1000u64))]),
+            ],
+            vec![
+                charms(&[(&nft_app, legacy_nft(u64::MAX))]),
+                charms(&[(&token_app, Data::from(&1000u64))]),
+            ],
+        );
+
+        assert_eq!(
+            can_execute_subscription_payment(
+                &token_app,
+                &nft_app,
+                &tx,
+                &SubscriptionWitness::default()
+            ),
+            Err(ValidationError::SupplyIncreased)
+        );
+        assert!(token_contract_satisfied(&token_app, &tx, &Data::empty()).is_err());
+    }
+
+    #[test]
+    fn test_versioned_state_v1_roundtrip() {
+        let data = Data::from(&SubscriptionStateV::V1(NftContent {
+            ticker: "SUBSCRIPTION-sub_001".to_string(),
+            remaining: 1000,
+        }));
+
+        match SubscriptionStateV::parse(&data) {
+            Ok(SubscriptionStateV::V1(content)) => {
+                assert_eq!(content.ticker, "SUBSCRIPTION-sub_001");
+                assert_eq!(content.remaining, 1000);
+            }
+            other => panic!("expected V1 state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_versioned_state_v2_roundtrip() {
+        let data = Data::from(&SubscriptionStateV::V2(active_state()));
+
+        match SubscriptionStateV::parse(&data) {
+            Ok(SubscriptionStateV::V2(state)) => assert_eq!(state, active_state()),
+            other => panic!("expected V2 state, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_untagged_legacy_states_still_parse() {
+        assert!(matches!(
+            SubscriptionStateV::parse(&legacy_nft(1000)),
+            Ok(SubscriptionStateV::V1(NftContent {
+                remaining: 1000,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            SubscriptionStateV::parse(&Data::from(&active_state())),
+            Ok(SubscriptionStateV::V2(_))
+        ));
+        assert_eq!(
+            SubscriptionStateV::parse(&Data::from(&42u64)).unwrap_err(),
+            ValidationError::InvalidNftState
+        );
+    }
+
+    #[test]
+    fn test_unknown_version_rejected() {
+        let mut state = BTreeMap::new();
+        state.insert("version", "V9");
+        state.insert("ticker", "SUBSCRIPTION-sub_001");
+        let data = Data::from(&state);
+
+        assert_eq!(
+            SubscriptionStateV::parse(&data).unwrap_err(),
+            ValidationError::UnknownVersion
+        );
+    }
+
+    // Mint `state` with its balance in tokens, locking all of a funding input worth `funded`
+    // sats with the NFT
+    fn funded_mint_tx(state: &MinimalSubscriptionState, funded: u64) -> Transaction {
+        let (nft_app, token_app) = test_apps();
+        let mut tx = test_tx(
+            vec![charms(&[])],
+            vec![charms(&[
+                (&nft_app, Data::from(&SubscriptionStateV::V2(state.clone()))),
+                (&token_app, Data::from(&state.remaining_balance)),
+            ])],
+        );
+        tx.coin_ins = Some(vec![NativeOutput {
+            amount: funded,
+            dest: recipient_script(PAYER).unwrap(),
+        }]);
+        tx.coin_outs = Some(vec![NativeOutput {
+            amount: funded,
+            dest: recipient_script(HOLDER).unwrap(),
+        }]);
+        tx
+    }
+
+    #[test]
+    fn test_mint_tokens_for_full_state() {
+        let (nft_app, token_app) = test_apps();
+        let tx = funded_mint_tx(&active_state(), 1000000);
+        assert_eq!(can_mint_token(&token_app, &nft_app, &tx), Ok(()));
+    }
+
+    #[test]
+    fn test_duplicate_inputs() {
+        let (nft_app, token_app) = test_apps();
+        let w = block_witness(850144);
+        let in_state = active_state();
+        let mut tx = payment_tx(&in_state, &paid_state(&in_state, 850144));
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+
+        // The NFT-bearing input listed twice
+        tx.ins.push(tx.ins[0].clone());
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::DuplicateInput)
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &w),
+            Err(ValidationError::DuplicateInput)
+        );
+
+        // Nor can a mint spend its funding input twice
+        let mut tx = funded_mint_tx(&active_state(), 1000000);
+        tx.ins.push(tx.ins[0].clone());
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::DuplicateInput)
+        );
+    }
+
+    #[test]
+    fn test_underfunded_mint() {
+        let (nft_app, token_app) = test_apps();
+        let tx = funded_mint_tx(&active_state(), 999999);
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::InsufficientFunding {
+                required: 1000000,
+                funded: 999999
+            })
+        );
+        // Token bookkeeping alone does not fund a mint
+        let mut unfunded = tx;
+        unfunded.coin_outs = None;
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &unfunded),
+            Err(ValidationError::InsufficientFunding {
+                required: 1000000,
+                funded: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_mint_funding_excludes_change() {
+        let (nft_app, token_app) = test_apps();
+        // A large input spent into the mint, most of it returned to the payer as change
+        let mut tx = funded_mint_tx(&active_state(), 1000000);
+        tx.coin_outs.as_mut().unwrap()[0].amount = 1000;
+        tx.outs.push(charms(&[]));
+        tx.coin_outs.as_mut().unwrap().push(NativeOutput {
+            amount: 999000,
+            dest: recipient_script(PAYER).unwrap(),
+        });
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::InsufficientFunding {
+                required: 1000000,
+                funded: 1000
+            })
+        );
+    }
+
+    // Mint a subscription with a 5000 sats setup fee, paying the merchant `setup_fee` sats
+    fn setup_fee_mint(setup_fee: u64) -> Result<(), ValidationError> {
+        let (nft_app, token_app) = test_apps();
+        let state = MinimalSubscriptionState {
+            setup_fee_sats: 5000,
+            ..active_state()
+        };
+        let mut tx = funded_mint_tx(&state, 1000000);
+        tx.coin_ins.as_mut().unwrap()[0].amount += setup_fee;
+        tx.outs.push(charms(&[]));
+        tx.coin_outs.as_mut().unwrap().push(NativeOutput {
+            amount: setup_fee,
+            dest: recipient_script(MERCHANT).unwrap(),
+        });
+        can_mint_token(&token_app, &nft_app, &tx)
+    }
+
+    #[test]
+    fn test_mint_with_setup_fee() {
+        assert_eq!(setup_fee_mint(5000), Ok(()));
+    }
+
+    #[test]
+    fn test_mint_missing_setup_fee() {
+        assert_eq!(
+            setup_fee_mint(0),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 5000,
+                actual: 0
+            })
+        );
+        // Funding the fee is not enough, it must reach the merchant
+        let (nft_app, token_app) = test_apps();
+        let state = MinimalSubscriptionState {
+            setup_fee_sats: 5000,
+            ..active_state()
+        };
+        let tx = funded_mint_tx(&state, 1005000);
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 5000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_cannot_charge_setup_fee() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            setup_fee_sats: 5000,
+            ..active_state()
+        };
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 895000,
+            total_paid_sats: 105000,
+            ..in_state.apply_payment(850144).unwrap()
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(850144)
+            ),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 105000
+            })
+        );
+        // Nor waive it on the way
+        let waived = MinimalSubscriptionState {
+            setup_fee_sats: 0,
+            ..in_state.apply_payment(850144).unwrap()
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &waived),
+                &block_witness(850144)
+            ),
+            Err(ValidationError::ImmutableFieldChanged("setup_fee_sats"))
+        );
+    }
+
+    fn migration_witness(block: u32) -> SubscriptionWitness {
+        SubscriptionWitness {
+            current_block: Some(block),
+            migration: Some(MigrationTerms {
+                payer_pubkey: PAYER.to_string(),
+                merchant_pubkey: MERCHANT.to_string(),
+                amount_sats: 100000,
+                billing_interval_blocks: 144,
+            }),
+            ..Default::default()
+        }
+    }
+
+    // A legacy state of `active_state`'s terms, its ticker id their hash
+    fn hashed_legacy_nft(remaining: u64) -> NftContent {
+        NftContent::from(&MinimalSubscriptionState {
+            subscription_id: String::new(),
+            remaining_balance: remaining,
+            ..active_state()
+        })
+    }
+
+    // `active_state` migrated from `hashed_legacy_nft`, keeping its ticker id
+    fn migrated_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            subscription_id: active_terms().subscription_id(),
+            ..active_state()
+        }
+    }
+
+    fn migration_tx(legacy_remaining: u64, out_state: &MinimalSubscriptionState) -> Transaction {
+        let (nft_app, token_app) = test_apps();
+        test_tx(
+            vec![
+                charms(&[(&nft_app, Data::from(&hashed_legacy_nft(legacy_remaining)))]),
+                charms(&[(&token_app, Data::from(&legacy_remaining))]),
+            ],
+            vec![
+                charms(&[(&nft_app, Data::from(out_state))]),
+                charms(&[(&token_app, Data::from(&legacy_remaining))]),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_valid_migration() {
+        let (nft_app, token_app) = test_apps();
+        let out_state = migrated_state();
+        let tx = migration_tx(1000000, &out_state);
+        let w = Data::from(&migration_witness(850000));
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), Ok(()));
+    }
+
+    #[test]
+    fn test_mixed_state_formats() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = paid_state(&in_state, 850144);
+        let w = block_witness(850144);
+
+        // A legacy state spent alongside the full one
+        let mut tx = payment_tx(&in_state, &out_state);
+        tx.ins.push((
+            UtxoId::from_str("dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:2")
+                .unwrap(),
+            charms(&[(&nft_app, legacy_nft(1000000))]),
+        ));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::MixedStateFormats)
+        );
+
+        // A full state spent into a legacy one
+        let tx = transition_tx(&Data::from(&in_state), &legacy_nft(900000), 1000000, 900000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::MixedStateFormats)
+        );
+
+        // The sanctioned migration is the one change of format
+        let tx = migration_tx(1000000, &migrated_state());
+        let w = Data::from(&migration_witness(850000));
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+    }
+
+    #[test]
+    fn test_contract_version_mismatch() {
+        let (nft_app, token_app) = test_apps();
+        let future_state = MinimalSubscriptionState {
+            contract_version: CONTRACT_VERSION + 1,
+            ..active_state()
+        };
+        let mismatch = Err(ValidationError::ContractVersionMismatch {
+            expected: CONTRACT_VERSION,
+            actual: CONTRACT_VERSION + 1,
+        });
+
+        // Neither minted
+        let tx = funded_mint_tx(&future_state, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            mismatch
+        );
+
+        // Nor spent
+        let tx = payment_tx(&future_state, &paid_state(&future_state, 850144));
+        let w = block_witness(850144);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), mismatch);
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), mismatch);
+
+        // States written before versioning are still accepted
+        let untagged = MinimalSubscriptionState {
+            contract_version: 0,
+            ..active_state()
+        };
+        let tx = payment_tx(&untagged, &paid_state(&untagged, 850144));
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+    }
+
+    #[test]
+    fn test_migration_cannot_inflate_balance() {
+        let (_, token_app) = test_apps();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 5000000,
+            ..migrated_state()
+        };
+        let tx = migration_tx(1000000, &out_state);
+        let w = Data::from(&migration_witness(850000));
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000000,
+                actual: 5000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_migration_requires_terms() {
+        let (_, token_app) = test_apps();
+        let tx = migration_tx(1000000, &migrated_state());
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Err(ValidationError::MissingMigrationTerms)
+        );
+    }
+
+    #[test]
+    fn test_migration_rejects_mismatched_terms() {
+        let (_, token_app) = test_apps();
+
+        // Terms other than those the ticker id was derived from
+        let inflated = MinimalSubscriptionState {
+            amount_sats: 200000,
+            ..migrated_state()
+        };
+        let tx = migration_tx(1000000, &inflated);
+        let mut witness = migration_witness(850000);
+        if let Some(terms) = witness.migration.as_mut() {
+            terms.amount_sats = 200000;
+        }
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::from(&witness)),
+            Err(ValidationError::InvalidTicker)
+        );
+
+        // A human-readable ticker id commits to no terms at all
+        let (nft_app, _) = test_apps();
+        let mut tx = migration_tx(1000000, &active_state());
+        tx.ins[0].1 = charms(&[(&nft_app, legacy_nft(1000000))]);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::from(&migration_witness(850000))),
+            Err(ValidationError::InvalidTicker)
+        );
+    }
+
+    #[test]
+    fn test_legacy_ticker_subscription_id() {
+        let content = NftContent {
+            ticker: "SUBSCRIPTION-sub_001".to_string(),
+            remaining: 0,
+        };
+        let bogus = NftContent {
+            ticker: "SOMETHING-ELSE".to_string(),
+            remaining: 0,
+        };
+
+        assert_eq!(content.subscription_id(), Some("sub_001"));
+        assert_eq!(bogus.subscription_id(), None);
+    }
+
+    fn split_states(
+        splits: Vec<(String, u16)>,
+    ) -> (SplitSubscriptionState, SplitSubscriptionState, u32) {
+        let (in_state, out_state, block) = due_payment();
+        let in_split = SplitSubscriptionState {
+            subscription: in_state,
+            splits,
+        };
+        let out_split = SplitSubscriptionState {
+            subscription: out_state,
+            splits: in_split.splits.clone(),
+        };
+        (in_split, out_split, block)
+    }
+
+    fn split_tx(
+        in_state: &SplitSubscriptionState,
+        out_state: &SplitSubscriptionState,
+        payouts: &[(&str, u64)],
+    ) -> Transaction {
+        let mut tx = transition_tx(
+            &SubscriptionStateV::V3(in_state.clone()),
+            &SubscriptionStateV::V3(out_state.clone()),
+            in_state.subscription.remaining_balance,
+            out_state.subscription.remaining_balance,
+        );
+        for (pubkey, amount) in payouts {
+            add_payout(&mut tx, pubkey, *amount);
+        }
+        tx
+    }
+
+    #[test]
+    fn test_split_subscription_cancellation() {
+        let (nft_app, token_app) = test_apps();
+        let splits = vec![(MERCHANT.to_string(), 7000), (PLATFORM.to_string(), 3000)];
+        let in_split = SplitSubscriptionState {
+            subscription: active_state(),
+            splits: splits.clone(),
+        };
+        let out_split = SplitSubscriptionState {
+            subscription: active_state().cancel().unwrap(),
+            splits,
+        };
+        // At the start of the cycle the whole balance is refunded to the payer
+        let tx = split_tx(&in_split, &out_split, &[(PAYER, 1000000)]);
+        let w = block_witness(850000);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), Ok(()));
+
+        // The shares cannot be rewritten on the way out
+        let rewritten = SplitSubscriptionState {
+            splits: vec![(PAYER.to_string(), 10000)],
+            ..out_split
+        };
+        let tx = split_tx(&in_split, &rewritten, &[(PAYER, 1000000)]);
+        assert!(token_contract_satisfied(&token_app, &tx, &w).is_err());
+    }
+
+    #[test]
+    fn test_split_payment_70_30() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = split_states(vec![
+            (MERCHANT.to_string(), 7000),
+            (PLATFORM.to_string(), 3000),
+        ]);
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 70000), (PLATFORM, 30000)],
+        );
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 80000), (PLATFORM, 20000)],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "split recipient",
+                expected: 30000,
+                actual: 20000
+            })
+        );
+    }
+
+    #[test]
+    fn test_split_must_sum_to_whole_payment() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = split_states(vec![
+            (MERCHANT.to_string(), 7000),
+            (PLATFORM.to_string(), 2000),
+        ]);
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 70000), (PLATFORM, 30000)],
+        );
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::InvalidSplits)
+        );
+    }
+
+    #[test]
+    fn test_split_rounding_tolerance() {
+        let (_, token_app) = test_apps();
+        let (mut in_state, _, block) = split_states(vec![
+            (MERCHANT.to_string(), 5000),
+            (PLATFORM.to_string(), 5000),
+        ]);
+        in_state.subscription.amount_sats = 100001;
+        let out_state = SplitSubscriptionState {
+            subscription: paid_state(&in_state.subscription, block),
+            splits: in_state.splits.clone(),
+        };
+
+        // Each share is 50000.5 sats, floored to 50000; one sat short is still accepted
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 50002), (PLATFORM, 49999)],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 50003), (PLATFORM, 49998)],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "split recipient",
+                expected: 50000,
+                actual: 49998
+            })
+        );
+    }
+
+    #[test]
+    fn test_split_payment_in_millisatoshis_with_platform_fee() {
+        let (_, token_app) = test_apps();
+        let in_state = SplitSubscriptionState {
+            subscription: MinimalSubscriptionState {
+                amount_sats: 100000000,
+                currency_unit: CurrencyUnit::MilliSatoshi,
+                platform_pubkey: PLATFORM.to_string(),
+                platform_fee_sats: 5000,
+                ..active_state()
+            },
+            splits: vec![(MERCHANT.to_string(), 7000), (STRANGER.to_string(), 3000)],
+        };
+        let out_state = SplitSubscriptionState {
+            subscription: in_state.subscription.apply_payment(850144).unwrap(),
+            splits: in_state.splits.clone(),
+        };
+        assert_eq!(out_state.subscription.last_charge_sats, 100000);
+
+        // The 100000 tokens charged pay the platform fee, the rest is split 70/30
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(PLATFORM, 5000), (MERCHANT, 66500), (STRANGER, 28500)],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Ok(())
+        );
+
+        let tx = split_tx(
+            &in_state,
+            &out_state,
+            &[(MERCHANT, 70000), (STRANGER, 30000)],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Err(ValidationError::Underpaid {
+                recipient: "platform",
+                expected: 5000,
+                actual: 0
+            })
+        );
+    }
+
+    fn fee_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            platform_pubkey: PLATFORM.to_string(),
+            platform_fee_sats: 5000,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_platform_fee_deducted_from_payment() {
+        let (_, token_app) = test_apps();
+        let in_state = fee_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        let mut tx = transition_tx(
+            &in_state,
+            &out_state,
+            in_state.remaining_balance,
+            out_state.remaining_balance,
+        );
+        add_payout(&mut tx, MERCHANT, 95000);
+        add_payout(&mut tx, PLATFORM, 5000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_fee_above_amount() {
+        assert_eq!(
+            test_builder()
+                .platform_pubkey(PLATFORM)
+                .platform_fee_sats(100001)
+                .build(),
+            Err(ValidationError::FeeExceedsAmount {
+                fee: 100001,
+                amount: 100000
+            })
+        );
+    }
+
+    #[test]
+    fn test_platform_fee_requires_platform_output() {
+        let (_, token_app) = test_apps();
+        let in_state = fee_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "platform",
+                expected: 5000,
+                actual: 0
+            })
+        );
+    }
+
+    fn metered_states(units: u32) -> (MeteredSubscriptionState, MeteredSubscriptionState, Data) {
+        let in_state = MeteredSubscriptionState {
+            subscription: active_state(),
+            price_per_unit_sats: 250,
+            max_units_per_cycle: 1000,
+            cycle_usage: 0,
+            ..Default::default()
+        };
+        let block = in_state.subscription.last_payment_block
+            + in_state.subscription.billing_interval_blocks;
+        let mut subscription = in_state.subscription.clone();
+        subscription.last_payment_block = block;
+        subscription.remaining_balance -= 250 * units as u64;
+        subscription.cycles_paid += 1;
+        subscription.total_paid_sats += 250 * units as u64;
+        subscription.last_charge_sats = 250 * units as u64;
+        subscription.nonce += 1;
+        let out_state = MeteredSubscriptionState {
+            subscription,
+            cycle_usage: units,
+            ..in_state.clone()
+        };
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            units: Some(units),
+            ..Default::default()
+        });
+        (in_state, out_state, witness)
+    }
+
+    fn metered_tx(
+        in_state: &MeteredSubscriptionState,
+        out_state: &MeteredSubscriptionState,
+    ) -> Transaction {
+        let payment =
+            in_state.subscription.remaining_balance - out_state.subscription.remaining_balance;
+        let mut tx = transition_tx(
+            &SubscriptionStateV::V4(in_state.clone()),
+            &SubscriptionStateV::V4(out_state.clone()),
+            in_state.subscription.remaining_balance,
+            out_state.subscription.remaining_balance,
+        );
+        add_payout(&mut tx, MERCHANT, payment);
+        tx
+    }
+
+    #[test]
+    fn test_metered_payment_charges_usage() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, witness) = metered_states(120);
+        let tx = metered_tx(&in_state, &out_state);
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+
+        // Charging for more usage than reported is rejected
+        let (_, _, witness) = metered_states(100);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::AmountMismatch {
+                expected: 25000,
+                actual: 30000
+            })
+        );
+    }
+
+    #[test]
+    fn test_metered_payment_in_millisatoshis() {
+        let (_, token_app) = test_apps();
+        let (mut in_state, mut out_state, witness) = metered_states(120);
+        // 250000 millisatoshis a unit are still 250 tokens
+        for state in [&mut in_state, &mut out_state] {
+            state.subscription.currency_unit = CurrencyUnit::MilliSatoshi;
+            state.price_per_unit_sats = 250000;
+        }
+        let tx = metered_tx(&in_state, &out_state);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+    }
+
+    #[test]
+    fn test_metered_payment_unit_cap() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, witness) = metered_states(1001);
+        let tx = metered_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::UnitCapExceeded {
+                max: 1000,
+                actual: 1001
+            })
+        );
+    }
+
+    #[test]
+    fn test_metered_payment_rejects_overflow() {
+        let (_, token_app) = test_apps();
+        let (mut in_state, _, witness) = metered_states(2);
+        in_state.price_per_unit_sats = u64::MAX;
+        let out_state = in_state.clone();
+        let tx = metered_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::Overflow)
+        );
+    }
+
+    // Tiered subscription: 100000 sats per cycle, 80000 once 300000 has been paid
+    fn tiered_state(total_paid_sats: u64) -> TieredSubscriptionState {
+        let mut state =
+            TieredSubscriptionState::new(active_state(), vec![(300000, 80000), (600000, 60000)])
+                .unwrap();
+        state.subscription.total_paid_sats = total_paid_sats;
+        state
+    }
+
+    fn tiered_payment(
+        in_state: &TieredSubscriptionState,
+        payment: u64,
+    ) -> (TieredSubscriptionState, Transaction, Data) {
+        let block = in_state.subscription.last_payment_block
+            + in_state.subscription.billing_interval_blocks;
+        let mut out_state = in_state.clone();
+        out_state.subscription.last_payment_block = block;
+        out_state.subscription.remaining_balance -= payment;
+        out_state.subscription.cycles_paid += 1;
+        out_state.subscription.total_paid_sats += payment;
+        out_state.subscription.last_charge_sats = payment;
+        out_state.subscription.nonce += 1;
+        let mut tx = transition_tx(
+            &SubscriptionStateV::V5(in_state.clone()),
+            &SubscriptionStateV::V5(out_state.clone()),
+            in_state.subscription.remaining_balance,
+            out_state.subscription.remaining_balance,
+        );
+        add_payout(&mut tx, MERCHANT, payment);
+        (out_state, tx, block_witness(block))
+    }
+
+    #[test]
+    fn test_tiered_payment_first_tier() {
+        let (_, token_app) = test_apps();
+        let in_state = tiered_state(200000);
+
+        let (_, tx, witness) = tiered_payment(&in_state, 100000);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+
+        // The discount does not apply before the threshold is reached
+        let (_, tx, witness) = tiered_payment(&in_state, 80000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 80000
+            })
+        );
+    }
+
+    #[test]
+    fn test_tiered_payment_after_threshold() {
+        let (_, token_app) = test_apps();
+        let in_state = tiered_state(300000);
+        assert_eq!(in_state.current_amount(), 80000);
+
+        let (_, tx, witness) = tiered_payment(&in_state, 80000);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+
+        let (_, tx, witness) = tiered_payment(&in_state, 100000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::AmountMismatch {
+                expected: 80000,
+                actual: 100000
+            })
+        );
+    }
+
+    #[test]
+    fn test_tiered_payment_in_millisatoshis() {
+        let (_, token_app) = test_apps();
+        let subscription = MinimalSubscriptionState {
+            amount_sats: 100000000,
+            currency_unit: CurrencyUnit::MilliSatoshi,
+            ..active_state()
+        };
+        let in_state =
+            TieredSubscriptionState::new(subscription, vec![(300000, 80000000)]).unwrap();
+
+        let (_, tx, witness) = tiered_payment(&in_state, 100000);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+    }
+
+    #[test]
+    fn test_tiered_rejects_unsorted_tiers() {
+        assert_eq!(
+            TieredSubscriptionState::new(active_state(), vec![(600000, 60000), (300000, 80000)]),
+            Err(ValidationError::InvalidTiers)
+        );
+    }
+
+    const COUPON: &str = "WELCOME25";
+
+    fn coupon_witness(block: u32, coupon_code: &str) -> Data {
+        Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            coupon: Some(CouponRedemption {
+                coupon_code: coupon_code.to_string(),
+                coupon_hash: hash(COUPON),
+                discount_bps: 2500,
+            }),
+            ..Default::default()
+        })
+    }
+
+    fn coupon_payment(in_state: &MinimalSubscriptionState) -> (Transaction, u32) {
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: in_state.remaining_balance - 75000,
+            total_paid_sats: in_state.total_paid_sats + 75000,
+            last_charge_sats: 75000,
+            coupon_used: true,
+            ..paid_state(in_state, block)
+        };
+        (payment_tx(in_state, &out_state), block)
+    }
+
+    #[test]
+    fn test_coupon_discounts_single_payment() {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder().coupon_code(COUPON).build().unwrap();
+        let (tx, block) = coupon_payment(&in_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &coupon_witness(block, COUPON)),
+            Ok(())
+        );
+
+        // Without the coupon the full amount is due
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::UnexpectedFieldChange("coupon_used"))
+        );
+    }
+
+    #[test]
+    fn test_coupon_rejects_wrong_code() {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder().coupon_code(COUPON).build().unwrap();
+        let (tx, block) = coupon_payment(&in_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &coupon_witness(block, "FREEMONEY")),
+            Err(ValidationError::InvalidCoupon)
+        );
+    }
+
+    #[test]
+    fn test_coupon_rejects_reuse() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            coupon_used: true,
+            ..test_builder().coupon_code(COUPON).build().unwrap()
+        };
+        let (tx, block) = coupon_payment(&in_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &coupon_witness(block, COUPON)),
+            Err(ValidationError::CouponAlreadyUsed)
+        );
+    }
+
+    #[test]
+    fn test_coupon_discounts_usd_price() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            coupon_hash: Some(hash(COUPON)),
+            ..usd_state()
+        };
+        // $50 at 4000 sats per dollar within 1%, a quarter off: 148500 to 151500
+        let coupon_usd_payment = |payment: u64| {
+            let out_state = MinimalSubscriptionState {
+                remaining_balance: in_state.remaining_balance - payment,
+                total_paid_sats: payment,
+                last_charge_sats: payment,
+                coupon_used: true,
+                oracle_auth_hash: Some(oracle_chain()[2].clone()),
+                ..in_state.apply_payment(850144).unwrap()
+            };
+            let witness = Data::from(&SubscriptionWitness {
+                oracle_price: Some(oracle_price(4000, 850144)),
+                ..coupon_witness(850144, COUPON).value().unwrap()
+            });
+            token_contract_satisfied(&token_app, &payment_tx(&in_state, &out_state), &witness)
+        };
+        assert_eq!(coupon_usd_payment(150000), Ok(()));
+        // The placeholder `amount_sats` is not what gets discounted
+        assert_eq!(
+            coupon_usd_payment(75000),
+            Err(ValidationError::AmountOutOfRange {
+                min: 148500,
+                max: 151500,
+                actual: 75000
+            })
+        );
+    }
+
+    #[test]
+    fn test_mint_nft_with_configured_hash_algo() {
+        let funding_utxo = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:0";
+        let state = MinimalSubscriptionState {
+            hash_algo: HashAlgo::Blake3,
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash_with(HashAlgo::Blake3, funding_utxo),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        let w = Data::from(&funding_utxo.to_string());
+
+        assert_eq!(can_mint_nft(&nft_app, &tx, &w), Ok(()));
+
+        // The identity must be derived with the algorithm recorded in the state
+        let sha256_app = App {
+            identity: hash(funding_utxo),
+            ..nft_app.clone()
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&sha256_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&sha256_app, &tx, &w),
+            Err(ValidationError::IdentityMismatch)
+        );
+    }
+
+    fn cancellation_witness(mode: CancellationMode) -> Data {
+        Data::from(&SubscriptionWitness {
+            current_block: Some(850000),
+            cancellation_mode: mode,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_cancellation_refund_mode() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.cancel().unwrap();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &tx,
+                &cancellation_witness(CancellationMode::Refund)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_cancellation_burn_mode() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.cancel().unwrap();
+        let tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        let w = cancellation_witness(CancellationMode::Burn);
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), Ok(()));
+    }
+
+    // Cancel a subscription committed to 3 cycles after `cycles_paid` of them, paying
+    // `fee` to the merchant and the rest of the balance back to the payer
+    fn committed_cancellation(cycles_paid: u32, fee: u64) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            cycles_paid,
+            ..test_builder()
+                .last_payment_block(850000)
+                .min_commitment(3, 50000)
+                .build()
+                .unwrap()
+        };
+        let out_state = in_state.cancel().unwrap();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        if fee > 0 {
+            add_payout(&mut tx, MERCHANT, fee);
+        }
+        add_payout(&mut tx, PAYER, 1000000 - fee);
+        token_contract_satisfied(
+            &token_app,
+            &tx,
+            &cancellation_witness(CancellationMode::Refund),
+        )
+    }
+
+    #[test]
+    fn test_cancellation_after_commitment() {
+        assert_eq!(committed_cancellation(3, 0), Ok(()));
+    }
+
+    #[test]
+    fn test_cancellation_within_commitment() {
+        assert_eq!(
+            committed_cancellation(1, 0),
+            Err(ValidationError::CommitmentNotMet {
+                required: 3,
+                paid: 1
+            })
+        );
+        assert_eq!(committed_cancellation(1, 50000), Ok(()));
+    }
+
+    #[test]
+    fn test_cancellation_mode_mismatch() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.cancel().unwrap();
+
+        // Burn mode, but the tokens are kept in an unaddressed output
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &tx,
+                &cancellation_witness(CancellationMode::Burn)
+            ),
+            Err(ValidationError::AmountMismatch {
+                expected: 0,
+                actual: 1000000
+            })
+        );
+
+        // Refund mode, but the tokens are burned
+        let tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &tx,
+                &cancellation_witness(CancellationMode::Refund)
+            ),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000000,
+                actual: 0
+            })
+        );
+    }
+
+    fn fixed_term_state(cycles_paid: u32) -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            max_cycles: 12,
+            cycles_paid,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_payment_increments_cycles_paid() {
+        let (_, token_app) = test_apps();
+        let in_state = fixed_term_state(3);
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+        assert_eq!(out_state.cycles_paid, 4);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Ok(())
+        );
+
+        // A payment that is not counted is rejected
+        let uncounted = MinimalSubscriptionState {
+            cycles_paid: 3,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &uncounted),
+                &block_witness(block)
+            ),
+            Err(ValidationError::UnexpectedFieldChange("cycles_paid"))
+        );
+    }
+
+    #[test]
+    fn test_final_cycle_deactivates_subscription() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = fixed_term_state(11);
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            is_active: false,
+            ..paid_state(&in_state, block)
+        };
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+
+        // The final payment cannot leave the subscription active
+        let still_active = paid_state(&in_state, block);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &still_active),
+                &block_witness(block)
+            ),
+            Err(ValidationError::UnexpectedFieldChange("is_active"))
+        );
+    }
+
+    // Subscription allowing a final partial payment, whose balance covers `remaining_balance`
+    fn partial_final_state(remaining_balance: u64) -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            remaining_balance,
+            ..test_builder()
+                .last_payment_block(850000)
+                .allow_partial_final(true)
+                .build()
+                .unwrap()
+        }
+    }
+
+    fn partial_final_payment(
+        in_state: &MinimalSubscriptionState,
+        out_state: &MinimalSubscriptionState,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        token_contract_satisfied(
+            &token_app,
+            &payment_tx(in_state, out_state),
+            &block_witness(out_state.last_payment_block),
+        )
+    }
+
+    #[test]
+    fn test_full_payment_with_partial_final_allowed() {
+        let in_state = partial_final_state(1000000);
+        let out_state = in_state.apply_payment(850144).unwrap();
+        assert_eq!(out_state.remaining_balance, 900000);
+        assert!(out_state.is_active);
+        assert_eq!(partial_final_payment(&in_state, &out_state), Ok(()));
+    }
+
+    #[test]
+    fn test_final_partial_payment_deactivates() {
+        let in_state = partial_final_state(60000);
+        let out_state = in_state.apply_payment(850144).unwrap();
+        assert_eq!(out_state.remaining_balance, 0);
+        assert_eq!(out_state.total_paid_sats, 60000);
+        assert!(!out_state.is_active);
+        assert_eq!(partial_final_payment(&in_state, &out_state), Ok(()));
+
+        // Without the mode, a short balance cannot be paid at all
+        let strict = MinimalSubscriptionState {
+            allow_partial_final: false,
+            ..in_state
+        };
+        assert_eq!(
+            strict.apply_payment(850144),
+            Err(ValidationError::InsufficientBalance {
+                required: 100000,
+                available: 60000
+            })
+        );
+    }
+
+    #[test]
+    fn test_final_partial_payment_must_deactivate() {
+        let in_state = partial_final_state(60000);
+        let still_active = MinimalSubscriptionState {
+            is_active: true,
+            ..in_state.apply_payment(850144).unwrap()
+        };
+        assert_eq!(
+            partial_final_payment(&in_state, &still_active),
+            Err(ValidationError::UnexpectedFieldChange("is_active"))
+        );
+        // The whole balance must be paid out
+        let short = MinimalSubscriptionState {
+            remaining_balance: 10000,
+            total_paid_sats: 50000,
+            ..in_state.apply_payment(850144).unwrap()
+        };
+        assert_eq!(
+            partial_final_payment(&in_state, &short),
+            Err(ValidationError::AmountMismatch {
+                expected: 60000,
+                actual: 50000
+            })
+        );
+    }
+
+    // Pay the cycle due at block 850144 of a partial-final subscription holding `balance` and
+    // earning 5% a year, whose yield is minted and paid out with the charge
+    fn partial_final_yield_payment(balance: u64) -> (MinimalSubscriptionState, Transaction) {
+        let in_state = MinimalSubscriptionState {
+            annual_yield_bps: 500,
+            ..partial_final_state(balance)
+        };
+        let out_state = in_state.apply_payment(850144).unwrap();
+        let mut tx = transition_tx(&in_state, &out_state, balance, out_state.remaining_balance);
+        add_payout(&mut tx, MERCHANT, out_state.last_charge_sats);
+        (out_state, tx)
+    }
+
+    #[test]
+    fn test_final_partial_payment_with_yield() {
+        let (_, token_app) = test_apps();
+        let w = block_witness(850144);
+        // 60000 are credited 8 of yield for the cycle, and all of it is paid out
+        let (out_state, tx) = partial_final_yield_payment(60000);
+        assert_eq!(out_state.total_paid_sats, 60008);
+        assert!(!out_state.is_active);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+
+        // A balance the yield lifts to the charge pays a full cycle and stays active
+        let (out_state, tx) = partial_final_yield_payment(99995);
+        assert_eq!(out_state.remaining_balance, 8);
+        assert!(out_state.is_active);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+    }
+
+    fn accruing_state() -> MinimalSubscriptionState {
+        test_builder()
+            .last_payment_block(850000)
+            .accrue_payments(true)
+            .build()
+            .unwrap()
+    }
+
+    // Payment tx keeping the merchant's share locked with the subscription NFT
+    fn accrued_payment(
+        in_state: &MinimalSubscriptionState,
+        out_state: &MinimalSubscriptionState,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let tx = transition_tx(
+            in_state,
+            out_state,
+            in_state.locked_sats(),
+            out_state.locked_sats(),
+        );
+        token_contract_satisfied(
+            &token_app,
+            &tx,
+            &block_witness(out_state.last_payment_block),
+        )
+    }
+
+    fn merchant_withdrawal(
+        in_state: &MinimalSubscriptionState,
+        withdrawn: u64,
+    ) -> Result<(), ValidationError> {
+        let (nft_app, token_app) = test_apps();
+        let out_state = MinimalSubscriptionState {
+            merchant_withdrawable_sats: 0,
+            nonce: in_state.nonce + 1,
+            ..in_state.clone()
+        };
+        let tx = TxBuilder::new(&nft_app)
+            .held_by(HOLDER)
+            .nft_input(in_state)
+            .token_input(in_state.locked_sats())
+            .nft_output(&out_state)
+            .token_output(in_state.locked_sats() - withdrawn)
+            .payout(MERCHANT, withdrawn)
+            .build();
+        token_contract_satisfied(&token_app, &tx, &Data::empty())
+    }
+
+    #[test]
+    fn test_payments_accrue_for_merchant() {
+        let first = accruing_state();
+        let second = first.apply_payment(850144).unwrap();
+        let third = second.apply_payment(850288).unwrap();
+        assert_eq!(second.merchant_withdrawable_sats, 100000);
+        assert_eq!(third.merchant_withdrawable_sats, 200000);
+        assert_eq!(third.remaining_balance, 800000);
+        assert_eq!(third.locked_sats(), 1000000);
+
+        // Nothing is paid out, the whole amount stays locked with the NFT
+        assert_eq!(accrued_payment(&first, &second), Ok(()));
+        assert_eq!(accrued_payment(&second, &third), Ok(()));
+        let skimmed = MinimalSubscriptionState {
+            merchant_withdrawable_sats: 150000,
+            ..third.clone()
+        };
+        assert_eq!(
+            accrued_payment(&second, &skimmed),
+            Err(ValidationError::UnexpectedFieldChange(
+                "merchant_withdrawable_sats"
+            ))
+        );
+
+        assert_eq!(merchant_withdrawal(&third, 200000), Ok(()));
+    }
+
+    #[test]
+    fn test_withdrawal_exceeding_accrued() {
+        let in_state = accruing_state().apply_payment(850144).unwrap();
+        assert_eq!(
+            merchant_withdrawal(&in_state, 150000),
+            Err(ValidationError::WithdrawalExceedsAccrued {
+                accrued: 100000,
+                withdrawn: 150000
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_past_max_cycles() {
+        let (_, token_app) = test_apps();
+        let in_state = fixed_term_state(12);
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = paid_state(&in_state, block);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Err(ValidationError::MaxCyclesReached)
+        );
+    }
+
+    // Subscription whose next payment is due at 850144 and that expires at 850150
+    fn expiring_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            grace_period_blocks: 12,
+            expiry_block: 850150,
+            ..active_state()
+        }
+    }
+
+    fn expiry_payment(block: u32) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = expiring_state();
+        let out_state = paid_state(&in_state, block);
+        token_contract_satisfied(
+            &token_app,
+            &payment_tx(&in_state, &out_state),
+            &block_witness(block),
+        )
+    }
+
+    #[test]
+    fn test_payment_before_expiry() {
+        assert_eq!(expiry_payment(850149), Ok(()));
+    }
+
+    #[test]
+    fn test_payment_at_expiry() {
+        assert_eq!(expiry_payment(850150), Ok(()));
+    }
+
+    #[test]
+    fn test_payment_after_expiry() {
+        assert_eq!(
+            expiry_payment(850151),
+            Err(ValidationError::SubscriptionExpired)
+        );
+    }
+
+    // Release the whole balance of `in_state` to `recipient` at `block`
+    fn expired_refund(
+        in_state: &MinimalSubscriptionState,
+        recipient: &str,
+        block: u32,
+    ) -> Result<(), ValidationError> {
+        let (nft_app, token_app) = test_apps();
+        let out_state = in_state.cancel().unwrap();
+        let tx = TxBuilder::new(&nft_app)
+            .nft_input(in_state)
+            .token_input(in_state.remaining_balance)
+            .nft_output(&out_state)
+            .token_output(0)
+            .payout(recipient, in_state.remaining_balance)
+            .build();
+        token_contract_satisfied(&token_app, &tx, &block_witness(block))
+    }
+
+    #[test]
+    fn test_expired_refund() {
+        // The merchant keeps nothing of the cycle the expiry cut short
+        assert_eq!(expired_refund(&expiring_state(), PAYER, 850151), Ok(()));
+
+        // No active-state precondition: a subscription ended early is refunded as well
+        let ended = MinimalSubscriptionState {
+            is_active: false,
+            ..expiring_state()
+        };
+        assert_eq!(expired_refund(&ended, PAYER, 850151), Ok(()));
+    }
+
+    #[test]
+    fn test_expired_refund_before_expiry() {
+        let ended = MinimalSubscriptionState {
+            is_active: false,
+            ..expiring_state()
+        };
+        assert_eq!(
+            expired_refund(&ended, PAYER, 850150),
+            Err(ValidationError::SubscriptionNotExpired)
+        );
+        // An active subscription is cancelled instead, paying the merchant's share
+        assert_eq!(
+            expired_refund(&expiring_state(), PAYER, 850150),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 100000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_expired_refund_to_wrong_key() {
+        assert_eq!(
+            expired_refund(&expiring_state(), STRANGER, 850151),
+            Err(ValidationError::Underpaid {
+                recipient: "payer",
+                expected: 1000000,
+                actual: 0
+            })
+        );
+    }
+
+    const TRANSFER_AUTH: &str = "move-to-new-wallet";
+
+    fn transfer_witness(auth: &str) -> Data {
+        Data::from(&SubscriptionWitness {
+            transfer_auth: Some(auth.to_string()),
+            ..Default::default()
+        })
+    }
+
+    fn transferable_state() -> MinimalSubscriptionState {
+        test_builder().transfer_auth(TRANSFER_AUTH).build().unwrap()
+    }
+
+    #[test]
+    fn test_ownership_transfer() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = transferable_state();
+        let out_state = MinimalSubscriptionState {
+            payer_pubkey: STRANGER.to_string(),
+            nonce: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let w = transfer_witness(TRANSFER_AUTH);
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &w), Ok(()));
+    }
+
+    #[test]
+    fn test_ownership_transfer_bad_auth() {
+        let (_, token_app) = test_apps();
+        let in_state = transferable_state();
+        let out_state = MinimalSubscriptionState {
+            payer_pubkey: STRANGER.to_string(),
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &transfer_witness("guess")),
+            Err(ValidationError::TransferNotAuthorized)
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::TransferNotAuthorized)
+        );
+    }
+
+    #[test]
+    fn test_ownership_transfer_cannot_change_amount() {
+        let (_, token_app) = test_apps();
+        let in_state = transferable_state();
+        let out_state = MinimalSubscriptionState {
+            payer_pubkey: STRANGER.to_string(),
+            amount_sats: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &transfer_witness(TRANSFER_AUTH)),
+            Err(ValidationError::ImmutableFieldChanged("amount_sats"))
+        );
+    }
+
+    fn banded_payment(charge: u64) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder().amount_bounds(50000, 150000).build().unwrap();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: in_state.remaining_balance - charge,
+            total_paid_sats: in_state.total_paid_sats + charge,
+            last_charge_sats: charge,
+            ..paid_state(&in_state, block)
+        };
+        token_contract_satisfied(
+            &token_app,
+            &payment_tx(&in_state, &out_state),
+            &block_witness(block),
+        )
+    }
+
+    #[test]
+    fn test_banded_payment_at_minimum() {
+        assert_eq!(banded_payment(50000), Ok(()));
+    }
+
+    #[test]
+    fn test_banded_payment_at_maximum() {
+        assert_eq!(banded_payment(150000), Ok(()));
+    }
+
+    #[test]
+    fn test_banded_payment_outside_band() {
+        assert_eq!(
+            banded_payment(150001),
+            Err(ValidationError::AmountOutOfRange {
+                min: 50000,
+                max: 150000,
+                actual: 150001
+            })
+        );
+        assert_eq!(
+            banded_payment(49999),
+            Err(ValidationError::AmountOutOfRange {
+                min: 50000,
+                max: 150000,
+                actual: 49999
+            })
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_inverted_amount_bounds() {
+        assert_eq!(
+            test_builder().amount_bounds(150000, 50000).build(),
+            Err(ValidationError::InvalidAmountBounds {
+                min: 150000,
+                max: 50000
+            })
+        );
+    }
+
+    #[test]
+    fn test_total_paid_tracks_consecutive_cycles() {
+        let (_, token_app) = test_apps();
+        let first = active_state();
+        let first_block = first.last_payment_block + first.billing_interval_blocks;
+        let second = paid_state(&first, first_block);
+        let second_block = first_block + first.billing_interval_blocks;
+        let third = paid_state(&second, second_block);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&first, &second),
+                &block_witness(first_block)
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&second, &third),
+                &block_witness(second_block)
+            ),
+            Ok(())
+        );
+        assert_eq!(third.total_paid_sats, 200000);
+        assert_eq!(third.remaining_balance, 800000);
+    }
+
+    #[test]
+    fn test_payment_must_update_total_paid() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.last_payment_block + in_state.billing_interval_blocks;
+        let out_state = MinimalSubscriptionState {
+            total_paid_sats: in_state.total_paid_sats,
+            ..paid_state(&in_state, block)
+        };
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_next_due_block() {
+        let state = active_state();
+        assert_eq!(state.next_due_block(), 850144);
+        assert!(!state.is_due(850143));
+        assert!(state.is_due(850144));
+
+        let saturated = MinimalSubscriptionState {
+            last_payment_block: u32::MAX - 1,
+            ..active_state()
+        };
+        assert_eq!(saturated.next_due_block(), u32::MAX);
+    }
+
+    #[test]
+    fn test_next_due_block_after_trial() {
+        let state = trial_state();
+        assert_eq!(state.next_due_block(), state.trial_end_block);
+        assert!(!state.is_due(state.trial_end_block - 1));
+        assert!(state.is_due(state.trial_end_block));
+    }
+
+    #[test]
+    fn test_remaining_cycles_exact_multiple() {
+        let state = active_state();
+        assert_eq!(state.remaining_cycles(), 10);
+        // The 10th payment is due 9 intervals after the next due block
+        assert_eq!(state.cycles_until_empty_block(850000), 850144 + 9 * 144);
+        // An overdue payment moves the whole schedule
+        assert_eq!(state.cycles_until_empty_block(850200), 850200 + 9 * 144);
+    }
+
+    #[test]
+    fn test_remaining_cycles_partial_remainder() {
+        let state = MinimalSubscriptionState {
+            remaining_balance: 250000,
+            ..active_state()
+        };
+        assert_eq!(state.remaining_cycles(), 2);
+        assert_eq!(state.cycles_until_empty_block(850000), 850144 + 144);
+
+        let capped = MinimalSubscriptionState {
+            max_cycles: 12,
+            cycles_paid: 11,
+            ..active_state()
+        };
+        assert_eq!(capped.remaining_cycles(), 1);
+    }
+
+    #[test]
+    fn test_remaining_cycles_zero_amount() {
+        let state = MinimalSubscriptionState {
+            amount_sats: 0,
+            ..active_state()
+        };
+        assert_eq!(state.remaining_cycles(), 0);
+        assert_eq!(state.cycles_until_empty_block(850000), 850000);
+    }
+
+    #[test]
+    fn test_apply_payment_round_trip() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        assert_eq!(out_state, paid_state(&in_state, block));
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Ok(())
+        );
+
+        // The final cycle of a fixed-term subscription round-trips as well
+        let in_state = fixed_term_state(11);
+        let out_state = in_state.apply_payment(block).unwrap();
+        assert!(!out_state.is_active);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_apply_payment_rejects_invalid_payments() {
+        let state = active_state();
+        assert_eq!(
+            state.apply_payment(state.next_due_block() - 1),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+
+        let underfunded = MinimalSubscriptionState {
+            remaining_balance: 99999,
+            ..active_state()
+        };
+        assert_eq!(
+            underfunded.apply_payment(underfunded.next_due_block()),
+            Err(ValidationError::InsufficientBalance {
+                required: 100000,
+                available: 99999
+            })
+        );
+    }
+
+    #[test]
+    fn test_cancel_round_trip() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = grace_state();
+        let out_state = in_state.cancel().unwrap();
+        assert!(!out_state.is_active);
+        assert_eq!(out_state.remaining_balance, 0);
+        assert_eq!(ensure_immutable_fields(&in_state, &out_state), Ok(()));
+        // A nonce that cannot be incremented gives no cancelled state
+        let exhausted = MinimalSubscriptionState {
+            nonce: u64::MAX,
+            ..in_state.clone()
+        };
+        assert_eq!(exhausted.cancel(), Err(ValidationError::Overflow));
+
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+        assert_eq!(
+            validate_subscription_cancellation(
+                &in_state,
+                &out_state,
+                &token_app,
+                &scan_tx(&token_app, &nft_app, &tx).unwrap(),
+                &SubscriptionWitness {
+                    current_block: Some(850000),
+                    ..Default::default()
+                }
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_nft_content_from_minimal_state() {
+        let state = active_state();
+        let content = NftContent::from(&state);
+        assert_eq!(content.remaining, state.remaining_balance);
+        assert_eq!(content.ticker, "SUBSCRIPTION-sub_001");
+
+        // Without an id, the ticker is the hash of the parties and terms
+        let state = MinimalSubscriptionState {
+            subscription_id: String::new(),
+            ..active_state()
+        };
+        let content = NftContent::from(&state);
+        assert_eq!(content.subscription_id().map(str::len), Some(64));
+
+        let other_merchant = MinimalSubscriptionState {
+            merchant_pubkey: STRANGER.to_string(),
+            ..state.clone()
+        };
+        let other_amount = MinimalSubscriptionState {
+            amount_sats: 50000,
+            ..state.clone()
+        };
+        assert_ne!(content.ticker, NftContent::from(&other_merchant).ticker);
+        assert_ne!(content.ticker, NftContent::from(&other_amount).ticker);
+    }
+
+    const FUNDING_UTXO: &str = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:0";
+
+    fn terms_identity(state: &MinimalSubscriptionState, funding_utxo: &str) -> B32 {
+        derive_subscription_identity(
+            &state.payer_pubkey,
+            &state.merchant_pubkey,
+            state.amount_sats,
+            state.billing_interval_blocks,
+            funding_utxo,
+        )
+    }
+
+    #[test]
+    fn test_mint_nft_with_terms_identity() {
+        let state = active_state();
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: terms_identity(&state, FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        let w = Data::from(&MintWitness {
+            funding_utxo: FUNDING_UTXO.to_string(),
+            subscription_id: None,
+        });
+        assert_eq!(can_mint_nft(&nft_app, &tx, &w), Ok(()));
+
+        // The legacy UTXO-only witness does not match a terms-derived identity
+        let legacy_w = Data::from(&FUNDING_UTXO.to_string());
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &legacy_w),
+            Err(ValidationError::IdentityMismatch)
+        );
+    }
+
+    #[test]
+    fn test_terms_identity_depends_on_every_parameter() {
+        let state = active_state();
+        let identity = terms_identity(&state, FUNDING_UTXO);
+        let variants = [
+            MinimalSubscriptionState {
+                payer_pubkey: STRANGER.to_string(),
+                ..active_state()
+            },
+            MinimalSubscriptionState {
+                merchant_pubkey: STRANGER.to_string(),
+                ..active_state()
+            },
+            MinimalSubscriptionState {
+                amount_sats: 100001,
+                ..active_state()
+            },
+            MinimalSubscriptionState {
+                billing_interval_blocks: 145,
+                ..active_state()
+            },
+        ];
+        for variant in &variants {
+            assert_ne!(terms_identity(variant, FUNDING_UTXO), identity);
+        }
+        let other_utxo = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1";
+        assert_ne!(terms_identity(&state, other_utxo), identity);
+    }
+
+    #[test]
+    fn test_subscription_id_identity_depends_on_funding_utxo() {
+        let identity = derive_subscription_id_identity("sub_001", FUNDING_UTXO);
+        let other_utxo = "dc78b09d767c8565c4a58a95e7ad5ee22b28fc1685535056a395dc94929cdd5f:1";
+        assert_ne!(
+            derive_subscription_id_identity("sub_001", other_utxo),
+            identity
+        );
+        assert_ne!(
+            derive_subscription_id_identity("sub_002", FUNDING_UTXO),
+            identity
+        );
+    }
+
+    #[test]
+    fn test_mint_nft_with_subscription_id_identity() {
+        let content = NftContent::from(SubscriptionState {
+            subscription_id: "sub_001".to_string(),
+            recipient: MERCHANT.to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 1000000,
+            total_locked: 1000000,
+        });
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: derive_subscription_id_identity("sub_001", FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&content))])],
+        );
+        let mint = |subscription_id: &str| {
+            Data::from(&MintWitness {
+                funding_utxo: FUNDING_UTXO.to_string(),
+                subscription_id: Some(subscription_id.to_string()),
+            })
+        };
+        assert_eq!(can_mint_nft(&nft_app, &tx, &mint("sub_001")), Ok(()));
+
+        // The witnessed id must be the one the minted NFT carries
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &mint("sub_002")),
+            Err(ValidationError::SubscriptionIdMismatch)
+        );
+
+        // Full states are keyed by their recorded id, or the hash of their terms without one,
+        // but a new subscription must record its id
+        assert_eq!(
+            SubscriptionStateV::V2(active_state()).subscription_id(),
+            Some("sub_001".to_string())
+        );
+        let state = MinimalSubscriptionState {
+            subscription_id: String::new(),
+            ..active_state()
+        };
+        let subscription_id = SubscriptionStateV::V2(state.clone())
+            .subscription_id()
+            .unwrap();
+        let nft_app = App {
+            identity: derive_subscription_id_identity(&subscription_id, FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(subscription_id.len(), 64);
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &mint(&subscription_id)),
+            Err(ValidationError::EmptySubscriptionId)
+        );
+    }
+
+    #[test]
+    fn test_payment_with_single_nft_output() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            can_execute_subscription_payment(
+                &token_app,
+                &nft_app,
+                &tx,
+                &SubscriptionWitness {
+                    current_block: Some(block),
+                    ..Default::default()
+                }
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_rejects_state_of_another_subscription() {
+        let (nft_app, token_app) = test_apps();
+        let other_nft = App {
+            identity: B32([3; 32]),
+            ..nft_app.clone()
+        };
+        let (in_state, out_state, block) = due_payment();
+        // The rich subscription is spent, but its paid state is written under another identity
+        let tx = test_tx(
+            vec![charms(&[
+                (&nft_app, Data::from(&in_state)),
+                (&token_app, Data::from(&in_state.remaining_balance)),
+            ])],
+            vec![charms(&[
+                (&other_nft, Data::from(&out_state)),
+                (&token_app, Data::from(&out_state.remaining_balance)),
+            ])],
+        );
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::NftIdentityMismatch)
+        );
+        assert_eq!(
+            ensure_nft_identity_conserved(&nft_app, &payment_tx(&in_state, &out_state)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_empty_transaction_is_rejected() {
+        let (_, token_app) = test_apps();
+        let tx = test_tx(vec![], vec![]);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::EmptyTransaction)
+        );
+    }
+
+    #[test]
+    fn test_tokens_without_nft_are_rejected() {
+        let (_, token_app) = test_apps();
+        let tx = test_tx(
+            vec![charms(&[(&token_app, Data::from(&1000u64))])],
+            vec![charms(&[(&token_app, Data::from(&1000u64))])],
+        );
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::TokensWithoutNft)
+        );
+        // Minting tokens from nothing is rejected the same way
+        let tx = test_tx(vec![], vec![charms(&[(&token_app, Data::from(&1000u64))])]);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::TokensWithoutNft)
+        );
+    }
+
+    #[test]
+    fn test_payment_rejects_second_nft_output() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let mut tx = payment_tx(&in_state, &out_state);
+        tx.outs
+            .push(charms(&[(&nft_app, Data::from(&active_state()))]));
+        if let Some(coin_outs) = tx.coin_outs.as_mut() {
+            coin_outs.push(NativeOutput {
+                amount: 546,
+                dest: vec![],
+            });
+        }
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::UnexpectedNftCount(2))
+        );
+        assert_eq!(
+            can_execute_subscription_payment(
+                &token_app,
+                &nft_app,
+                &tx,
+                &SubscriptionWitness {
+                    current_block: Some(block),
+                    ..Default::default()
+                }
+            ),
+            Err(ValidationError::UnexpectedNftCount(2))
+        );
+        assert_eq!(
+            can_mint_token(&token_app, &nft_app, &tx),
+            Err(ValidationError::UnexpectedNftCount(2))
+        );
+    }
+
+    // Payment tx where the balance left in the subscription is sent to `holder`
+    fn locked_payment_tx(holder: &str) -> (Transaction, u32) {
+        let (in_state, out_state, block) = due_payment();
+        let mut tx = transition_tx(&in_state, &out_state, in_state.remaining_balance, 0);
+        add_payout(&mut tx, MERCHANT, in_state.amount_sats);
+        add_payout(&mut tx, holder, out_state.remaining_balance);
+        // The subscription NFT stays with the payer
+        let coin_outs = tx.coin_outs.as_mut().unwrap();
+        coin_outs[0].dest = p2wpkh_script(PAYER).unwrap();
+        (tx, block)
+    }
+
+    #[test]
+    fn test_payment_cannot_mint_tokens() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        // The balance goes down by a cycle while 50000 new tokens appear next to it
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 950000);
+        add_payout(&mut tx, MERCHANT, 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::TokensMinted { minted: 50000 })
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &block_witness(block)),
+            Err(ValidationError::TokensMinted { minted: 50000 })
+        );
+
+        // Neither can a cancellation
+        let cancelled = in_state.cancel().unwrap();
+        let mut tx = transition_tx(&in_state, &cancelled, 1000000, 1);
+        add_payout(&mut tx, PAYER, 1000000);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &tx,
+                &cancellation_witness(CancellationMode::Refund)
+            ),
+            Err(ValidationError::TokensMinted { minted: 1 })
+        );
+    }
+
+    #[test]
+    fn test_payment_keeps_tokens_locked() {
+        let (_, token_app) = test_apps();
+        let (tx, block) = locked_payment_tx(PAYER);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_rejects_siphoned_balance() {
+        let (_, token_app) = test_apps();
+        let (tx, block) = locked_payment_tx(STRANGER);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::TokensNotLocked {
+                expected: 900000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_rejects_siphon_without_destinations() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        // Without native outputs no destination is known: the balance left outside the NFT
+        // output is not kept with the subscription
+        let tx = test_tx(
+            vec![
+                charms(&[(&nft_app, Data::from(&in_state))]),
+                charms(&[(&token_app, Data::from(&in_state.remaining_balance))]),
+            ],
+            vec![
+                charms(&[(&nft_app, Data::from(&out_state))]),
+                charms(&[(&token_app, Data::from(&in_state.amount_sats))]),
+                charms(&[(&token_app, Data::from(&out_state.remaining_balance))]),
+            ],
+        );
+        assert_eq!(tx.coin_outs, None);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::TokensNotLocked {
+                expected: 900000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_legacy_payment_rejects_siphoned_supply() {
+        let (nft_app, token_app) = test_apps();
+        let mut tx = test_tx(
+            vec![
+                charms(&[(&nft_app, legacy_nft(1000))]),
+                charms(&[(&token_app, Data::from(&1000u64))]),
+            ],
+            vec![charms(&[(&nft_app, legacy_nft(900))])],
+        );
+        add_payout(&mut tx, MERCHANT, 100);
+        add_payout(&mut tx, STRANGER, 900);
+
+        assert_eq!(
+            can_execute_subscription_payment(
+                &token_app,
+                &nft_app,
+                &tx,
+                &SubscriptionWitness::default()
+            ),
+            Err(ValidationError::TokensNotLocked {
+                expected: 900,
+                actual: 0
+            })
+        );
+    }
+
+    // Lower a legacy state of `active_state`'s terms, its ticker id their hash, from 1000000
+    // to `remaining`, revealing `terms` in the witness
+    fn legacy_payment(
+        remaining: u64,
+        terms: Option<MigrationTerms>,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let legacy = NftContent::from(&MinimalSubscriptionState {
+            subscription_id: String::new(),
+            ..active_state()
+        });
+        let paid = NftContent {
+            remaining,
+            ..legacy.clone()
+        };
+        let mut tx = transition_tx(&legacy, &paid, 1000000, remaining);
+        add_payout(&mut tx, MERCHANT, 1000000 - remaining);
+        let witness = Data::from(&SubscriptionWitness {
+            legacy_terms: terms,
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &tx, &witness)
+    }
+
+    fn active_terms() -> MigrationTerms {
+        let state = active_state();
+        MigrationTerms {
+            payer_pubkey: state.payer_pubkey,
+            merchant_pubkey: state.merchant_pubkey,
+            amount_sats: state.amount_sats,
+            billing_interval_blocks: state.billing_interval_blocks,
+        }
+    }
+
+    #[test]
+    fn test_legacy_single_cycle_payment() {
+        assert_eq!(legacy_payment(900000, Some(active_terms())), Ok(()));
+    }
+
+    #[test]
+    fn test_legacy_payment_rejects_overdraw() {
+        assert_eq!(
+            legacy_payment(800000, Some(active_terms())),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 200000
+            })
+        );
+        // The terms must be the ones the ticker id was derived from
+        let inflated = MigrationTerms {
+            amount_sats: 200000,
+            ..active_terms()
+        };
+        assert_eq!(
+            legacy_payment(800000, Some(inflated)),
+            Err(ValidationError::InvalidTicker)
+        );
+        assert_eq!(
+            legacy_payment(900000, None),
+            Err(ValidationError::MissingLegacyTerms)
+        );
+    }
+
+    #[test]
+    fn test_legacy_human_ticker_payment() {
+        let (nft_app, token_app) = test_apps();
+        let mut tx = test_tx(
+            vec![
+                charms(&[(&nft_app, legacy_nft(1000000))]),
+                charms(&[(&token_app, Data::from(&1000000u64))]),
+            ],
+            vec![
+                charms(&[(&nft_app, legacy_nft(900000))]),
+                charms(&[(&token_app, Data::from(&900000u64))]),
+            ],
+        );
+        hold_outputs(&mut tx);
+        add_payout(&mut tx, MERCHANT, 100000);
+
+        // `SUBSCRIPTION-sub_001` commits to no terms, so it stays payable without them
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Ok(())
+        );
+    }
+
+    // A second subscription minted with `active_state`'s, paid by a different payer
+    fn second_subscription() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            payer_pubkey: STRANGER.to_string(),
+            subscription_id: "sub_002".to_string(),
+            ..active_state()
+        }
+    }
+
+    // Pay `active_state` and `second_in` together, `second_payment` tokens going to the
+    // merchant for the latter, with the outgoing states in reverse order
+    fn batch_tx(second_in: &MinimalSubscriptionState, second_payment: u64) -> (Transaction, u32) {
+        let (nft_app, token_app) = test_apps();
+        let (first_in, first_out, block) = due_payment();
+        let second_out = paid_state(second_in, block);
+        let balances = first_in.remaining_balance + second_in.remaining_balance;
+        let mut tx = test_tx(
+            vec![
+                charms(&[(&nft_app, Data::from(&first_in))]),
+                charms(&[(&nft_app, Data::from(second_in))]),
+                charms(&[(&token_app, Data::from(&balances))]),
+            ],
+            vec![
+                charms(&[(&nft_app, Data::from(&second_out))]),
+                charms(&[(&nft_app, Data::from(&first_out))]),
+                charms(&[(
+                    &token_app,
+                    Data::from(&(balances - 100000 - second_payment)),
+                )]),
+            ],
+        );
+        hold_outputs(&mut tx);
+        add_payout(&mut tx, MERCHANT, 100000);
+        add_payout(&mut tx, MERCHANT, second_payment);
+        (tx, block)
+    }
+
+    #[test]
+    fn test_batch_of_two_payments() {
+        let (nft_app, token_app) = test_apps();
+        let (tx, block) = batch_tx(&second_subscription(), 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_batch_credits_yield() {
+        let (nft_app, token_app) = test_apps();
+        let second_in = MinimalSubscriptionState {
+            annual_yield_bps: 500,
+            ..second_subscription()
+        };
+        let (mut tx, block) = batch_tx(&second_in, 100000);
+        // The second subscription's yield is minted with its payment
+        let second_out = second_in.apply_payment(block).unwrap();
+        let (_, first_out, _) = due_payment();
+        let balances = first_out.remaining_balance + second_out.remaining_balance;
+        tx.outs[0] = charms(&[(&nft_app, Data::from(&second_out))]);
+        tx.outs[2] = charms(&[(&token_app, Data::from(&balances))]);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_batch_rejects_underpaid_payment() {
+        let (_, token_app) = test_apps();
+        let (tx, block) = batch_tx(&second_subscription(), 99999);
+
+        // Both payments go to the merchant, which is owed them together
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 200000,
+                actual: 199999
+            })
+        );
+    }
+
+    #[test]
+    fn test_batch_pairs_by_subscription_id() {
+        let (_, token_app) = test_apps();
+        // Same terms as `active_state`, only the id and balance tell the subscriptions apart
+        let second_in = MinimalSubscriptionState {
+            subscription_id: "sub_002".to_string(),
+            remaining_balance: 500000,
+            ..active_state()
+        };
+        let (tx, block) = batch_tx(&second_in, 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_batch_rejects_shared_subscription_id() {
+        let (_, token_app) = test_apps();
+        let second_in = MinimalSubscriptionState {
+            subscription_id: active_state().subscription_id,
+            ..second_subscription()
+        };
+        let (tx, block) = batch_tx(&second_in, 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Err(ValidationError::UnpairedSubscription)
+        );
+    }
+
+    #[test]
+    fn test_dry_run_reports_failing_check() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+        let x = Data::empty();
+
+        assert_eq!(dry_run(&token_app, &tx, &x, &block_witness(block)), Ok(()));
+        assert_eq!(dry_run(&nft_app, &tx, &x, &block_witness(block)), Ok(()));
+        assert_eq!(
+            dry_run(&token_app, &tx, &x, &Data::empty()),
+            Err(ValidationError::MissingBlockHeight)
+        );
+        assert_eq!(
+            dry_run(&token_app, &tx, &x, &block_witness(block - 1)),
+            Err(ValidationError::BlockHeightMismatch {
+                expected: block - 1,
+                actual: block
+            })
+        );
+
+        let early_state = paid_state(&in_state, block - 1);
+        assert_eq!(
+            dry_run(
+                &token_app,
+                &payment_tx(&in_state, &early_state),
+                &x,
+                &block_witness(block - 1)
+            ),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+
+        let unpaid_tx = transition_tx(
+            &in_state,
+            &out_state,
+            in_state.remaining_balance,
+            in_state.remaining_balance,
+        );
+        assert_eq!(
+            dry_run(&token_app, &unpaid_tx, &x, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 100000,
+                actual: 0
+            })
+        );
+
+        let inactive = MinimalSubscriptionState {
+            is_active: false,
+            ..in_state.clone()
+        };
+        let inactive_out = MinimalSubscriptionState {
+            is_active: false,
+            ..out_state
+        };
+        assert_eq!(
+            dry_run(
+                &token_app,
+                &payment_tx(&inactive, &inactive_out),
+                &x,
+                &block_witness(block)
+            ),
+            Err(ValidationError::SubscriptionInactive)
+        );
+    }
+
+    #[test]
+    fn test_dry_run_rejects_malformed_invocation() {
+        let (_, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+
+        assert_eq!(
+            dry_run(&token_app, &tx, &Data::from(&1u64), &block_witness(block)),
+            Err(ValidationError::UnexpectedPublicInput)
+        );
+        let other_app = App {
+            tag: 'x',
+            ..token_app
+        };
+        assert_eq!(
+            dry_run(&other_app, &tx, &Data::empty(), &block_witness(block)),
+            Err(ValidationError::UnknownAppTag('x'))
+        );
+    }
+
+    // Subscription due at 850144 with a 12-block grace period and a 5000 sat late fee
+    fn late_fee_payment(block: u32, charge: u64) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .grace_period_blocks(12)
+            .late_fee_sats(5000)
+            .build()
+            .unwrap();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: in_state.remaining_balance - charge,
+            total_paid_sats: charge,
+            last_charge_sats: charge,
+            ..paid_state(&in_state, block)
+        };
+        token_contract_satisfied(
+            &token_app,
+            &payment_tx(&in_state, &out_state),
+            &block_witness(block),
+        )
+    }
+
+    #[test]
+    fn test_on_time_payment_has_no_late_fee() {
+        assert_eq!(late_fee_payment(850144, 100000), Ok(()));
+        assert_eq!(
+            late_fee_payment(850144, 105000),
+            Err(ValidationError::AmountMismatch {
+                expected: 100000,
+                actual: 105000
+            })
+        );
+    }
+
+    #[test]
+    fn test_late_payment_within_grace_pays_late_fee() {
+        assert_eq!(late_fee_payment(850150, 105000), Ok(()));
+
+        let in_state = MinimalSubscriptionState {
+            grace_period_blocks: 12,
+            late_fee_sats: 5000,
+            ..active_state()
+        };
+        let out_state = in_state.apply_payment(850150).unwrap();
+        assert_eq!(out_state.remaining_balance, 895000);
+    }
+
+    #[test]
+    fn test_late_payment_without_late_fee() {
+        assert_eq!(
+            late_fee_payment(850150, 100000),
+            Err(ValidationError::AmountMismatch {
+                expected: 105000,
+                actual: 100000
+            })
+        );
+    }
+
+    #[test]
+    fn test_prepaid_cycles_consistent_at_creation() {
+        let state = test_builder().prepaid_cycles(10).build().unwrap();
+        assert_eq!(state.prepaid_cycles, 10);
+
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_prepaid_cycles_inconsistent_at_creation() {
+        assert_eq!(
+            test_builder().prepaid_cycles(9).build(),
+            Err(ValidationError::PrepaidBalanceMismatch {
+                expected: 900000,
+                actual: 1000000
+            })
+        );
+
+        let state = MinimalSubscriptionState {
+            prepaid_cycles: 9,
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::PrepaidBalanceMismatch {
+                expected: 900000,
+                actual: 1000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_prepaid_cycles_in_millisatoshis() {
+        // Ten cycles of 100000000 millisatoshis are a million tokens
+        let state = test_builder()
+            .amount_sats(100000000)
+            .currency_unit(CurrencyUnit::MilliSatoshi)
+            .prepaid_cycles(10)
+            .build()
+            .unwrap();
+        assert_eq!(state.validate_prepaid_cycles(), Ok(()));
+        assert_eq!(
+            MinimalSubscriptionState {
+                prepaid_cycles: 9,
+                ..state
+            }
+            .validate_prepaid_cycles(),
+            Err(ValidationError::PrepaidBalanceMismatch {
+                expected: 900000,
+                actual: 1000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_must_decrement_prepaid_cycles() {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .prepaid_cycles(10)
+            .build()
+            .unwrap();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        assert_eq!(out_state.prepaid_cycles, 9);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Ok(())
+        );
+
+        let stale = MinimalSubscriptionState {
+            prepaid_cycles: 10,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &stale),
+                &block_witness(block)
+            ),
+            Err(ValidationError::UnexpectedFieldChange("prepaid_cycles"))
+        );
+    }
+
+    // The original long-key layout of a minimal state, as written by earlier spells
+    #[derive(Serialize)]
+    struct LongKeyState {
+        payer_pubkey: String,
+        merchant_pubkey: String,
+        amount_sats: u64,
+        billing_interval_blocks: u32,
+        last_payment_block: u32,
+        is_active: bool,
+        remaining_balance: u64,
+    }
+
+    // Short keys of the core fields and the long names they replaced
+    const COMPACT_KEYS: [(&str, &str); 7] = [
+        ("p", "payer_pubkey"),
+        ("m", "merchant_pubkey"),
+        ("a", "amount_sats"),
+        ("i", "billing_interval_blocks"),
+        ("l", "last_payment_block"),
+        ("act", "is_active"),
+        ("bal", "remaining_balance"),
+    ];
+
+    #[test]
+    fn test_compact_keys_shrink_serialized_state() {
+        let compact = Data::from(&active_state());
+        // Re-encode the same fields under their long names to measure the size before renaming
+        let mut fields: BTreeMap<String, Data> = compact.value().unwrap();
+        for (short, long) in COMPACT_KEYS {
+            let value = fields.remove(short).unwrap();
+            fields.insert(long.to_string(), value);
+        }
+        let long = Data::from(&fields);
+        assert_eq!(
+            long.value::<MinimalSubscriptionState>().unwrap(),
+            active_state()
+        );
+
+        // Seven renamed keys: 105 bytes of long names replaced by 11 bytes of short ones
+        assert_eq!(long.bytes().len() - compact.bytes().len(), 94);
+    }
+
+    #[test]
+    fn test_compact_keys_round_trip_through_versioned_state() {
+        let state = active_state();
+        let data = Data::from(&SubscriptionStateV::V2(state.clone()));
+        assert!(matches!(
+            SubscriptionStateV::parse(&data),
+            Ok(SubscriptionStateV::V2(parsed)) if parsed == state
+        ));
+        assert!(matches!(
+            SubscriptionStateV::parse(&Data::from(&state)),
+            Ok(SubscriptionStateV::V2(parsed)) if parsed == state
+        ));
+    }
+
+    #[test]
+    fn test_long_key_state_still_parses() {
+        let legacy = LongKeyState {
+            payer_pubkey: PAYER.to_string(),
+            merchant_pubkey: MERCHANT.to_string(),
+            amount_sats: 100000,
+            billing_interval_blocks: 144,
+            last_payment_block: 850000,
+            is_active: true,
+            remaining_balance: 1000000,
+        };
+        let data = Data::from(&legacy);
+        // Written before contract versions and subscription ids were recorded
+        let expected = MinimalSubscriptionState {
+            contract_version: 0,
+            subscription_id: String::new(),
+            ..active_state()
+        };
+        assert!(matches!(
+            SubscriptionStateV::parse(&data),
+            Ok(SubscriptionStateV::V2(parsed)) if parsed == expected
+        ));
+    }
+
+    #[test]
+    fn test_validate_pubkey_accepts_compressed_key() {
+        assert_eq!(validate_pubkey(PAYER), Ok(()));
+        assert_eq!(validate_pubkey(MERCHANT), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_pubkey_rejects_wrong_length() {
+        let truncated = &PAYER[..64];
+        assert_eq!(
+            validate_pubkey(truncated),
+            Err(ValidationError::InvalidPubkey)
+        );
+        assert_eq!(
+            test_builder().payer_pubkey(truncated).build(),
+            Err(ValidationError::InvalidPubkey)
+        );
+
+        let state = MinimalSubscriptionState {
+            merchant_pubkey: format!("{MERCHANT}00"),
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::InvalidPubkey)
+        );
+    }
+
+    #[test]
+    fn test_validate_pubkey_rejects_non_hex() {
+        let typo = PAYER.replacen('1', "g", 1);
+        assert_eq!(typo.len(), 66);
+        assert_eq!(validate_pubkey(&typo), Err(ValidationError::InvalidPubkey));
+        assert_eq!(
+            test_builder().merchant_pubkey("03def456...").build(),
+            Err(ValidationError::InvalidPubkey)
+        );
+    }
+
+    // BIP-173 and BIP-350 test vectors
+    const MAINNET_ADDRESS: &str = "bc1qw508d6qejxtdg4y5r3zarvary0c5xw7kv8f3t4";
+    const TAPROOT_ADDRESS: &str = "bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0";
+    const TESTNET_ADDRESS: &str = "tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7";
+
+    #[test]
+    fn test_recipient_parses_mainnet_bech32() {
+        let recipient = Recipient::parse(MAINNET_ADDRESS).unwrap();
+        assert_eq!(recipient, Recipient::Bech32(MAINNET_ADDRESS.to_string()));
+        assert_eq!(
+            recipient.script_pubkey().map(hex::encode).as_deref(),
+            Some("0014751e76e8199196d454941c45d1b3a323f1433bd6")
+        );
+        assert_eq!(recipient.to_string(), MAINNET_ADDRESS);
+
+        let upper = MAINNET_ADDRESS.to_ascii_uppercase();
+        assert_eq!(
+            Recipient::parse(&upper).unwrap().script_pubkey(),
+            recipient.script_pubkey()
+        );
+
+        let taproot = Recipient::parse(TAPROOT_ADDRESS).unwrap();
+        assert_eq!(
+            taproot.script_pubkey().map(hex::encode).as_deref(),
+            Some("512079be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+        );
+
+        assert_eq!(
+            Recipient::parse(MERCHANT),
+            Ok(Recipient::Pubkey(MERCHANT.to_string()))
+        );
+    }
+
+    #[test]
+    fn test_recipient_parses_testnet_bech32() {
+        let recipient = Recipient::parse(TESTNET_ADDRESS).unwrap();
+        assert_eq!(recipient, Recipient::Bech32(TESTNET_ADDRESS.to_string()));
+        assert_eq!(
+            recipient.script_pubkey().map(hex::encode).as_deref(),
+            Some("00201863143c14c5166804bd19203356da136c985678cd4d27a1b8c6329604903262")
+        );
+    }
+
+    #[test]
+    fn test_recipient_rejects_invalid_checksum() {
+        let corrupted = MAINNET_ADDRESS.replace("3t4", "3t5");
+        assert_eq!(
+            Recipient::parse(&corrupted),
+            Err(ValidationError::InvalidRecipient)
+        );
+        assert_eq!(
+            test_builder().merchant_pubkey(corrupted).build(),
+            Err(ValidationError::InvalidRecipient)
+        );
+        // Mixing upper and lower case is not a valid encoding either
+        let mixed_case = format!(
+            "{}{}",
+            &MAINNET_ADDRESS[..10],
+            MAINNET_ADDRESS[10..].to_uppercase()
+        );
+        assert_eq!(
+            Recipient::parse(&mixed_case),
+            Err(ValidationError::InvalidRecipient)
+        );
+    }
+
+    #[test]
+    fn test_payment_to_bech32_merchant() {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder()
+            .merchant_pubkey(MAINNET_ADDRESS)
+            .last_payment_block(850000)
+            .build()
+            .unwrap();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        let mut tx = transition_tx(&in_state, &out_state, 1000000, 900000);
+        add_payout(&mut tx, MAINNET_ADDRESS, 100000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(block)),
+            Ok(())
+        );
+
+        // The address is stored as-is and survives serialization
+        let data = Data::from(&in_state);
+        assert_eq!(
+            data.value::<MinimalSubscriptionState>()
+                .unwrap()
+                .merchant_pubkey,
+            MAINNET_ADDRESS
+        );
+
+        let mut misdirected = transition_tx(&in_state, &out_state, 1000000, 900000);
+        add_payout(&mut misdirected, TESTNET_ADDRESS, 100000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &misdirected, &block_witness(block)),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 100000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_validate_accepts_consistent_state() {
+        assert_eq!(active_state().validate(), Ok(()));
+
+        let data = Data::from(&active_state());
+        let untrusted: MinimalSubscriptionState = data.value().unwrap();
+        assert_eq!(untrusted.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_validate_rejects_each_invariant() {
+        let cases = [
+            (
+                MinimalSubscriptionState {
+                    subscription_id: String::new(),
+                    ..active_state()
+                },
+                ValidationError::EmptySubscriptionId,
+            ),
+            (
+                MinimalSubscriptionState {
+                    payer_pubkey: "02abc...".to_string(),
+                    ..active_state()
+                },
+                ValidationError::InvalidPubkey,
+            ),
+            (
+                MinimalSubscriptionState {
+                    merchant_pubkey: "bc1qinvalid".to_string(),
+                    ..active_state()
+                },
+                ValidationError::InvalidRecipient,
+            ),
+            (
+                MinimalSubscriptionState {
+                    amount_sats: 0,
+                    ..active_state()
+                },
+                ValidationError::ZeroAmount,
+            ),
+            (
+                MinimalSubscriptionState {
+                    billing_interval_blocks: 0,
+                    ..active_state()
+                },
+                ValidationError::ZeroInterval,
+            ),
+            (
+                MinimalSubscriptionState {
+                    min_amount_sats: 150000,
+                    max_amount_sats: 50000,
+                    ..active_state()
+                },
+                ValidationError::InvalidAmountBounds {
+                    min: 150000,
+                    max: 50000,
+                },
+            ),
+            (
+                MinimalSubscriptionState {
+                    platform_fee_sats: 100001,
+                    ..active_state()
+                },
+                ValidationError::FeeExceedsAmount {
+                    fee: 100001,
+                    amount: 100000,
+                },
+            ),
+            (
+                MinimalSubscriptionState {
+                    remaining_balance: 99999,
+                    ..active_state()
+                },
+                ValidationError::InsufficientBalance {
+                    required: 100000,
+                    available: 99999,
+                },
+            ),
+            (
+                MinimalSubscriptionState {
+                    prepaid_cycles: 11,
+                    ..active_state()
+                },
+                ValidationError::PrepaidBalanceMismatch {
+                    expected: 1100000,
+                    actual: 1000000,
+                },
+            ),
+        ];
+        for (state, error) in cases {
+            assert_eq!(state.validate(), Err(error));
+        }
+    }
+
+    // Payment transactions of every state version, each with the decision it must receive
+    fn payment_fixtures() -> Vec<(Transaction, Data, bool)> {
+        let (nft_app, _) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let due = payment_tx(&in_state, &out_state);
+        let unpaid = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let mut second_nft = due.clone();
+        second_nft
+            .outs
+            .push(charms(&[(&nft_app, Data::from(&active_state()))]));
+
+        let (split_in, split_out, split_block) = split_states(vec![
+            (MERCHANT.to_string(), 7000),
+            (PLATFORM.to_string(), 3000),
+        ]);
+        let split = split_tx(
+            &split_in,
+            &split_out,
+            &[(MERCHANT, 70000), (PLATFORM, 30000)],
+        );
+        let short_split = split_tx(&split_in, &split_out, &[(MERCHANT, 70000)]);
+
+        let (metered_in, metered_out, metered_witness) = metered_states(120);
+        let (_, _, overreported_witness) = metered_states(100);
+        let (_, tiered, tiered_witness) = tiered_payment(&tiered_state(200000), 100000);
+        let (_, discounted, discounted_witness) = tiered_payment(&tiered_state(200000), 80000);
+
+        vec![
+            (due.clone(), block_witness(block), true),
+            (due, block_witness(block - 1), false),
+            (unpaid, block_witness(block), false),
+            (second_nft, block_witness(block), false),
+            (split, block_witness(split_block), true),
+            (short_split, block_witness(split_block), false),
+            (metered_tx(&metered_in, &metered_out), metered_witness, true),
+            (
+                metered_tx(&metered_in, &metered_out),
+                overreported_witness,
+                false,
+            ),
+            (tiered, tiered_witness, true),
+            (discounted, discounted_witness, false),
+        ]
+    }
+
+    #[test]
+    fn test_scan_tx_matches_separate_passes() {
+        let (nft_app, token_app) = test_apps();
+        let as_data = |state: Option<SubscriptionStateV>| state.as_ref().map(Data::from);
+
+        for (tx, witness, accepted) in payment_fixtures() {
+            let scan = scan_tx(&token_app, &nft_app, &tx).unwrap();
+            assert_eq!(scan.nft_outputs, nft_output_count(&nft_app, &tx));
+            assert_eq!(
+                (scan.input_tokens, scan.output_tokens),
+                token_totals(&token_app, &tx).unwrap()
+            );
+            assert_eq!(
+                as_data(scan.incoming_state),
+                as_data(find_subscription_state(
+                    &nft_app,
+                    tx.ins.iter().map(|(_, v)| v)
+                ))
+            );
+            assert_eq!(
+                as_data(scan.outgoing_state),
+                as_data(find_subscription_state(&nft_app, tx.outs.iter()))
+            );
+
+            let witness: SubscriptionWitness = witness.value().unwrap();
+            assert_eq!(
+                can_execute_subscription_payment(&token_app, &nft_app, &tx, &witness).is_ok(),
+                accepted
+            );
+        }
+    }
+
+    #[test]
+    fn test_sibling_apps_pair_nft_and_token() {
+        let (nft_app, token_app) = test_apps();
+        assert!(sibling_app(&token_app, NFT) == nft_app);
+        assert!(sibling_app(&nft_app, TOKEN) == token_app);
+
+        // A payment is accepted through either half of the pair
+        let (in_state, out_state, block) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+        assert!(app_contract(
+            &token_app,
+            &tx,
+            &Data::empty(),
+            &block_witness(block)
+        ));
+        assert!(app_contract(
+            &nft_app,
+            &tx,
+            &Data::empty(),
+            &block_witness(block)
+        ));
+
+        // An NFT of another identity does not manage these tokens
+        let foreign_token = App {
+            identity: B32([9; 32]),
+            ..token_app
+        };
+        assert_eq!(
+            dry_run(&foreign_token, &tx, &Data::empty(), &block_witness(block)),
+            Err(ValidationError::EmptyTransaction)
+        );
+    }
+
+    mod token_conservation {
+        use super::*;
+        use proptest::prelude::*;
+
+        // Amounts shrink towards zero; the extremes are drawn often enough to hit the
+        // overflow and underflow paths
+        fn amount() -> impl Strategy<Value = u64> {
+            prop_oneof![
+                4 => 0..=2_000_000u64,
+                1 => Just(u64::MAX),
+                1 => any::<u64>(),
+            ]
+        }
+
+        // Supplies and token amounts as (incoming supply, outgoing supply, input, output);
+        // half the cases are balanced up to a nudge of one token, so acceptances are common
+        fn supply_case() -> impl Strategy<Value = (u64, u64, u64, u64)> {
+            (
+                amount(),
+                amount(),
+                amount(),
+                amount(),
+                -1i64..=1,
+                any::<bool>(),
+            )
+                .prop_map(|(incoming, outgoing, input, output, nudge, balanced)| {
+                    let output = if balanced {
+                        input
+                            .wrapping_add(incoming.wrapping_sub(outgoing))
+                            .wrapping_add_signed(nudge)
+                    } else {
+                        output
+                    };
+                    (incoming, outgoing, input, output)
+                })
+        }
+
+        fn legacy_mint(incoming: u64, outgoing: u64, input: u64, output: u64) -> Transaction {
+            let (nft_app, _) = test_apps();
+            let nft = |remaining| NftContent {
+                ticker: "SUBSCRIPTION-sub_001".to_string(),
+                remaining,
+            };
+            TxBuilder::new(&nft_app)
+                .nft_input(&nft(incoming))
+                .token_input(input)
+                .nft_output(&nft(outgoing))
+                .token_output(output)
+                .build()
+        }
+
+        proptest! {
+            #[test]
+            fn can_mint_token_accepts_exactly_the_released_supply(
+                (incoming, outgoing, input, output) in supply_case()
+            ) {
+                let (nft_app, token_app) = test_apps();
+                let tx = legacy_mint(incoming, outgoing, input, output);
+                let expected = incoming >= outgoing
+                    && output.checked_sub(input) == Some(incoming - outgoing);
+                prop_assert_eq!(can_mint_token(&token_app, &nft_app, &tx).is_ok(), expected);
+            }
+        }
+    }
+
+    mod state_decoding {
+        use super::*;
+        use charms_sdk::data::util;
+        use proptest::prelude::*;
+        use proptest::sample::Index;
+
+        // Decode `bytes` every way the contract reads untrusted charm data; errors are expected,
+        // panics are not
+        fn decode_all(bytes: &[u8]) {
+            let _ = util::read::<MinimalSubscriptionState, _>(bytes);
+            let _ = util::read::<NftContent, _>(bytes);
+            let _ = util::read::<SubscriptionState, _>(bytes);
+            let _ = util::read::<SubscriptionStateV, _>(bytes);
+            let Ok(data) = util::read::<Data, _>(bytes) else {
+                return;
+            };
+            let _ = data.value::<MinimalSubscriptionState>();
+            let _ = data.value::<NftContent>();
+            let _ = data.value::<SubscriptionState>();
+            let _ = SubscriptionStateV::parse(&data);
+
+            // The decoded value also goes through the whole contract as an NFT state
+            let (nft_app, token_app) = test_apps();
+            let tx = TxBuilder::new(&nft_app)
+                .nft_input(&data)
+                .token_input(1000000)
+                .nft_output(&data)
+                .token_output(1000000)
+                .build();
+            let _ = dry_run(&token_app, &tx, &Data::empty(), &block_witness(850144));
+            let _ = dry_run(&nft_app, &tx, &Data::empty(), &block_witness(850144));
+        }
+
+        // Valid encodings of every state version, as seeds for byte-level mutations
+        fn encoded_states() -> Vec<Vec<u8>> {
+            let split = SplitSubscriptionState {
+                subscription: active_state(),
+                splits: vec![(MERCHANT.to_string(), 10000)],
+            };
+            vec![
+                Data::from(&active_state()).bytes(),
+                Data::from(&NftContent::from(&active_state())).bytes(),
+                Data::from(&SubscriptionStateV::V2(active_state())).bytes(),
+                Data::from(&SubscriptionStateV::V3(split)).bytes(),
+                Data::from(&SubscriptionStateV::V5(tiered_state(0))).bytes(),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn arbitrary_bytes_never_panic(bytes in prop::collection::vec(any::<u8>(), 0..512)) {
+                decode_all(&bytes);
+            }
+
+            #[test]
+            fn mutated_states_never_panic(
+                seed in prop::sample::select(encoded_states()),
+                mutations in prop::collection::vec((any::<Index>(), any::<u8>()), 1..8),
+                truncate in any::<Index>(),
+            ) {
+                let mut bytes = seed;
+                for (index, byte) in mutations {
+                    let index = index.index(bytes.len());
+                    bytes[index] = byte;
+                }
+                decode_all(&bytes);
+                decode_all(&bytes[..truncate.index(bytes.len())]);
+            }
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    mod cbor {
+        use super::*;
+
+        // A state as a non-Rust client writes it: only the core fields, under their short keys
+        // {"p": "02ab", "m": "03de", "a": 100000, "i": 144, "l": 850000, "act": true,
+        //  "bal": 900000}
+        const CLIENT_STATE: &str = concat!(
+            "a7",
+            "6170",
+            "6430326162",
+            "616d",
+            "6430336465",
+            "6161",
+            "1a000186a0",
+            "6169",
+            "1890",
+            "616c",
+            "1a000cf850",
+            "63616374",
+            "f5",
+            "6362616c",
+            "1a000dbba0",
+        );
+
+        #[test]
+        fn test_cbor_round_trip() {
+            let state = MinimalSubscriptionState {
+                coupon_hash: Some(hash(COUPON)),
+                max_amount_cap: Some(2000000),
+                authorization_mode: AuthorizationMode::Pull,
+                ..active_state()
+            };
+            let bytes = state.to_cbor();
+
+            // The encoding is the one the charm data layer stores, so states decode either way
+            assert_eq!(bytes, Data::from(&state).bytes());
+            assert_eq!(
+                MinimalSubscriptionState::from_cbor(&bytes),
+                Ok(state.clone())
+            );
+            let data: Data = charms_sdk::data::util::read(bytes.as_slice()).unwrap();
+            assert!(matches!(
+                SubscriptionStateV::parse(&data),
+                Ok(SubscriptionStateV::V2(parsed)) if parsed == state
+            ));
+        }
+
+        #[test]
+        fn test_cbor_known_vector() {
+            let bytes = hex::decode(CLIENT_STATE).unwrap();
+            let state = MinimalSubscriptionState::from_cbor(&bytes).unwrap();
+            assert_eq!(
+                state,
+                MinimalSubscriptionState {
+                    payer_pubkey: "02ab".to_string(),
+                    merchant_pubkey: "03de".to_string(),
+                    amount_sats: 100000,
+                    billing_interval_blocks: 144,
+                    last_payment_block: 850000,
+                    is_active: true,
+                    remaining_balance: 900000,
+                    ..Default::default()
+                }
+            );
+            assert_eq!(
+                MinimalSubscriptionState::from_cbor(&bytes[..bytes.len() - 1]),
+                Err(ValidationError::InvalidNftState)
+            );
+        }
+    }
+
+    #[cfg(feature = "schemars")]
+    mod json_schema {
+        use super::*;
+
+        #[test]
+        fn test_schema_describes_every_field() {
+            let schema = schema();
+            let properties = schema
+                .get("properties")
+                .and_then(|properties| properties.as_object())
+                .unwrap();
+
+            // Every serialized key of a state has a property, and nothing else does
+            let data = Data::from(&active_state());
+            let keys: std::collections::BTreeMap<String, Data> = data.value().unwrap();
+            assert_eq!(
+                properties.keys().collect::<Vec<_>>(),
+                keys.keys().collect::<Vec<_>>()
+            );
+
+            let type_of = |key: &str| properties[key]["type"].clone();
+            assert_eq!(type_of("p"), "string");
+            assert_eq!(type_of("a"), "integer");
+            assert_eq!(type_of("act"), "boolean");
+            assert_eq!(properties["a"]["format"], "uint64");
+            assert_eq!(properties["i"]["format"], "uint32");
+            let hash_types = type_of("coupon_hash");
+            let hash_types = hash_types.as_array().unwrap();
+            assert!(hash_types.iter().any(|t| t == "string"));
+            assert!(hash_types.iter().any(|t| t == "null"));
+            assert!(properties["p"]["description"]
+                .as_str()
+                .unwrap()
+                .starts_with("Public key or address of the payer"));
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    mod tracing_events {
+        use super::*;
+        use std::fmt;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata, Subscriber};
+
+        // Subscriber recording the fields of every event as (name, debug value) pairs
+        #[derive(Clone, Default)]
+        struct Recorder(Arc<Mutex<Vec<Fields>>>);
+
+        #[derive(Default)]
+        struct Fields(Vec<(String, String)>);
+
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+                self.0
+                    .push((field.name().to_string(), format!("{value:?}")));
+            }
+        }
+
+        impl Subscriber for Recorder {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, _: &Attributes<'_>) -> Id {
+                Id::from_u64(1)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields::default();
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        #[test]
+        fn rejected_mint_emits_supply_fields() {
+            let (nft_app, token_app) = test_apps();
+            let nft = |remaining| NftContent {
+                ticker: "SUBSCRIPTION-sub_001".to_string(),
+                remaining,
+            };
+            let tx = TxBuilder::new(&nft_app)
+                .nft_input(&nft(500000))
+                .token_input(0)
+                .nft_output(&nft(600000))
+                .token_output(100000)
+                .build();
+
+            let recorder = Recorder::default();
+            tracing::subscriber::with_default(recorder.clone(), || {
+                assert!(!app_contract(
+                    &token_app,
+                    &tx,
+                    &Data::empty(),
+                    &Data::empty()
+                ));
+            });
+
+            let events = recorder.0.lock().unwrap();
+            let has_field = |name: &str, value: &str| {
+                events
+                    .iter()
+                    .any(|fields| fields.0.contains(&(name.to_string(), value.to_string())))
+            };
+            assert!(has_field("incoming_supply", "500000"));
+            assert!(has_field("outgoing_supply", "600000"));
+            assert!(has_field("error", "SupplyIncreased"));
+        }
+    }
+
+    #[test]
+    fn test_classify_created() {
+        let (nft_app, token_app) = test_apps();
+        let tx = TxBuilder::new(&nft_app)
+            .nft_output(&active_state())
+            .token_output(1000000)
+            .build();
+
+        assert_eq!(classify_tx(&nft_app, &tx), Some(SubscriptionEvent::Created));
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Created)
+        );
+    }
+
+    #[test]
+    fn test_classify_payment() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, _) = due_payment();
+        let tx = payment_tx(&in_state, &out_state);
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Payment { amount: 100000 })
+        );
+
+        // The final cycle of a fixed-term subscription is a payment, not a cancellation
+        let in_state = MinimalSubscriptionState {
+            max_cycles: 1,
+            ..active_state()
+        };
+        let out_state = in_state.apply_payment(in_state.next_due_block()).unwrap();
+        assert!(!out_state.is_active);
+        let tx = payment_tx(&in_state, &out_state);
+        assert_eq!(
+            classify_tx(&nft_app, &tx),
+            Some(SubscriptionEvent::Payment { amount: 100000 })
+        );
+
+        // Legacy states are paid by lowering their remaining supply
+        let legacy = NftContent::from(&active_state());
+        let paid = NftContent {
+            remaining: 900000,
+            ..legacy.clone()
+        };
+        let tx = transition_tx(&legacy, &paid, 1000000, 1000000);
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Payment { amount: 100000 })
+        );
+    }
+
+    #[test]
+    fn test_classify_top_up() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 1500000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1500000);
+
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::TopUp { amount: 500000 })
+        );
+    }
+
+    #[test]
+    fn test_classify_pause_and_resume() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let paused = MinimalSubscriptionState {
+            paused: true,
+            ..in_state.clone()
+        };
+
+        let tx = transition_tx(&in_state, &paused, 1000000, 1000000);
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Paused)
+        );
+        let tx = transition_tx(&paused, &in_state, 1000000, 1000000);
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Resumed)
+        );
+    }
+
+    #[test]
+    fn test_classify_cancelled() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::Cancelled)
+        );
+    }
+
+    #[test]
+    fn test_classify_other_shapes() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+
+        // Ownership transfers and no-op transitions are not lifecycle steps
+        let transferred = MinimalSubscriptionState {
+            payer_pubkey: STRANGER.to_string(),
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &transferred, 1000000, 1000000);
+        assert_eq!(classify_tx(&token_app, &tx), None);
+        let tx = transition_tx(&in_state, &in_state, 1000000, 1000000);
+        assert_eq!(classify_tx(&token_app, &tx), None);
+
+        // Nor is a transaction without a subscription NFT output
+        let tx = test_tx(vec![], vec![]);
+        assert_eq!(classify_tx(&token_app, &tx), None);
+    }
+
+    fn stablecoin_app(identity: u8) -> App {
+        App {
+            tag: TOKEN,
+            identity: B32([identity; 32]),
+            vk: B32([8; 32]),
+        }
+    }
+
+    // A payment of a stablecoin-denominated subscription, with its tokens in `payment_token`
+    fn denominated_payment(payment_token: &App) -> (Transaction, Data) {
+        let (nft_app, _) = test_apps();
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .denomination_token(stablecoin_app(7).identity)
+            .build()
+            .unwrap();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        let tx = TxBuilder::new(&nft_app)
+            .with_token(payment_token)
+            .held_by(HOLDER)
+            .nft_input(&in_state)
+            .token_input(1000000)
+            .nft_output(&out_state)
+            .token_output(900000)
+            .payout(MERCHANT, 100000)
+            .build();
+        (tx, block_witness(block))
+    }
+
+    #[test]
+    fn test_stablecoin_denominated_payment() {
+        let (nft_app, token_app) = test_apps();
+        let (tx, witness) = denominated_payment(&stablecoin_app(7));
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &witness), Ok(()));
+    }
+
+    #[test]
+    fn test_denominated_payment_in_wrong_token() {
+        let (nft_app, token_app) = test_apps();
+
+        // Paying in the subscription's own token
+        let (tx, witness) = denominated_payment(&token_app);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::WrongPaymentToken)
+        );
+
+        // Paying in another stablecoin, which no state declares
+        let (tx, witness) = denominated_payment(&stablecoin_app(9));
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &witness),
+            Err(ValidationError::ForeignToken)
+        );
+    }
+
+    #[test]
+    fn test_foreign_token_in_outputs() {
+        let (nft_app, token_app) = test_apps();
+        let (in_state, out_state, block) = due_payment();
+        let w = block_witness(block);
+        let mut tx = payment_tx(&in_state, &out_state);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+
+        // A stray token of another app riding along with the payout
+        tx.outs
+            .last_mut()
+            .unwrap()
+            .insert(stablecoin_app(9), Data::from(&5u64));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::ForeignToken)
+        );
+        assert_eq!(
+            nft_contract_satisfied(&nft_app, &tx, &w),
+            Err(ValidationError::ForeignToken)
+        );
+
+        // Nor can a token of the same identity under another verification key pass as ours
+        let mut tx = payment_tx(&in_state, &out_state);
+        let impostor = App {
+            vk: B32([9; 32]),
+            ..token_app.clone()
+        };
+        tx.outs.push(charms(&[(&impostor, Data::from(&5u64))]));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::ForeignToken)
+        );
+    }
+
+    // Cancel active_state() (cycle from 850000 to 850144) at `block`, paying out `refund` to
+    // the payer and the rest of the balance to the merchant
+    fn prorated_cancellation(block: u32, refund: u64) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, refund);
+        add_payout(&mut tx, MERCHANT, 1000000 - refund);
+        token_contract_satisfied(&token_app, &tx, &block_witness(block))
+    }
+
+    #[test]
+    fn test_prorated_cancellation_at_cycle_start() {
+        assert_eq!(active_state().prorated_charge(850000), 0);
+        // The whole current cycle is refunded along with the rest of the balance
+        assert_eq!(prorated_cancellation(850000, 1000000), Ok(()));
+    }
+
+    #[test]
+    fn test_prorated_cancellation_at_midpoint() {
+        assert_eq!(active_state().prorated_charge(850072), 50000);
+        assert_eq!(prorated_cancellation(850072, 950000), Ok(()));
+        // Refunding the whole balance leaves the merchant unpaid for the elapsed half
+        assert_eq!(
+            prorated_cancellation(850072, 1000000),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 50000,
+                actual: 0
+            })
+        );
+        // And the payer cannot be shorted on the unused half
+        assert_eq!(
+            prorated_cancellation(850072, 900000),
+            Err(ValidationError::Underpaid {
+                recipient: "payer",
+                expected: 950000,
+                actual: 900000
+            })
+        );
+    }
+
+    #[test]
+    fn test_prorated_cancellation_before_next_due_block() {
+        // One block of the 144 remains: 100000 * 143 / 144 is earned, 695 refunded
+        assert_eq!(active_state().prorated_charge(850143), 99305);
+        assert_eq!(prorated_cancellation(850143, 900695), Ok(()));
+        // Once due, the whole cycle is earned
+        assert_eq!(active_state().prorated_charge(850144), 100000);
+        assert_eq!(active_state().prorated_charge(900000), 100000);
+    }
+
+    #[test]
+    fn test_prorated_cancellation_in_millisatoshis() {
+        let (_, token_app) = test_apps();
+        // 100000000 millisatoshis a cycle are 100000 tokens, half of them earned at the midpoint
+        let in_state = MinimalSubscriptionState {
+            amount_sats: 100000000,
+            currency_unit: CurrencyUnit::MilliSatoshi,
+            ..active_state()
+        };
+        assert_eq!(in_state.prorated_charge(850072), 50000);
+        let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 950000);
+        add_payout(&mut tx, MERCHANT, 50000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850072)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_prorated_cancellation_requires_block() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::MissingBlockHeight)
+        );
+    }
+
+    fn dunning_state() -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            max_attempts: 3,
+            ..active_state()
+        }
+    }
+
+    #[test]
+    fn test_failed_attempt_increments_counter() {
+        let (nft_app, token_app) = test_apps();
+        let in_state = dunning_state();
+        let out_state = in_state.record_failed_attempt().unwrap();
+        assert_eq!(out_state.failed_attempts, 1);
+        assert!(out_state.is_active);
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        let witness = block_witness(in_state.next_due_block());
+
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+        assert_eq!(nft_contract_satisfied(&nft_app, &tx, &witness), Ok(()));
+        assert_eq!(
+            classify_tx(&token_app, &tx),
+            Some(SubscriptionEvent::PaymentFailed { failed_attempts: 1 })
+        );
+
+        // Recording a failure cannot skip attempts, move funds or precede the due block
+        let skipped = MinimalSubscriptionState {
+            failed_attempts: 2,
+            ..out_state.clone()
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &transition_tx(&in_state, &skipped, 1000000, 1000000),
+                &witness
+            ),
+            Err(ValidationError::UnexpectedFieldChange("failed_attempts"))
+        );
+        let drained = MinimalSubscriptionState {
+            remaining_balance: 900000,
+            ..out_state.clone()
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &transition_tx(&in_state, &drained, 1000000, 900000),
+                &witness
+            ),
+            Err(ValidationError::UnexpectedFieldChange("retried state"))
+        );
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &tx,
+                &block_witness(in_state.next_due_block() - 1)
+            ),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+    }
+
+    #[test]
+    fn test_failed_attempt_requires_dunning() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = MinimalSubscriptionState {
+            failed_attempts: 1,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Err(ValidationError::DunningDisabled)
+        );
+    }
+
+    #[test]
+    fn test_failed_attempts_deactivate_at_cap() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            failed_attempts: 2,
+            ..dunning_state()
+        };
+        let out_state = in_state.record_failed_attempt().unwrap();
+        assert_eq!(out_state.failed_attempts, 3);
+        assert!(!out_state.is_active);
+        assert!(out_state.is_dunning_exhausted());
+        let witness = block_witness(850144);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &transition_tx(&in_state, &out_state, 1000000, 1000000),
+                &witness
+            ),
+            Ok(())
+        );
+
+        // The final attempt must deactivate, and no further failure can be recorded
+        let still_active = MinimalSubscriptionState {
+            is_active: true,
+            ..out_state.clone()
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &transition_tx(&in_state, &still_active, 1000000, 1000000),
+                &witness
+            ),
+            Err(ValidationError::UnexpectedFieldChange("is_active"))
+        );
+        assert_eq!(
+            out_state.record_failed_attempt(),
+            Err(ValidationError::SubscriptionInactive)
+        );
+        assert_eq!(
+            out_state.apply_payment(850144),
+            Err(ValidationError::SubscriptionInactive)
+        );
+    }
+
+    #[test]
+    fn test_dunning_exhausted_subscription_can_be_cancelled() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            failed_attempts: 3,
+            is_active: false,
+            ..dunning_state()
+        };
+        // The merchant keeps the overdue cycle, the payer recovers the rest of the balance
+        let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+        add_payout(&mut tx, PAYER, 900000);
+        add_payout(&mut tx, MERCHANT, 100000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_successful_payment_resets_failed_attempts() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            failed_attempts: 2,
+            ..dunning_state()
+        };
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        assert_eq!(out_state.failed_attempts, 0);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Ok(())
+        );
+
+        let not_reset = MinimalSubscriptionState {
+            failed_attempts: 2,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &not_reset),
+                &block_witness(block)
+            ),
+            Err(ValidationError::UnexpectedFieldChange("failed_attempts"))
+        );
+    }
+
+    const PAYER_AUTH: &str = "payer-authorization";
+    const MERCHANT_AUTH: &str = "merchant-authorization";
+
+    // Pay the first due cycle of a subscription authorized in `mode`, revealing `payment_auth`
+    fn authorized_payment(
+        mode: AuthorizationMode,
+        payment_auth: Option<&str>,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .authorization_mode(mode)
+            .payer_auth(PAYER_AUTH)
+            .merchant_auth(MERCHANT_AUTH)
+            .build()
+            .unwrap();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            payment_auth: payment_auth.map(str::to_string),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &payment_tx(&in_state, &out_state), &witness)
+    }
+
+    #[test]
+    fn test_pull_payment_authorized_by_merchant() {
+        assert_eq!(
+            authorized_payment(AuthorizationMode::Pull, Some(MERCHANT_AUTH)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_pull_payment_authorized_by_payer() {
+        assert_eq!(
+            authorized_payment(AuthorizationMode::Pull, Some(PAYER_AUTH)),
+            Err(ValidationError::PaymentNotAuthorized)
+        );
+        assert_eq!(
+            authorized_payment(AuthorizationMode::Pull, None),
+            Err(ValidationError::PaymentNotAuthorized)
+        );
+    }
+
+    #[test]
+    fn test_push_payment_authorized_by_payer() {
+        assert_eq!(
+            authorized_payment(AuthorizationMode::Push, Some(PAYER_AUTH)),
+            Ok(())
+        );
+        assert_eq!(
+            authorized_payment(AuthorizationMode::Push, Some(MERCHANT_AUTH)),
+            Err(ValidationError::PaymentNotAuthorized)
+        );
+    }
+
+    #[test]
+    fn test_authorization_mode_is_immutable() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let block = in_state.next_due_block();
+        let out_state = MinimalSubscriptionState {
+            authorization_mode: AuthorizationMode::Pull,
+            ..in_state.apply_payment(block).unwrap()
+        };
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Err(ValidationError::ImmutableFieldChanged("authorization_mode"))
+        );
+    }
+
+    const PAYER_CONSENT: &str = "payer-reassignment-consent";
+    const MERCHANT_CONSENT: &str = "merchant-reassignment-consent";
+
+    // A subscription restricted to `allowed_merchants` whose merchant can be reassigned with
+    // both consents, and its reassignment to STRANGER under fresh consent hashes
+    fn reassignable_states(
+        allowed_merchants: &[&str],
+    ) -> (MinimalSubscriptionState, MinimalSubscriptionState) {
+        let in_state = test_builder()
+            .payer_auth(PAYER_AUTH)
+            .merchant_auth(MERCHANT_AUTH)
+            .reassignment_auth(PAYER_CONSENT, MERCHANT_CONSENT)
+            .allowed_merchants(allowed_merchants.iter().copied())
+            .build()
+            .unwrap();
+        let out_state = MinimalSubscriptionState {
+            merchant_pubkey: STRANGER.to_string(),
+            payer_reassign_auth_hash: Some(hash("next-payer-consent")),
+            merchant_reassign_auth_hash: Some(hash("next-merchant-consent")),
+            nonce: 1,
+            ..in_state.clone()
+        };
+        (in_state, out_state)
+    }
+
+    fn reassignment_tx(
+        in_state: &MinimalSubscriptionState,
+        out_state: &MinimalSubscriptionState,
+        payer_auth: Option<&str>,
+        merchant_auth: Option<&str>,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let witness = Data::from(&SubscriptionWitness {
+            payer_auth: payer_auth.map(str::to_string),
+            merchant_auth: merchant_auth.map(str::to_string),
+            ..Default::default()
+        });
+        let tx = transition_tx(in_state, out_state, 1000000, 1000000);
+        token_contract_satisfied(&token_app, &tx, &witness)
+    }
+
+    // Move the payments of `reassignable_states` to STRANGER, revealing `payer_auth` and
+    // `merchant_auth`
+    fn merchant_reassignment(
+        payer_auth: Option<&str>,
+        merchant_auth: Option<&str>,
+        allowed_merchants: &[&str],
+    ) -> Result<(), ValidationError> {
+        let (in_state, out_state) = reassignable_states(allowed_merchants);
+        reassignment_tx(&in_state, &out_state, payer_auth, merchant_auth)
+    }
+
+    #[test]
+    fn test_merchant_reassignment_with_both_authorizations() {
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_CONSENT), Some(MERCHANT_CONSENT), &[]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_merchant_reassignment_without_merchant_auth() {
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_CONSENT), None, &[]),
+            Err(ValidationError::ReassignmentNotAuthorized("merchant"))
+        );
+        // Each preimage must match its own party's hash
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_CONSENT), Some(PAYER_CONSENT), &[]),
+            Err(ValidationError::ReassignmentNotAuthorized("merchant"))
+        );
+    }
+
+    #[test]
+    fn test_merchant_reassignment_without_payer_auth() {
+        assert_eq!(
+            merchant_reassignment(None, Some(MERCHANT_CONSENT), &[]),
+            Err(ValidationError::ReassignmentNotAuthorized("payer"))
+        );
+    }
+
+    #[test]
+    fn test_merchant_reassignment_rejects_payment_authorizations() {
+        // Payments reveal these preimages, so they cannot stand in for the consents
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_AUTH), Some(MERCHANT_AUTH), &[]),
+            Err(ValidationError::ReassignmentNotAuthorized("payer"))
+        );
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_CONSENT), Some(MERCHANT_AUTH), &[]),
+            Err(ValidationError::ReassignmentNotAuthorized("merchant"))
+        );
+    }
+
+    #[test]
+    fn test_merchant_reassignment_rotates_consents() {
+        let (in_state, out_state) = reassignable_states(&[]);
+        let replayable = MinimalSubscriptionState {
+            payer_reassign_auth_hash: in_state.payer_reassign_auth_hash.clone(),
+            ..out_state.clone()
+        };
+        assert_eq!(
+            reassignment_tx(
+                &in_state,
+                &replayable,
+                Some(PAYER_CONSENT),
+                Some(MERCHANT_CONSENT)
+            ),
+            Err(ValidationError::AuthorizationNotRotated("payer"))
+        );
+        let replayable = MinimalSubscriptionState {
+            merchant_reassign_auth_hash: in_state.merchant_reassign_auth_hash.clone(),
+            ..out_state.clone()
+        };
+        assert_eq!(
+            reassignment_tx(
+                &in_state,
+                &replayable,
+                Some(PAYER_CONSENT),
+                Some(MERCHANT_CONSENT)
+            ),
+            Err(ValidationError::AuthorizationNotRotated("merchant"))
+        );
+
+        // Once revealed, the consents no longer move the subscription any further
+        let onward = MinimalSubscriptionState {
+            merchant_pubkey: MERCHANT.to_string(),
+            nonce: 2,
+            ..out_state.clone()
+        };
+        assert_eq!(
+            reassignment_tx(
+                &out_state,
+                &onward,
+                Some(PAYER_CONSENT),
+                Some(MERCHANT_CONSENT)
+            ),
+            Err(ValidationError::ReassignmentNotAuthorized("payer"))
+        );
+    }
+
+    #[test]
+    fn test_payment_cannot_replace_consents() {
+        let (_, token_app) = test_apps();
+        let (in_state, _) = reassignable_states(&[]);
+        let block = in_state.next_due_block();
+        let out_state = MinimalSubscriptionState {
+            merchant_reassign_auth_hash: Some(hash("payer-chosen-consent")),
+            ..in_state.apply_payment(block).unwrap()
+        };
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            payment_auth: Some(PAYER_AUTH.to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &payment_tx(&in_state, &out_state), &witness),
+            Err(ValidationError::ImmutableFieldChanged(
+                "merchant_reassign_auth_hash"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_merchant_reassignment_outside_allowed_merchants() {
+        assert_eq!(
+            merchant_reassignment(Some(PAYER_CONSENT), Some(MERCHANT_CONSENT), &[MERCHANT]),
+            Err(ValidationError::MerchantNotAllowed)
+        );
+        assert_eq!(
+            merchant_reassignment(
+                Some(PAYER_CONSENT),
+                Some(MERCHANT_CONSENT),
+                &[MERCHANT, STRANGER]
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_to_allowed_merchant() {
+        let (_, token_app) = test_apps();
+        let w = block_witness(850144);
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .allowed_merchants([MERCHANT, STRANGER])
+            .build()
+            .unwrap();
+        let tx = payment_tx(&in_state, &paid_state(&in_state, 850144));
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &w), Ok(()));
+
+        // A state whose merchant is not listed can never be paid
+        let in_state = MinimalSubscriptionState {
+            allowed_merchants: vec![STRANGER.to_string()],
+            ..active_state()
+        };
+        let tx = payment_tx(&in_state, &paid_state(&in_state, 850144));
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &w),
+            Err(ValidationError::MerchantNotAllowed)
+        );
+
+        // No list, no restriction
+        assert!(active_state().is_merchant_allowed(STRANGER));
+    }
+
+    #[test]
+    fn test_mint_rejects_merchant_not_allowed() {
+        assert_eq!(
+            test_builder().allowed_merchants([STRANGER]).build(),
+            Err(ValidationError::MerchantNotAllowed)
+        );
+
+        let state = MinimalSubscriptionState {
+            allowed_merchants: vec![STRANGER.to_string()],
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::MerchantNotAllowed)
+        );
+    }
+
+    #[test]
+    fn test_builder_rejects_amount_below_dust() {
+        assert_eq!(
+            test_builder().amount_sats(DUST_LIMIT_SATS - 1).build(),
+            Err(ValidationError::BelowDustLimit {
+                amount: 545,
+                dust_limit: 546
+            })
+        );
+
+        let state = MinimalSubscriptionState {
+            amount_sats: DUST_LIMIT_SATS - 1,
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::BelowDustLimit {
+                amount: 545,
+                dust_limit: 546
+            })
+        );
+    }
+
+    #[test]
+    fn test_builder_accepts_amount_at_dust() {
+        let state = test_builder().amount_sats(DUST_LIMIT_SATS).build().unwrap();
+        assert_eq!(state.amount_sats, 546);
+    }
+
+    #[test]
+    fn test_builder_custom_dust_limit() {
+        assert_eq!(
+            test_builder()
+                .amount_sats(1000)
+                .dust_limit_sats(2000)
+                .build(),
+            Err(ValidationError::BelowDustLimit {
+                amount: 1000,
+                dust_limit: 2000
+            })
+        );
+        assert!(test_builder()
+            .amount_sats(2000)
+            .dust_limit_sats(2000)
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_amount_within_cap() {
+        let state = test_builder().max_amount_cap(2000000).build().unwrap();
+        assert_eq!(state.validate_amount_cap(), Ok(()));
+    }
+
+    #[test]
+    fn test_amount_at_cap() {
+        let state = test_builder()
+            .amount_sats(1000000)
+            .remaining_balance(1000000)
+            .max_amount_cap(1000000)
+            .build()
+            .unwrap();
+        assert_eq!(state.max_amount_cap, Some(1000000));
+    }
+
+    #[test]
+    fn test_amount_over_cap() {
+        assert_eq!(
+            test_builder()
+                .amount_sats(1000001)
+                .remaining_balance(1000001)
+                .max_amount_cap(1000000)
+                .build(),
+            Err(ValidationError::AmountAboveCap {
+                amount: 1000001,
+                cap: 1000000
+            })
+        );
+        // The locked balance is capped as well
+        assert_eq!(
+            test_builder()
+                .remaining_balance(1000001)
+                .max_amount_cap(1000000)
+                .build(),
+            Err(ValidationError::AmountAboveCap {
+                amount: 1000001,
+                cap: 1000000
+            })
+        );
+
+        let state = MinimalSubscriptionState {
+            max_amount_cap: Some(500000),
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::AmountAboveCap {
+                amount: 1000000,
+                cap: 500000
+            })
+        );
+    }
+
+    #[test]
+    fn test_top_up_cannot_exceed_cap() {
+        let (_, token_app) = test_apps();
+        let in_state = MinimalSubscriptionState {
+            max_amount_cap: Some(1500000),
+            ..active_state()
+        };
+        let out_state = MinimalSubscriptionState {
+            remaining_balance: 2000000,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &out_state, 1000000, 2000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Err(ValidationError::AmountAboveCap {
+                amount: 2000000,
+                cap: 1500000
+            })
+        );
     }
 
     #[test]
-    fn test_payment_validation() {
+    fn test_no_amount_cap() {
+        let state = test_builder()
+            .amount_sats(u64::MAX / 2)
+            .remaining_balance(u64::MAX)
+            .build()
+            .unwrap();
+        assert_eq!(state.max_amount_cap, None);
+        assert_eq!(state.validate_amount_cap(), Ok(()));
+    }
+
+    #[test]
+    fn test_display_summary() {
+        let state = MinimalSubscriptionState {
+            remaining_balance: 900000,
+            ..active_state()
+        };
+        assert_eq!(
+            state.to_string(),
+            "sub payer=0211.. merchant=0322.. amount=100000 interval=144 bal=900000 active"
+        );
+
+        // Keys too short to truncate are shown whole, multi-byte characters are not split
+        let state = MinimalSubscriptionState {
+            payer_pubkey: "02".to_string(),
+            merchant_pubkey: "ünïcødé".to_string(),
+            paused: true,
+            ..state
+        };
+        assert_eq!(
+            state.to_string(),
+            "sub payer=02 merchant=ünïc.. amount=100000 interval=144 bal=900000 paused"
+        );
+        assert!(state
+            .cancel()
+            .unwrap()
+            .to_string()
+            .ends_with("bal=0 inactive"));
+    }
+
+    #[test]
+    fn test_legacy_states_are_comparable_and_hashable() {
+        let state = SubscriptionState {
+            subscription_id: "sub_001".to_string(),
+            recipient: MERCHANT.to_string(),
+            amount_per_cycle: 100000,
+            remaining_balance: 900000,
+            total_locked: 1000000,
+        };
+        assert_eq!(state, state.clone());
+        assert_ne!(
+            state,
+            SubscriptionState {
+                total_locked: 2000000,
+                ..state.clone()
+            }
+        );
+
+        let content = NftContent::from(state);
+        let contents = std::collections::HashSet::from([
+            content.clone(),
+            content.clone(),
+            NftContent {
+                remaining: 800000,
+                ..content.clone()
+            },
+        ]);
+        assert_eq!(contents.len(), 2);
+        assert!(contents.contains(&content));
+    }
+
+    #[test]
+    fn test_monthly_period_to_blocks() {
+        assert_eq!(BillingPeriod::Months(1).to_blocks(), 4320);
+        assert_eq!(BillingPeriod::Months(12).to_blocks(), 51840);
+        assert_eq!(BillingPeriod::Days(30).to_blocks(), 4320);
+        let state = test_builder()
+            .billing_period(BillingPeriod::Months(1))
+            .build()
+            .unwrap();
+        assert_eq!(state.billing_interval_blocks, 4320);
+    }
+
+    #[test]
+    fn test_weekly_period_to_blocks() {
+        assert_eq!(BillingPeriod::Weeks(1).to_blocks(), 1008);
+        assert_eq!(
+            BillingPeriod::Weeks(2).to_blocks_with(BlocksPerDay(288)),
+            4032
+        );
+        assert_eq!(BillingPeriod::Weeks(u32::MAX).to_blocks(), u32::MAX);
+    }
+
+    #[test]
+    fn test_period_under_different_blocks_per_day() {
+        let period = BillingPeriod::Days(7);
+        assert_eq!(period.to_blocks_with(BlocksPerDay::default()), 1008);
+        assert_eq!(period.to_blocks_with(BlocksPerDay(720)), 5040);
+
+        // The builder converts at its own rate, whatever order the setters are called in
+        let state = test_builder()
+            .billing_period(period)
+            .blocks_per_day(BlocksPerDay(720))
+            .build()
+            .unwrap();
+        assert_eq!(state.billing_interval_blocks, 5040);
+        let state = test_builder()
+            .blocks_per_day(BlocksPerDay(720))
+            .billing_period(period)
+            .billing_interval_blocks(144)
+            .build()
+            .unwrap();
+        assert_eq!(state.billing_interval_blocks, 144);
+    }
+
+    const FUNDER: &str = "025555555555555555555555555555555555555555555555555555555555555555";
+
+    // Mint a gift subscription funded by an input locked to `funding_script`
+    fn mint_gift(funding_script: Option<Vec<u8>>) -> Result<(), ValidationError> {
+        let state = MinimalSubscriptionState {
+            funder_pubkey: FUNDER.to_string(),
+            ..active_state()
+        };
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let mut tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        tx.coin_ins = funding_script.map(|dest| {
+            vec![NativeOutput {
+                amount: 1000000,
+                dest,
+            }]
+        });
+        can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string()))
+    }
+
+    #[test]
+    fn test_gift_mint_requires_funder_funds() {
+        assert_eq!(mint_gift(recipient_script(FUNDER)), Ok(()));
+        // The beneficiary's own coins do not fund someone else's gift
+        assert_eq!(
+            mint_gift(recipient_script(PAYER)),
+            Err(ValidationError::FundingNotFromFunder)
+        );
+        assert_eq!(mint_gift(None), Err(ValidationError::FundingNotFromFunder));
+    }
+
+    #[test]
+    fn test_gift_cancellation_refunds_payer_not_funder() {
+        let (_, token_app) = test_apps();
+        let in_state = test_builder()
+            .last_payment_block(850000)
+            .funder_pubkey(FUNDER)
+            .build()
+            .unwrap();
+        let cancel = |refund_to: &str| {
+            let mut tx = transition_tx(&in_state, &in_state.cancel().unwrap(), 1000000, 0);
+            add_payout(&mut tx, refund_to, 1000000);
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000))
+        };
+
+        assert_eq!(cancel(PAYER), Ok(()));
+        assert_eq!(
+            cancel(FUNDER),
+            Err(ValidationError::Underpaid {
+                recipient: "payer",
+                expected: 1000000,
+                actual: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_funder_is_immutable() {
+        let (_, token_app) = test_apps();
         let in_state = MinimalSubscriptionState {
-            payer_pubkey: "02abc...".to_string(),
-            merchant_pubkey: "03def...".to_string(),
+            funder_pubkey: FUNDER.to_string(),
+            ..active_state()
+        };
+        let block = in_state.next_due_block();
+        let out_state = MinimalSubscriptionState {
+            funder_pubkey: PAYER.to_string(),
+            ..in_state.apply_payment(block).unwrap()
+        };
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(block)
+            ),
+            Err(ValidationError::ImmutableFieldChanged("funder_pubkey"))
+        );
+    }
+
+    // Five seats at 20000 sats, last paid at block 850000
+    fn seat_state() -> MinimalSubscriptionState {
+        test_builder()
+            .last_payment_block(850000)
+            .per_seat(5, 20000)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_seat_increase() {
+        let (_, token_app) = test_apps();
+        let in_state = seat_state();
+        // Three more seats for the second half of the cycle cost 30000
+        let out_state = in_state.change_seats(8, 850072).unwrap();
+        assert_eq!(out_state.amount_sats, 160000);
+        assert_eq!(out_state.remaining_balance, 970000);
+        assert_eq!(out_state.total_paid_sats, 30000);
+
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(850072)
+            ),
+            Ok(())
+        );
+        let unpaid = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &unpaid, &block_witness(850072)),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 30000,
+                actual: 0
+            })
+        );
+
+        // The amount must follow the seat count
+        let underpriced = MinimalSubscriptionState {
             amount_sats: 100000,
-            billing_interval_blocks: 144,
-            last_payment_block: 850000,
-            is_active: true,
-            remaining_balance: 1000000,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &underpriced),
+                &block_witness(850072)
+            ),
+            Err(ValidationError::ImmutableFieldChanged("amount_sats"))
+        );
+    }
+
+    #[test]
+    fn test_seat_decrease() {
+        let (_, token_app) = test_apps();
+        let in_state = seat_state();
+        // Two fewer seats for the second half of the cycle credit 20000
+        let out_state = in_state.change_seats(3, 850072).unwrap();
+        assert_eq!(out_state.amount_sats, 60000);
+        assert_eq!(out_state.remaining_balance, 1000000);
+        assert_eq!(out_state.seat_credit_sats, 20000);
+        let tx = transition_tx(&in_state, &out_state, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850072)),
+            Ok(())
+        );
+
+        // The credit is deducted from the next payment
+        let paid = out_state.apply_payment(850144).unwrap();
+        assert_eq!(paid.remaining_balance, 960000);
+        assert_eq!(paid.seat_credit_sats, 0);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&out_state, &paid),
+                &block_witness(850144)
+            ),
+            Ok(())
+        );
+
+        // Seats cannot be dropped to zero, nor changed once the payment is due
+        assert_eq!(
+            in_state.change_seats(0, 850072),
+            Err(ValidationError::ZeroSeats)
+        );
+        assert_eq!(
+            in_state.change_seats(3, 850144),
+            Err(ValidationError::PaymentDue)
+        );
+    }
+
+    // Reprice a payer-authorized subscription last paid at block 850000, at block 850072
+    const PLAN_CHANGE_AUTH: &str = "plan-change-authorization";
+
+    // Change `plan_state` to `out_state`, revealing `plan_change_auth` and committing to a fresh
+    // authorization for the next change
+    fn plan_change(
+        out_state: &MinimalSubscriptionState,
+        plan_change_auth: Option<&str>,
+        merchant_payout: u64,
+    ) -> Result<(), ValidationError> {
+        let out_state = &MinimalSubscriptionState {
+            plan_change_auth_hash: Some(hash("next-plan-change-authorization")),
+            ..out_state.clone()
+        };
+        rotated_plan_change(out_state, plan_change_auth, merchant_payout)
+    }
+
+    fn rotated_plan_change(
+        out_state: &MinimalSubscriptionState,
+        plan_change_auth: Option<&str>,
+        merchant_payout: u64,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = plan_state();
+        let mut tx = transition_tx(
+            &in_state,
+            out_state,
+            in_state.remaining_balance,
+            out_state.remaining_balance,
+        );
+        add_payout(&mut tx, MERCHANT, merchant_payout);
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(850072),
+            plan_change_auth: plan_change_auth.map(str::to_string),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &tx, &witness)
+    }
+
+    fn plan_state() -> MinimalSubscriptionState {
+        test_builder()
+            .last_payment_block(850000)
+            .payer_auth(PAYER_AUTH)
+            .plan_change_auth(PLAN_CHANGE_AUTH)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_plan_upgrade() {
+        // Upgrading to 160000 for the second half of the cycle costs 30000
+        let out_state = plan_state().change_plan(160000, 850072).unwrap();
+        assert_eq!(out_state.remaining_balance, 970000);
+        assert_eq!(out_state.total_paid_sats, 30000);
+        assert_eq!(
+            plan_change(&out_state, Some(PLAN_CHANGE_AUTH), 30000),
+            Ok(())
+        );
+        assert_eq!(
+            plan_change(&out_state, Some(PLAN_CHANGE_AUTH), 10000),
+            Err(ValidationError::Underpaid {
+                recipient: "merchant",
+                expected: 30000,
+                actual: 10000
+            })
+        );
+    }
+
+    #[test]
+    fn test_plan_downgrade() {
+        // Downgrading to 60000 for the second half of the cycle credits 20000
+        let out_state = plan_state().change_plan(60000, 850072).unwrap();
+        assert_eq!(out_state.remaining_balance, 1000000);
+        assert_eq!(out_state.seat_credit_sats, 20000);
+        assert_eq!(plan_change(&out_state, Some(PLAN_CHANGE_AUTH), 0), Ok(()));
+
+        // The credit is deducted from the next payment at the new amount
+        let paid = out_state.apply_payment(850144).unwrap();
+        assert_eq!(paid.remaining_balance, 960000);
+    }
+
+    #[test]
+    fn test_plan_change_in_millisatoshis() {
+        let in_state = MinimalSubscriptionState {
+            amount_sats: 100000000,
+            currency_unit: CurrencyUnit::MilliSatoshi,
+            ..plan_state()
+        };
+        // The 60000 tokens more a cycle are prorated, not the difference in millisatoshis
+        let upgraded = in_state.change_plan(160000000, 850072).unwrap();
+        assert_eq!(upgraded.remaining_balance, 970000);
+        assert_eq!(upgraded.total_paid_sats, 30000);
+        let downgraded = in_state.change_plan(60000000, 850072).unwrap();
+        assert_eq!(downgraded.seat_credit_sats, 20000);
+    }
+
+    #[test]
+    fn test_plan_change_without_authorization() {
+        let out_state = plan_state().change_plan(160000, 850072).unwrap();
+        assert_eq!(
+            plan_change(&out_state, None, 30000),
+            Err(ValidationError::PlanChangeNotAuthorized)
+        );
+        assert_eq!(
+            plan_change(&out_state, Some(MERCHANT_AUTH), 30000),
+            Err(ValidationError::PlanChangeNotAuthorized)
+        );
+        // Push payments reveal the payer's payment authorization, so it cannot change the plan
+        assert_eq!(
+            plan_change(&out_state, Some(PAYER_AUTH), 30000),
+            Err(ValidationError::PlanChangeNotAuthorized)
+        );
+    }
+
+    #[test]
+    fn test_plan_change_rotates_authorization() {
+        let out_state = plan_state().change_plan(160000, 850072).unwrap();
+        assert_eq!(
+            rotated_plan_change(&out_state, Some(PLAN_CHANGE_AUTH), 30000),
+            Err(ValidationError::AuthorizationNotRotated("payer"))
+        );
+        let cleared = MinimalSubscriptionState {
+            plan_change_auth_hash: None,
+            ..out_state
+        };
+        assert_eq!(
+            rotated_plan_change(&cleared, Some(PLAN_CHANGE_AUTH), 30000),
+            Err(ValidationError::AuthorizationNotRotated("payer"))
+        );
+    }
+
+    #[test]
+    fn test_payment_at_current_seat_count() {
+        let (_, token_app) = test_apps();
+        let in_state = seat_state();
+        let out_state = in_state.apply_payment(850144).unwrap();
+        assert_eq!(out_state.remaining_balance, 900000);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &out_state),
+                &block_witness(850144)
+            ),
+            Ok(())
+        );
+
+        // A payment cannot change the seat count on the side
+        let more_seats = MinimalSubscriptionState {
+            seats: 6,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &more_seats),
+                &block_witness(850144)
+            ),
+            Err(ValidationError::PaymentDue)
+        );
+        assert_eq!(
+            test_builder().per_seat(5, 20000).amount_sats(90000).build(),
+            Err(ValidationError::SeatAmountMismatch {
+                expected: 100000,
+                actual: 90000
+            })
+        );
+    }
+
+    // Charge `units` halfway through a metered cycle in which `cycle_usage` units were charged
+    fn usage_charge(cycle_usage: u32, units: u32, block: u32) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let (mut in_state, _, _) = metered_states(0);
+        in_state.cycle_usage = cycle_usage;
+        let mut out_state = in_state.clone();
+        out_state.subscription.remaining_balance -= 250 * units as u64;
+        out_state.subscription.total_paid_sats += 250 * units as u64;
+        out_state.subscription.nonce += 1;
+        out_state.cycle_usage = cycle_usage + units;
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            units: Some(units),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &metered_tx(&in_state, &out_state), &witness)
+    }
+
+    #[test]
+    fn test_usage_within_quota() {
+        assert_eq!(usage_charge(600, 300, 850072), Ok(()));
+    }
+
+    #[test]
+    fn test_usage_hits_quota() {
+        assert_eq!(usage_charge(600, 400, 850072), Ok(()));
+    }
+
+    #[test]
+    fn test_usage_exceeds_quota() {
+        assert_eq!(
+            usage_charge(600, 401, 850072),
+            Err(ValidationError::UnitCapExceeded {
+                max: 1000,
+                actual: 1001
+            })
+        );
+        // Once the cycle is due, usage is charged with the cycle's payment instead
+        assert_eq!(
+            usage_charge(600, 100, 850144),
+            Err(ValidationError::PaymentDue)
+        );
+    }
+
+    #[test]
+    fn test_usage_resets_with_new_cycle() {
+        let (_, token_app) = test_apps();
+        let (mut in_state, out_state, witness) = metered_states(500);
+        in_state.cycle_usage = 900;
+        let tx = metered_tx(&in_state, &out_state);
+        assert_eq!(token_contract_satisfied(&token_app, &tx, &witness), Ok(()));
+
+        // The new cycle starts from the units of its first payment, not the previous total
+        let carried = MeteredSubscriptionState {
+            cycle_usage: 1400,
+            ..out_state
+        };
+        let tx = metered_tx(&in_state, &carried);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &witness),
+            Err(ValidationError::UnexpectedFieldChange("cycle_usage"))
+        );
+    }
+
+    // Charge `units` halfway through the cycle of a family plan shared by PAYER (quota 300) and
+    // FUNDER (quota 200), for `beneficiary`, in a tx spending a coin of `spender`
+    fn beneficiary_charge(
+        beneficiary: &str,
+        spender: &str,
+        prior_usage: [u32; 2],
+        units: u32,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let (mut in_state, _, _) = metered_states(0);
+        in_state.beneficiaries = vec![(PAYER.to_string(), 300), (FUNDER.to_string(), 200)];
+        in_state.beneficiary_usage = prior_usage.to_vec();
+        in_state.cycle_usage = prior_usage.iter().sum();
+        let mut out_state = in_state.clone();
+        out_state.subscription.remaining_balance -= 250 * units as u64;
+        out_state.subscription.total_paid_sats += 250 * units as u64;
+        out_state.subscription.nonce += 1;
+        out_state.cycle_usage += units;
+        if let Some(index) = [PAYER, FUNDER].iter().position(|key| *key == beneficiary) {
+            out_state.beneficiary_usage[index] += units;
+        }
+        let mut tx = metered_tx(&in_state, &out_state);
+        tx.coin_ins = Some(vec![NativeOutput {
+            amount: 546,
+            dest: recipient_script(spender).unwrap(),
+        }]);
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(850072),
+            units: Some(units),
+            beneficiary: Some(beneficiary.to_string()),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &tx, &witness)
+    }
+
+    #[test]
+    fn test_beneficiary_within_quota() {
+        assert_eq!(beneficiary_charge(FUNDER, FUNDER, [0, 0], 200), Ok(()));
+        // Each quota is separate: the other beneficiary's usage does not count against it
+        assert_eq!(beneficiary_charge(PAYER, PAYER, [100, 200], 200), Ok(()));
+    }
+
+    #[test]
+    fn test_beneficiary_over_quota() {
+        assert_eq!(
+            beneficiary_charge(FUNDER, FUNDER, [0, 150], 60),
+            Err(ValidationError::BeneficiaryQuotaExceeded {
+                quota: 200,
+                actual: 210
+            })
+        );
+    }
+
+    #[test]
+    fn test_unlisted_beneficiary() {
+        assert_eq!(
+            beneficiary_charge(STRANGER, STRANGER, [0, 0], 10),
+            Err(ValidationError::BeneficiaryNotListed)
+        );
+        // A listed beneficiary must be the one authorizing the charge
+        assert_eq!(
+            beneficiary_charge(PAYER, STRANGER, [0, 0], 10),
+            Err(ValidationError::BeneficiaryNotAuthorized)
+        );
+    }
+
+    // Links of the oracle's hash chain, each hashing to the next; the subscription commits to
+    // the hash of the last, so the links are revealed from the last down
+    fn oracle_chain() -> [B32; 3] {
+        let first = B32([7; 32]);
+        let second = hash_bytes(&first.0);
+        let third = hash_bytes(&second.0);
+        [first, second, third]
+    }
+
+    // $50 a cycle within 1% of the oracle's rate, last paid at block 850000
+    fn usd_state() -> MinimalSubscriptionState {
+        test_builder()
+            .last_payment_block(850000)
+            .usd_amount(5000, 100, hash_bytes(&oracle_chain()[2].0))
+            .build()
+            .unwrap()
+    }
+
+    // `in_state` after paying `payment` sats at `block`, committing to the revealed link
+    fn usd_paid_state(
+        in_state: &MinimalSubscriptionState,
+        block: u32,
+        payment: u64,
+        price: &OraclePrice,
+    ) -> MinimalSubscriptionState {
+        MinimalSubscriptionState {
+            remaining_balance: in_state.remaining_balance - payment,
+            total_paid_sats: in_state.total_paid_sats + payment,
+            last_charge_sats: payment,
+            oracle_auth_hash: Some(price.oracle_auth.clone()),
+            ..in_state.apply_payment(block).unwrap()
+        }
+    }
+
+    // Pay the cycle of `in_state` due at `block`, `payment` sats at the given oracle rate
+    fn usd_payment_at(
+        in_state: &MinimalSubscriptionState,
+        block: u32,
+        payment: u64,
+        price: OraclePrice,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let out_state = usd_paid_state(in_state, block, payment, &price);
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            oracle_price: Some(price),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &payment_tx(in_state, &out_state), &witness)
+    }
+
+    // Pay the cycle due at block 850144, `payment` sats at the given oracle rate
+    fn usd_payment(payment: u64, price: OraclePrice) -> Result<(), ValidationError> {
+        usd_payment_at(&usd_state(), 850144, payment, price)
+    }
+
+    // Rate attested with the last link of the oracle's chain
+    fn oracle_price(sats_per_usd: u64, block: u32) -> OraclePrice {
+        OraclePrice {
+            sats_per_usd,
+            block,
+            oracle_auth: oracle_chain()[2].clone(),
+        }
+    }
+
+    #[test]
+    fn test_usd_payment_at_oracle_rate() {
+        // $50 at 2000 sats per dollar
+        assert_eq!(usd_payment(100000, oracle_price(2000, 850144)), Ok(()));
+        // Within the slippage tolerance, and with a rate a few blocks old
+        assert_eq!(usd_payment(99000, oracle_price(2000, 850140)), Ok(()));
+    }
+
+    #[test]
+    fn test_usd_payment_outside_slippage() {
+        assert_eq!(
+            usd_payment(102000, oracle_price(2000, 850144)),
+            Err(ValidationError::AmountOutOfRange {
+                min: 99000,
+                max: 101000,
+                actual: 102000
+            })
+        );
+    }
+
+    #[test]
+    fn test_usd_payment_with_invalid_oracle_price() {
+        let forged = OraclePrice {
+            oracle_auth: B32([8; 32]),
+            ..oracle_price(2000, 850144)
+        };
+        assert_eq!(
+            usd_payment(100000, forged),
+            Err(ValidationError::OraclePriceNotAuthorized)
+        );
+        assert_eq!(
+            usd_payment(100000, oracle_price(2000, 850137)),
+            Err(ValidationError::StaleOraclePrice)
+        );
+        assert_eq!(
+            usd_payment(100000, oracle_price(2000, 850145)),
+            Err(ValidationError::StaleOraclePrice)
+        );
+    }
+
+    #[test]
+    fn test_usd_payment_rotates_oracle_link() {
+        let (_, token_app) = test_apps();
+        let in_state = usd_state();
+        let price = oracle_price(2000, 850144);
+
+        // The payment must commit to the link it revealed
+        let unrotated = MinimalSubscriptionState {
+            oracle_auth_hash: in_state.oracle_auth_hash.clone(),
+            ..usd_paid_state(&in_state, 850144, 100000, &price)
+        };
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(850144),
+            oracle_price: Some(price.clone()),
+            ..Default::default()
+        });
+        assert_eq!(
+            token_contract_satisfied(&token_app, &payment_tx(&in_state, &unrotated), &witness),
+            Err(ValidationError::AuthorizationNotRotated("oracle"))
+        );
+
+        // so a revealed link is not accepted again, and the next cycle needs the one before it
+        let paid = usd_paid_state(&in_state, 850144, 100000, &price);
+        assert_eq!(
+            usd_payment_at(&paid, 850288, 50000, oracle_price(1000, 850288)),
+            Err(ValidationError::OraclePriceNotAuthorized)
+        );
+        let next = OraclePrice {
+            oracle_auth: oracle_chain()[1].clone(),
+            ..oracle_price(1000, 850288)
+        };
+        assert_eq!(usd_payment_at(&paid, 850288, 50000, next), Ok(()));
+    }
+
+    #[test]
+    fn test_mint_rejects_slippage_above_price() {
+        let state = MinimalSubscriptionState {
+            slippage_bps: BASIS_POINTS as u16 + 1,
+            ..usd_state()
+        };
+        assert_eq!(state.validate(), Err(ValidationError::InvalidSlippage));
+
+        let (nft_app, _) = test_apps();
+        let nft_app = App {
+            identity: hash(FUNDING_UTXO),
+            ..nft_app
+        };
+        let tx = test_tx(
+            vec![Charms::new()],
+            vec![charms(&[(&nft_app, Data::from(&state))])],
+        );
+        assert_eq!(
+            can_mint_nft(&nft_app, &tx, &Data::from(&FUNDING_UTXO.to_string())),
+            Err(ValidationError::InvalidSlippage)
+        );
+    }
+
+    const FULFILLMENT: &str = "delivery-receipt-0001";
+
+    // Pay the first due cycle of an escrowed subscription, revealing `fulfillment`
+    fn escrowed_payment(
+        in_state: MinimalSubscriptionState,
+        fulfillment: Option<&str>,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let block = in_state.next_due_block();
+        let out_state = in_state.apply_payment(block).unwrap();
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            fulfillment: fulfillment.map(str::to_string),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &payment_tx(&in_state, &out_state), &witness)
+    }
+
+    fn escrow_state() -> MinimalSubscriptionState {
+        test_builder()
+            .last_payment_block(850000)
+            .escrow_release(FULFILLMENT)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_escrow_released_by_fulfillment() {
+        assert_eq!(escrowed_payment(escrow_state(), Some(FULFILLMENT)), Ok(()));
+    }
+
+    #[test]
+    fn test_escrow_rejects_wrong_fulfillment() {
+        assert_eq!(
+            escrowed_payment(escrow_state(), Some("delivery-receipt-0002")),
+            Err(ValidationError::FulfillmentNotProven)
+        );
+        assert_eq!(
+            escrowed_payment(escrow_state(), None),
+            Err(ValidationError::FulfillmentNotProven)
+        );
+    }
+
+    #[test]
+    fn test_no_escrow_passthrough() {
+        assert_eq!(escrowed_payment(active_state(), None), Ok(()));
+        assert_eq!(escrowed_payment(active_state(), Some(FULFILLMENT)), Ok(()));
+    }
+
+    // A subscription with a 20-block dispute window, paid at block 850144
+    fn disputed_payment() -> (MinimalSubscriptionState, MinimalSubscriptionState) {
+        let unpaid = MinimalSubscriptionState {
+            dispute_window_blocks: 20,
+            ..active_state()
+        };
+        let paid = unpaid.apply_payment(850144).unwrap();
+        (unpaid, paid)
+    }
+
+    fn reversal_witness(current_block: u32, prior_payment_block: u32) -> Data {
+        Data::from(&SubscriptionWitness {
+            current_block: Some(current_block),
+            prior_payment_block: Some(prior_payment_block),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn test_payment_reversal_in_window() {
+        let (_, token_app) = test_apps();
+        let (unpaid, paid) = disputed_payment();
+        // The merchant's 100000 tokens are spent back into the subscription
+        let reversed = MinimalSubscriptionState {
+            last_state_change_block: 850164,
+            nonce: paid.nonce + 1,
+            ..unpaid
         };
+        let tx = transition_tx(&paid, &reversed, 1000000, 1000000);
 
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850164, 850000)),
+            Ok(())
+        );
+        // The prior block must be one the reversed payment was due from
+        let early = MinimalSubscriptionState {
+            last_payment_block: 850100,
+            ..reversed
+        };
+        let tx = transition_tx(&paid, &early, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850164, 850100)),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+    }
+
+    #[test]
+    fn test_payment_reversal_outside_window() {
+        let (_, token_app) = test_apps();
+        let (unpaid, paid) = disputed_payment();
+        let tx = transition_tx(&paid, &unpaid, 1000000, 1000000);
+
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850165, 850000)),
+            Err(ValidationError::OutsideDisputeWindow)
+        );
+        // Without a window, payments are final
+        let final_paid = active_state().apply_payment(850144).unwrap();
+        let tx = transition_tx(&final_paid, &active_state(), 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850144, 850000)),
+            Err(ValidationError::OutsideDisputeWindow)
+        );
+    }
+
+    #[test]
+    fn test_payment_reversal_of_more_than_one_cycle() {
+        let (_, token_app) = test_apps();
+        let (unpaid, paid) = disputed_payment();
+        let twice = paid.apply_payment(850288).unwrap();
+        let overdrawn = MinimalSubscriptionState {
+            remaining_balance: 1100000,
+            ..unpaid.clone()
+        };
+        let tx = transition_tx(&paid, &overdrawn, 1100000, 1100000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850150, 850000)),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000000,
+                actual: 1100000
+            })
+        );
+
+        // Rolling back two payments at once is rejected as well
+        let tx = transition_tx(&twice, &unpaid, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850290, 850000)),
+            Err(ValidationError::AmountMismatch {
+                expected: 900000,
+                actual: 1000000
+            })
+        );
+    }
+
+    #[test]
+    fn test_payment_reversal_in_millisatoshis() {
+        let (_, token_app) = test_apps();
+        let unpaid = MinimalSubscriptionState {
+            amount_sats: 100000000,
+            currency_unit: CurrencyUnit::MilliSatoshi,
+            dispute_window_blocks: 20,
+            ..active_state()
+        };
+        let paid = unpaid.apply_payment(850144).unwrap();
+        assert_eq!(paid.last_charge_sats, 100000);
+        // The 100000 tokens charged are refunded, not the amount in millisatoshis
+        let reversed = MinimalSubscriptionState {
+            last_state_change_block: 850164,
+            nonce: paid.nonce + 1,
+            ..unpaid
+        };
+        assert_eq!(reversed.remaining_balance, 1000000);
+        let tx = transition_tx(&paid, &reversed, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850164, 850000)),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_payment_reversal_refunds_last_charge() {
+        let (_, token_app) = test_apps();
+        let unpaid = MinimalSubscriptionState {
+            dispute_window_blocks: 20,
+            grace_period_blocks: 12,
+            late_fee_sats: 5000,
+            ..active_state()
+        };
+        // A late payment charges the late fee on top of the cycle's amount
+        let paid = unpaid.apply_payment(850150).unwrap();
+        assert_eq!(paid.last_charge_sats, 105000);
+        let reversed = MinimalSubscriptionState {
+            last_state_change_block: 850160,
+            nonce: paid.nonce + 1,
+            ..unpaid.clone()
+        };
+        let tx = transition_tx(&paid, &reversed, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850160, 850000)),
+            Ok(())
+        );
+
+        // Refunding only the cycle's amount leaves the late fee with the merchant
+        let short = MinimalSubscriptionState {
+            remaining_balance: 995000,
+            total_paid_sats: 5000,
+            ..reversed.clone()
+        };
+        let tx = transition_tx(&paid, &short, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850160, 850000)),
+            Err(ValidationError::AmountMismatch {
+                expected: 1000000,
+                actual: 995000
+            })
+        );
+
+        // A payment made before charges were recorded cannot be reversed
+        let unrecorded = MinimalSubscriptionState {
+            last_charge_sats: 0,
+            ..paid
+        };
+        let tx = transition_tx(&unrecorded, &reversed, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &reversal_witness(850160, 850000)),
+            Err(ValidationError::NoChargeToReverse)
+        );
+    }
+
+    #[test]
+    fn test_transition_must_bump_nonce() {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let replayed = MinimalSubscriptionState {
+            nonce: in_state.nonce,
+            ..paid_state(&in_state, 850144)
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &replayed),
+                &block_witness(850144)
+            ),
+            Err(ValidationError::NonceNotIncremented {
+                expected: 1,
+                actual: 0
+            })
+        );
+
+        // Skipping ahead is rejected just like standing still
+        let paused = MinimalSubscriptionState {
+            paused: true,
+            last_state_change_block: 850000,
+            nonce: 2,
+            ..in_state.clone()
+        };
+        let tx = transition_tx(&in_state, &paused, 1000000, 1000000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850000)),
+            Err(ValidationError::NonceNotIncremented {
+                expected: 1,
+                actual: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_consecutive_transitions_increment_nonce() {
+        let (_, token_app) = test_apps();
+        let unpaid = active_state();
+        let paid = unpaid.apply_payment(850144).unwrap();
+        assert_eq!(paid.nonce, 1);
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&unpaid, &paid),
+                &block_witness(850144)
+            ),
+            Ok(())
+        );
+
+        let paused = MinimalSubscriptionState {
+            paused: true,
+            last_state_change_block: 850144,
+            nonce: 2,
+            ..paid.clone()
+        };
+        let tx = transition_tx(&paid, &paused, 900000, 900000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &block_witness(850144)),
+            Ok(())
+        );
+
+        let topped_up = MinimalSubscriptionState {
+            remaining_balance: 1400000,
+            nonce: 3,
+            ..paused.clone()
+        };
+        let mut tx = transition_tx(&paused, &topped_up, 900000, 1400000);
+        fund(&mut tx, 500000);
+        assert_eq!(
+            token_contract_satisfied(&token_app, &tx, &Data::empty()),
+            Ok(())
+        );
+
+        let cancelled = topped_up.cancel().unwrap();
+        assert_eq!(cancelled.nonce, 4);
+    }
+
+    // `<blocks> OP_CHECKSEQUENCEVERIFY OP_DROP <payer> OP_CHECKSIG`
+    fn timelock_script(blocks: u32) -> Vec<u8> {
+        let mut script = match blocks {
+            1..=16 => vec![0x50 + blocks as u8],
+            _ => {
+                let mut bytes = blocks.to_le_bytes().to_vec();
+                while bytes.len() > 1 && bytes[bytes.len() - 1] == 0 {
+                    bytes.pop();
+                }
+                if bytes[bytes.len() - 1] & 0x80 != 0 {
+                    bytes.push(0);
+                }
+                let mut push = vec![bytes.len() as u8];
+                push.extend(bytes);
+                push
+            }
+        };
+        script.extend([OP_CHECKSEQUENCEVERIFY, 0x75, 0x21]);
+        script.extend(hex::decode(PAYER).unwrap());
+        script.push(0xac);
+        script
+    }
+
+    // Pay a cycle at `block`, spending the NFT from a P2WSH output locked by `script` and
+    // revealing `revealed` as its witness script
+    fn timelocked_payment(
+        script: &[u8],
+        revealed: &[u8],
+        block: u32,
+    ) -> Result<(), ValidationError> {
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
         let out_state = MinimalSubscriptionState {
-            payer_pubkey: "02abc...".to_string(),
-            merchant_pubkey: "03def...".to_string(),
-            amount_sats: 100000,
-            billing_interval_blocks: 144,
-            last_payment_block: 850100, // Updated
-            is_active: true,
-            remaining_balance: 900000, // Decreased by amount_sats
+            last_payment_block: block,
+            remaining_balance: 900000,
+            cycles_paid: 1,
+            total_paid_sats: 100000,
+            last_charge_sats: 100000,
+            nonce: 1,
+            ..in_state.clone()
         };
+        let mut tx = payment_tx(&in_state, &out_state);
+        let mut p2wsh = vec![0x00, 0x20];
+        p2wsh.extend_from_slice(&Sha256::digest(script));
+        tx.coin_ins = Some(vec![NativeOutput {
+            amount: 546,
+            dest: p2wsh,
+        }]);
+        let witness = Data::from(&SubscriptionWitness {
+            current_block: Some(block),
+            timelock_script: Some(hex::encode(revealed)),
+            ..Default::default()
+        });
+        token_contract_satisfied(&token_app, &tx, &witness)
+    }
 
-        // Payment amount should match
-        assert_eq!(in_state.remaining_balance - out_state.remaining_balance, in_state.amount_sats);
-        
-        // Immutable fields should match
-        assert_eq!(in_state.payer_pubkey, out_state.payer_pubkey);
-        assert_eq!(in_state.merchant_pubkey, out_state.merchant_pubkey);
-        assert_eq!(in_state.amount_sats, out_state.amount_sats);
+    #[test]
+    fn test_relative_timelock_satisfies_interval() {
+        let script = timelock_script(144);
+        assert_eq!(csv_sequence(&script), Some(144));
+        assert_eq!(timelocked_payment(&script, &script, 850144), Ok(()));
+        // The timelock does not replace the schedule: the payment cannot be backdated
+        assert_eq!(
+            timelocked_payment(&script, &script, 850100),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+    }
+
+    #[test]
+    fn test_relative_timelock_too_short() {
+        let script = timelock_script(100);
+        assert_eq!(
+            timelocked_payment(&script, &script, 850144),
+            Err(ValidationError::TimelockTooShort {
+                required: 144,
+                actual: 100
+            })
+        );
+        // The revealed script must be the one the spent output committed to
+        assert_eq!(
+            timelocked_payment(&script, &timelock_script(144), 850144),
+            Err(ValidationError::InvalidTimelockScript)
+        );
+        // Time-based relative timelocks do not count blocks
+        let time_based = timelock_script(SEQUENCE_TYPE_FLAG | 144);
+        assert_eq!(
+            timelocked_payment(&time_based, &time_based, 850144),
+            Err(ValidationError::InvalidTimelockScript)
+        );
+        // Without a timelock script, the witnessed block is checked as before
+        let (_, token_app) = test_apps();
+        let in_state = active_state();
+        let out_state = in_state.apply_payment(850144).unwrap();
+        let early = MinimalSubscriptionState {
+            last_payment_block: 850100,
+            ..out_state
+        };
+        assert_eq!(
+            token_contract_satisfied(
+                &token_app,
+                &payment_tx(&in_state, &early),
+                &block_witness(850100)
+            ),
+            Err(ValidationError::IntervalNotElapsed)
+        );
+    }
+
+    #[test]
+    fn test_csv_sequence_requires_minimal_push() {
+        assert_eq!(csv_sequence(&[0x52, OP_CHECKSEQUENCEVERIFY]), Some(2));
+        assert_eq!(
+            csv_sequence(&[0x02, 0x90, 0x00, OP_CHECKSEQUENCEVERIFY]),
+            Some(144)
+        );
+        assert_eq!(
+            csv_sequence(&[0x02, 0x10, 0x00, OP_CHECKSEQUENCEVERIFY]),
+            None
+        );
+        assert_eq!(csv_sequence(&[0x01, 0x90, OP_CHECKSEQUENCEVERIFY]), None);
+        assert_eq!(csv_sequence(&[0x02, 0x90, 0x00, 0x75]), None);
+        assert_eq!(csv_sequence(&[]), None);
     }
 }
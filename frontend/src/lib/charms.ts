@@ -447,7 +447,7 @@ export async function extractCharms(
  * Calculate app ID from initial UTXO
  */
 export function calculateAppId(_utxo: string): string {
-  // App ID is sha256 of the initial UTXO
+  // App ID is sha256("charmpay-v1:" + initial UTXO), see DOMAIN_TAG in the contract
   // This should be done server-side or with a crypto library
   // For now, return placeholder
   return 'app_id_placeholder';